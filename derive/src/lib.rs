@@ -11,28 +11,152 @@ use quote::ToTokens;
 use syn::{Lit, Meta, MetaNameValue, NestedMeta};
 
 decl_derive!([ProcessAnnotatedValue, attributes(process_annotated_value)] => process_item_derive);
+decl_derive!([VisitAnnotatedValue, attributes(process_annotated_value)] => visit_item_derive);
+decl_derive!([SelectAnnotatedValue] => select_item_derive);
 
-fn process_wrapper_struct_derive(
-    s: synstructure::Structure,
-) -> Result<TokenStream, synstructure::Structure> {
-    // The next few blocks are for finding out whether the given type is of the form:
-    // struct Foo(Bar)  (tuple struct with a single field)
-
+/// Returns whether the given type is of the form `struct Foo(Bar)`.
+fn is_wrapper_struct(s: &synstructure::Structure) -> bool {
     if s.variants().len() != 1 {
         // We have more than one variant (e.g. `enum Foo { A, B }`)
-        return Err(s);
+        return false;
     }
 
     if s.variants()[0].bindings().len() != 1 {
         // The single variant has multiple fields
         // e.g. `struct Foo(Bar, Baz)`
         //      `enum Foo { A(X, Y) }`
-        return Err(s);
+        return false;
     }
 
     if let Some(_) = s.variants()[0].bindings()[0].ast().ident {
         // The variant has a name
         // e.g. `struct Foo { bar: Bar }` instead of `struct Foo(Bar)`
+        return false;
+    }
+
+    true
+}
+
+/// Returns whether the given type is of the form `Annotated<T>`.
+fn is_annotated(ty: &syn::Type) -> bool {
+    match *ty {
+        syn::Type::Path(syn::TypePath { ref path, .. }) => path
+            .segments
+            .iter()
+            .last()
+            .map_or(false, |segment| segment.ident == "Annotated"),
+        _ => false,
+    }
+}
+
+/// Attributes of a field that are relevant for processing and visiting.
+#[derive(Default)]
+struct FieldAttrs {
+    process_annotated_value: bool,
+    pii_kind: Option<TokenStream>,
+    cap: Option<TokenStream>,
+    rename: Option<String>,
+    flatten: bool,
+}
+
+impl FieldAttrs {
+    fn value_info(&self) -> TokenStream {
+        let pii_kind = self
+            .pii_kind
+            .as_ref()
+            .map(|x| quote!(Some(__processor::#x)))
+            .unwrap_or_else(|| quote!(None));
+        let cap = self
+            .cap
+            .as_ref()
+            .map(|x| quote!(Some(__processor::#x)))
+            .unwrap_or_else(|| quote!(None));
+        quote! {
+            __processor::ValueInfo {
+                pii_kind: #pii_kind,
+                cap: #cap,
+            }
+        }
+    }
+}
+
+fn parse_field_attrs(field: &syn::Field) -> FieldAttrs {
+    let mut rv = FieldAttrs::default();
+    for attr in &field.attrs {
+        let meta = match attr.interpret_meta() {
+            Some(meta) => meta,
+            None => continue,
+        };
+
+        if meta.name() == "serde" {
+            if let Meta::List(metalist) = meta {
+                for nested_meta in metalist.nested {
+                    match nested_meta {
+                        NestedMeta::Meta(Meta::Word(ident)) => {
+                            if ident == "flatten" {
+                                rv.flatten = true;
+                            }
+                        }
+                        NestedMeta::Meta(Meta::NameValue(MetaNameValue { ident, lit, .. })) => {
+                            if ident == "rename" {
+                                if let Lit::Str(litstr) = lit {
+                                    rv.rename = Some(litstr.value());
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            continue;
+        } else if meta.name() == "process_annotated_value" {
+            rv.process_annotated_value = true;
+        } else {
+            continue;
+        }
+
+        if let Meta::List(metalist) = meta {
+            for nested_meta in metalist.nested {
+                match nested_meta {
+                    NestedMeta::Literal(..) => panic!("unexpected literal attribute"),
+                    NestedMeta::Meta(meta) => match meta {
+                        Meta::NameValue(MetaNameValue { ident, lit, .. }) => {
+                            if ident == "pii_kind" {
+                                match lit {
+                                    Lit::Str(litstr) => {
+                                        rv.pii_kind =
+                                            Some(pii_kind_to_enum_variant(&litstr.value()));
+                                    }
+                                    _ => {
+                                        panic!("Got non string literal for pii_kind");
+                                    }
+                                }
+                            } else if ident == "cap" {
+                                match lit {
+                                    Lit::Str(litstr) => {
+                                        rv.cap = Some(cap_to_enum_variant(&litstr.value()));
+                                    }
+                                    _ => {
+                                        panic!("Got non string literal for cap");
+                                    }
+                                }
+                            }
+                        }
+                        other => {
+                            panic!("Unexpected or bad attribute {}", other.name());
+                        }
+                    },
+                }
+            }
+        }
+    }
+    rv
+}
+
+fn process_wrapper_struct_derive(
+    s: synstructure::Structure,
+) -> Result<TokenStream, synstructure::Structure> {
+    if !is_wrapper_struct(&s) {
         return Err(s);
     }
 
@@ -94,70 +218,20 @@ fn process_item_derive(s: synstructure::Structure) -> TokenStream {
         }
         let mut variant_body = TokenStream::new();
         for bi in variant.bindings() {
-            let mut pii_kind = None;
-            let mut cap = None;
-            let mut process_annotated_value = false;
-            for attr in &bi.ast().attrs {
-                let meta = match attr.interpret_meta() {
-                    Some(meta) => meta,
-                    None => continue,
-                };
-                if meta.name() == "process_annotated_value" {
-                    process_annotated_value = true;
-                } else {
-                    continue;
-                }
-
-                if let Meta::List(metalist) = meta {
-                    process_annotated_value = true;
-                    for nested_meta in metalist.nested {
-                        match nested_meta {
-                            NestedMeta::Literal(..) => panic!("unexpected literal attribute"),
-                            NestedMeta::Meta(meta) => match meta {
-                                Meta::NameValue(MetaNameValue { ident, lit, .. }) => {
-                                    if ident == "pii_kind" {
-                                        match lit {
-                                            Lit::Str(litstr) => {
-                                                pii_kind =
-                                                    Some(pii_kind_to_enum_variant(&litstr.value()));
-                                            }
-                                            _ => {
-                                                panic!("Got non string literal for pii_kind");
-                                            }
-                                        }
-                                    } else if ident == "cap" {
-                                        match lit {
-                                            Lit::Str(litstr) => {
-                                                cap = Some(cap_to_enum_variant(&litstr.value()));
-                                            }
-                                            _ => {
-                                                panic!("Got non string literal for cap");
-                                            }
-                                        }
-                                    }
-                                }
-                                other => {
-                                    panic!("Unexpected or bad attribute {}", other.name());
-                                }
-                            },
-                        }
-                    }
-                }
-            }
-
-            if process_annotated_value {
-                let pii_kind = pii_kind
-                    .map(|x| quote!(Some(__processor::#x)))
-                    .unwrap_or_else(|| quote!(None));
-                let cap = cap
-                    .map(|x| quote!(Some(__processor::#x)))
-                    .unwrap_or_else(|| quote!(None));
+            let attrs = parse_field_attrs(bi.ast());
+            if attrs.process_annotated_value {
+                let info = attrs.value_info();
                 (quote! {
                     #bi = __processor::ProcessAnnotatedValue::process_annotated_value(
-                        #bi, __processor, &__processor::ValueInfo
-                    {
-                        pii_kind: #pii_kind,
-                        cap: #cap,
+                        #bi, __processor, &#info);
+                }).to_tokens(&mut variant_body);
+            } else if is_annotated(&bi.ast().ty) {
+                // Fields that are not processed may still retain an invalid original value in
+                // their meta data, which is processed like a databag.
+                (quote! {
+                    #bi = __processor::process_invalid_value(#bi, __processor, &__processor::ValueInfo {
+                        pii_kind: Some(__processor::PiiKind::Databag),
+                        cap: None,
                     });
                 }).to_tokens(&mut variant_body);
             } else {
@@ -180,7 +254,9 @@ fn process_item_derive(s: synstructure::Structure) -> TokenStream {
                 #variant_body
                 __protocol::Annotated(Some(#assemble_pat), __protocol)
             }
-            __annotated @ __protocol::Annotated(..) => __annotated
+            __annotated @ __protocol::Annotated(..) => {
+                __processor::process_invalid_value(__annotated, __processor, __info)
+            }
         }).to_tokens(&mut body);
     }
 
@@ -202,6 +278,366 @@ fn process_item_derive(s: synstructure::Structure) -> TokenStream {
     })
 }
 
+fn visit_wrapper_struct_derive(
+    s: synstructure::Structure,
+) -> Result<TokenStream, synstructure::Structure> {
+    if !is_wrapper_struct(&s) {
+        return Err(s);
+    }
+
+    // Like `ProcessAnnotatedValue`, single-field tuple structs are visited like a type alias.
+    let name = &s.ast().ident;
+
+    Ok(s.gen_impl(quote! {
+        use processor as __processor;
+        use protocol as __protocol;
+
+        gen impl __processor::VisitAnnotatedValue for @Self {
+            fn visit_annotated_value(
+                __value: Option<&Self>,
+                __meta: &__protocol::Meta,
+                __visitor: &mut __processor::Visitor,
+                __state: &__processor::VisitState
+            ) {
+                __processor::VisitAnnotatedValue::visit_annotated_value(
+                    __value.map(|x| &x.0),
+                    __meta,
+                    __visitor,
+                    __state
+                )
+            }
+
+            fn visit_annotated_value_mut(
+                __annotated: &mut __protocol::Annotated<Self>,
+                __visitor: &mut __processor::VisitorMut,
+                __state: &__processor::VisitState
+            ) {
+                let mut __inner = ::std::mem::replace(
+                    __annotated,
+                    __protocol::Annotated::empty()
+                ).map(|x| x.0);
+                __processor::VisitAnnotatedValue::visit_annotated_value_mut(
+                    &mut __inner,
+                    __visitor,
+                    __state
+                );
+                *__annotated = __inner.map(#name);
+            }
+        }
+    }))
+}
+
+fn visit_item_derive(s: synstructure::Structure) -> TokenStream {
+    let mut s = match visit_wrapper_struct_derive(s) {
+        Ok(stream) => return stream,
+        Err(s) => s,
+    };
+
+    // All annotated fields are visited, not just those marked with `#[process_annotated_value]`.
+    // Unmarked fields are visited without value info.
+    s.filter(|bi| is_annotated(&bi.ast().ty));
+
+    // Flattened fields and fields of tuple variants do not add to the path. All other fields are
+    // entered with their serialized name.
+    let enter = |bi: &synstructure::BindingInfo| {
+        let attrs = parse_field_attrs(bi.ast());
+        let info = attrs.value_info();
+        let key = attrs
+            .rename
+            .clone()
+            .or_else(|| bi.ast().ident.as_ref().map(|ident| ident.to_string()));
+        match key {
+            Some(ref key) if !attrs.flatten => quote!(__state.enter_key(#key, #info)),
+            _ => quote!(__state.enter_nothing(#info)),
+        }
+    };
+
+    let visit_body = s.each(|bi| {
+        let state = enter(bi);
+        quote! {
+            __processor::VisitAnnotatedValue::visit_annotated_value(
+                #bi.value(),
+                #bi.meta(),
+                __visitor,
+                &#state
+            );
+        }
+    });
+
+    s.bind_with(|_| synstructure::BindStyle::RefMut);
+
+    let visit_mut_body = s.each(|bi| {
+        let state = enter(bi);
+        quote! {
+            __processor::VisitAnnotatedValue::visit_annotated_value_mut(
+                #bi,
+                __visitor,
+                &#state
+            );
+        }
+    });
+
+    s.gen_impl(quote! {
+        use processor as __processor;
+        use protocol as __protocol;
+
+        gen impl __processor::VisitAnnotatedValue for @Self {
+            fn visit_children(
+                &self,
+                __visitor: &mut __processor::Visitor,
+                __state: &__processor::VisitState
+            ) {
+                match *self {
+                    #visit_body
+                }
+            }
+
+            fn visit_children_mut(
+                &mut self,
+                __visitor: &mut __processor::VisitorMut,
+                __state: &__processor::VisitState
+            ) {
+                match *self {
+                    #visit_mut_body
+                }
+            }
+        }
+    })
+}
+
+fn select_wrapper_struct_derive(
+    s: synstructure::Structure,
+) -> Result<TokenStream, synstructure::Structure> {
+    if !is_wrapper_struct(&s) {
+        return Err(s);
+    }
+
+    // Single-field tuple structs are selected like a type alias.
+    let name = &s.ast().ident;
+
+    Ok(s.gen_impl(quote! {
+        use processor as __processor;
+
+        gen impl __processor::SelectAnnotatedValue for @Self {
+            fn select_child(&self, __key: &str)
+                -> Option<(__processor::PathItem, &__processor::AnyAnnotated)>
+            {
+                self.0.select_child(__key)
+            }
+
+            fn select_child_mut(&mut self, __key: &str)
+                -> Option<(__processor::PathItem, &mut __processor::AnyAnnotated)>
+            {
+                self.0.select_child_mut(__key)
+            }
+
+            fn select_children(&self)
+                -> Vec<(__processor::PathItem, &__processor::AnyAnnotated)>
+            {
+                self.0.select_children()
+            }
+
+            fn select_children_mut(&mut self)
+                -> Vec<(__processor::PathItem, &mut __processor::AnyAnnotated)>
+            {
+                self.0.select_children_mut()
+            }
+
+            fn insert_child(&mut self, __key: &str) -> Option<&mut __processor::AnyAnnotated> {
+                self.0.insert_child(__key)
+            }
+
+            fn remove_child(&mut self, __key: &str) {
+                self.0.remove_child(__key)
+            }
+
+            fn needs_create(&self) -> bool {
+                self.0.needs_create()
+            }
+
+            fn create() -> Option<Self> {
+                __processor::SelectAnnotatedValue::create().map(#name)
+            }
+        }
+    }))
+}
+
+fn select_item_derive(s: synstructure::Structure) -> TokenStream {
+    let s = match select_wrapper_struct_derive(s) {
+        Ok(stream) => return stream,
+        Err(s) => s,
+    };
+
+    let fields = match s.ast().data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(ref fields),
+            ..
+        }) => &fields.named,
+        _ => panic!("SelectAnnotatedValue can only be derived for structs with named fields"),
+    };
+
+    // All fields are selectable, not just those marked with `#[process_annotated_value]`.
+    // Unknown keys are looked up in the flattened field, which holds additional properties.
+    let mut idents = Vec::new();
+    let mut keys = Vec::new();
+    let mut flatten = None;
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let attrs = parse_field_attrs(field);
+        if attrs.flatten {
+            if flatten.is_some() {
+                panic!("SelectAnnotatedValue supports at most one flattened field");
+            }
+            flatten = Some(ident);
+        } else {
+            keys.push(attrs.rename.unwrap_or_else(|| ident.to_string()));
+            idents.push(ident);
+        }
+    }
+
+    let all_idents = fields.iter().map(|field| field.ident.as_ref().unwrap());
+
+    let mut child_arms = TokenStream::new();
+    let mut child_mut_arms = TokenStream::new();
+    let mut children = TokenStream::new();
+    let mut children_mut = TokenStream::new();
+    let mut insert_arms = TokenStream::new();
+    for (ident, key) in idents.iter().zip(&keys) {
+        (quote! {
+            #key => Some((
+                __processor::PathItem::Key(#key),
+                &self.#ident as &__processor::AnyAnnotated,
+            )),
+        }).to_tokens(&mut child_arms);
+        (quote! {
+            #key => Some((
+                __processor::PathItem::Key(#key),
+                &mut self.#ident as &mut __processor::AnyAnnotated,
+            )),
+        }).to_tokens(&mut child_mut_arms);
+        (quote! {
+            (
+                __processor::PathItem::Key(#key),
+                &self.#ident as &__processor::AnyAnnotated,
+            ),
+        }).to_tokens(&mut children);
+        (quote! {
+            (
+                __processor::PathItem::Key(#key),
+                &mut self.#ident as &mut __processor::AnyAnnotated,
+            ),
+        }).to_tokens(&mut children_mut);
+        (quote! {
+            #key => Some(&mut self.#ident as &mut __processor::AnyAnnotated),
+        }).to_tokens(&mut insert_arms);
+    }
+
+    // Only children of the flattened field can be removed, struct fields always exist.
+    let remove_body = match flatten {
+        Some(ident) => quote! {
+            if let Some(x) = __processor::AnyAnnotated::value_mut(&mut self.#ident) {
+                x.remove_child(__key);
+            }
+        },
+        None => quote!(let _key = __key;),
+    };
+
+    let (select_flatten, select_flatten_mut, insert_flatten, children_body, children_mut_body) =
+        match flatten {
+            Some(ident) => (
+                quote! {
+                    __processor::AnyAnnotated::value(&self.#ident)
+                        .and_then(|x| x.select_child(__key))
+                },
+                quote! {
+                    __processor::AnyAnnotated::value_mut(&mut self.#ident)
+                        .and_then(|x| x.select_child_mut(__key))
+                },
+                quote! {
+                    __processor::AnyAnnotated::value_or_insert(&mut self.#ident)
+                        .and_then(|x| x.insert_child(__key))
+                },
+                quote! {
+                    let mut __children = vec![#children];
+                    if let Some(x) = __processor::AnyAnnotated::value(&self.#ident) {
+                        __children.extend(x.select_children());
+                    }
+                    __children
+                },
+                quote! {
+                    let mut __children = vec![#children_mut];
+                    if let Some(x) = __processor::AnyAnnotated::value_mut(&mut self.#ident) {
+                        __children.extend(x.select_children_mut());
+                    }
+                    __children
+                },
+            ),
+            None => (
+                quote!(None),
+                quote!(None),
+                quote!(None),
+                quote!(vec![#children]),
+                quote!(vec![#children_mut]),
+            ),
+        };
+
+    s.gen_impl(quote! {
+        use processor as __processor;
+        use protocol as __protocol;
+
+        gen impl __processor::SelectAnnotatedValue for @Self {
+            fn select_child(&self, __key: &str)
+                -> Option<(__processor::PathItem, &__processor::AnyAnnotated)>
+            {
+                match __key {
+                    #child_arms
+                    _ => #select_flatten,
+                }
+            }
+
+            fn select_child_mut(&mut self, __key: &str)
+                -> Option<(__processor::PathItem, &mut __processor::AnyAnnotated)>
+            {
+                match __key {
+                    #child_mut_arms
+                    _ => #select_flatten_mut,
+                }
+            }
+
+            fn select_children(&self)
+                -> Vec<(__processor::PathItem, &__processor::AnyAnnotated)>
+            {
+                #children_body
+            }
+
+            fn select_children_mut(&mut self)
+                -> Vec<(__processor::PathItem, &mut __processor::AnyAnnotated)>
+            {
+                #children_mut_body
+            }
+
+            fn insert_child(&mut self, __key: &str) -> Option<&mut __processor::AnyAnnotated> {
+                match __key {
+                    #insert_arms
+                    _ => #insert_flatten,
+                }
+            }
+
+            fn remove_child(&mut self, __key: &str) {
+                #remove_body
+            }
+
+            fn create() -> Option<Self> {
+                Some(Self {
+                    #(
+                        #all_idents: __protocol::Annotated::empty(),
+                    )*
+                })
+            }
+        }
+    })
+}
+
 fn pii_kind_to_enum_variant(name: &str) -> TokenStream {
     match name {
         "freeform" => quote!(PiiKind::Freeform),
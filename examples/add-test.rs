@@ -13,7 +13,7 @@ use dialoguer::{Confirmation, Editor, Input};
 use difference::Changeset;
 use failure::Error;
 use marshal::processor::PiiConfig;
-use marshal::protocol::{Annotated, Event};
+use marshal::protocol::{Annotated, Diff, Event};
 use serde_json::Value;
 
 static PII_CONFIG: &str = r#"{
@@ -53,6 +53,11 @@ fn check_diff(name: &str, before: &str, after: &str) -> Result<bool, Error> {
     Ok(Confirmation::new("Look good?").clear(false).interact()?)
 }
 
+fn check_changes(name: &str, diff: &Diff) -> Result<bool, Error> {
+    println!("{} Please check {}:\n{}", style(">").dim(), name, diff);
+    Ok(Confirmation::new("Look good?").clear(false).interact()?)
+}
+
 macro_rules! car {
     ($str:expr) => {
         &format!("{} {}", style(">").dim(), $str)
@@ -92,12 +97,12 @@ fn run() -> Result<(), Error> {
         return Ok(());
     }
 
-    let stripped = PiiConfig::from_json(PII_CONFIG)?
+    let stripped_event = PiiConfig::from_json(PII_CONFIG)?
         .processor()
-        .process_root_value(event)
-        .to_json_pretty()?;
+        .process_root_value(event.clone());
+    let stripped = stripped_event.to_json_pretty()?;
 
-    if !check_diff("PII stripping", &normalized, &normalized)? {
+    if !check_changes("PII stripping", &event.diff(&stripped_event)?)? {
         println!("{}", style("Aborting.").yellow());
         return Ok(());
     }
@@ -128,7 +133,7 @@ fn run() -> Result<(), Error> {
 
     println!();
     println!("All done. Don't forget to add the test in `tests/payloads.rs`:");
-    println!("  {}", style(&format!("run!($mode, {});", name)).dim());
+    println!("  {}", style(&format!("run!($mode, $expected, {});", name)).dim());
 
     Ok(())
 }
@@ -0,0 +1,636 @@
+//! A small rule language to tune in-app detection and grouping of stacktraces.
+//!
+//! Every line of an enhancements file contains one rule, consisting of one or more matchers
+//! followed by one or more actions. Empty lines and everything after `#` are ignored:
+//!
+//! ```text
+//! # mark all frames from the standard library as system frames
+//! family:native function:std::* -group -app
+//! path:**/vendor/** -app
+//! module:myapp.entrypoint group_start
+//! ```
+//!
+//! Matchers are written as `key:pattern` and can be negated with a leading `!`. Patterns are
+//! globs where `*` matches any sequence of characters and `?` a single character. For `path` and
+//! `package`, `*` does not match path separators while `**` matches any number of directories.
+//! Paths are matched case-insensitively. The `family` matcher accepts a comma separated list of
+//! platform families (`native`, `javascript` and `other`).
+//!
+//! The following actions are supported:
+//!
+//! - `+app` / `-app`: Marks the frame as in-app or system frame.
+//! - `+group` / `-group`: Includes or excludes the frame from grouping.
+//! - `group_start`: Starts the group at this frame, excluding all outer frames from grouping.
+//! - `group_end`: Ends the group at this frame, excluding all inner frames from grouping.
+use std::{fmt, str};
+
+use regex::Regex;
+
+use processor::for_each_stacktrace;
+use protocol::{get_str, Annotated, Event, Frame, Remark, RemarkType};
+
+/// The rule id used for frames whose in-app flag was changed by enhancements.
+const ENHANCEMENTS_RULE_ID: &str = "@enhancements";
+
+/// An error used when parsing `Enhancements`.
+#[derive(Debug, Fail, Clone, PartialEq, Eq)]
+pub enum ParseEnhancementsError {
+    /// A matcher refers to an unknown frame field.
+    #[fail(display = "unknown matcher '{}' on line {}", _1, _0)]
+    UnknownMatcher(usize, String),
+    /// An action is not supported.
+    #[fail(display = "unknown action '{}' on line {}", _1, _0)]
+    UnknownAction(usize, String),
+    /// A family matcher refers to an unknown family.
+    #[fail(display = "unknown family '{}' on line {}", _1, _0)]
+    UnknownFamily(usize, String),
+    /// A rule does not contain any matchers.
+    #[fail(display = "missing matchers on line {}", _0)]
+    MissingMatchers(usize),
+    /// A rule does not contain any actions.
+    #[fail(display = "missing actions on line {}", _0)]
+    MissingActions(usize),
+    /// Matchers were specified after an action.
+    #[fail(display = "matcher after action on line {}", _0)]
+    MatcherAfterAction(usize),
+}
+
+/// The frame field a matcher applies to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MatcherKind {
+    Function,
+    Module,
+    Package,
+    Path,
+    Family,
+}
+
+impl MatcherKind {
+    fn from_key(key: &str) -> Option<Self> {
+        Some(match key {
+            "function" => MatcherKind::Function,
+            "module" => MatcherKind::Module,
+            "package" => MatcherKind::Package,
+            "path" => MatcherKind::Path,
+            "family" => MatcherKind::Family,
+            _ => return None,
+        })
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            MatcherKind::Function => "function",
+            MatcherKind::Module => "module",
+            MatcherKind::Package => "package",
+            MatcherKind::Path => "path",
+            MatcherKind::Family => "family",
+        }
+    }
+
+    fn is_path_like(self) -> bool {
+        self == MatcherKind::Path || self == MatcherKind::Package
+    }
+}
+
+/// Known platform families.
+static FAMILIES: &[&str] = &["native", "javascript", "other"];
+
+/// Returns the family of a platform.
+fn get_family(platform: Option<&str>) -> &'static str {
+    match platform {
+        Some("native") | Some("cocoa") | Some("objc") | Some("swift") | Some("c") => "native",
+        Some("javascript") | Some("node") => "javascript",
+        _ => "other",
+    }
+}
+
+/// Translates a glob pattern into a regular expression.
+fn translate_glob(pattern: &str, path_like: bool) -> Regex {
+    let mut rv = String::from(if path_like { "(?i)^" } else { "^" });
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if path_like && chars.peek() == Some(&'/') {
+                    chars.next();
+                    rv.push_str("(?:.*/)?");
+                } else {
+                    rv.push_str(".*");
+                }
+            }
+            '*' if path_like => rv.push_str("[^/]*"),
+            '*' => rv.push_str(".*"),
+            '?' if path_like => rv.push_str("[^/]"),
+            '?' => rv.push('.'),
+            c => {
+                let mut buf = [0; 4];
+                rv.push_str(&::regex::escape(c.encode_utf8(&mut buf)));
+            }
+        }
+    }
+
+    rv.push('$');
+    Regex::new(&rv).unwrap()
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Glob(Regex),
+    Families(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Matcher {
+    kind: MatcherKind,
+    negated: bool,
+    raw: String,
+    pattern: Pattern,
+}
+
+impl Matcher {
+    fn parse(line: usize, token: &str) -> Result<Self, ParseEnhancementsError> {
+        let (negated, token) = if token.starts_with('!') {
+            (true, &token[1..])
+        } else {
+            (false, token)
+        };
+
+        let mut parts = token.splitn(2, ':');
+        let key = parts.next().unwrap_or("");
+        let raw = parts.next().unwrap_or("");
+        let kind = MatcherKind::from_key(key)
+            .ok_or_else(|| ParseEnhancementsError::UnknownMatcher(line, key.to_string()))?;
+
+        let pattern = if kind == MatcherKind::Family {
+            let families: Vec<String> = raw.split(',').map(str::to_string).collect();
+            if let Some(family) = families.iter().find(|f| !FAMILIES.contains(&f.as_str())) {
+                return Err(ParseEnhancementsError::UnknownFamily(line, family.clone()));
+            }
+            Pattern::Families(families)
+        } else {
+            Pattern::Glob(translate_glob(raw, kind.is_path_like()))
+        };
+
+        Ok(Matcher {
+            kind,
+            negated,
+            raw: raw.to_string(),
+            pattern,
+        })
+    }
+
+    fn matches_value(&self, frame: &Frame, platform: Option<&str>) -> bool {
+        let regex = match self.pattern {
+            Pattern::Families(ref families) => {
+                let family = get_family(platform);
+                return families.iter().any(|f| f == family);
+            }
+            Pattern::Glob(ref regex) => regex,
+        };
+
+        let value = match self.kind {
+            MatcherKind::Function => get_str(&frame.function).or_else(|| get_str(&frame.symbol)),
+            MatcherKind::Module => get_str(&frame.module),
+            MatcherKind::Package => get_str(&frame.package),
+            MatcherKind::Path => get_str(&frame.abs_path).or_else(|| get_str(&frame.filename)),
+            MatcherKind::Family => unreachable!(),
+        };
+
+        let value = match value {
+            Some(value) => value,
+            None => return false,
+        };
+
+        if self.kind.is_path_like() {
+            let value = value.replace('\\', "/");
+            regex.is_match(&value)
+                || value
+                    .rsplit('/')
+                    .next()
+                    .map_or(false, |basename| regex.is_match(basename))
+        } else {
+            regex.is_match(value)
+        }
+    }
+
+    fn matches(&self, frame: &Frame, platform: Option<&str>) -> bool {
+        self.matches_value(frame, platform) != self.negated
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated {
+            write!(f, "!")?;
+        }
+        write!(f, "{}:{}", self.kind.key(), self.raw)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Action {
+    App(bool),
+    Group(bool),
+    GroupStart,
+    GroupEnd,
+}
+
+impl Action {
+    fn parse(token: &str) -> Option<Self> {
+        Some(match token {
+            "+app" => Action::App(true),
+            "-app" => Action::App(false),
+            "+group" => Action::Group(true),
+            "-group" => Action::Group(false),
+            "group_start" => Action::GroupStart,
+            "group_end" => Action::GroupEnd,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::App(true) => write!(f, "+app"),
+            Action::App(false) => write!(f, "-app"),
+            Action::Group(true) => write!(f, "+group"),
+            Action::Group(false) => write!(f, "-group"),
+            Action::GroupStart => write!(f, "group_start"),
+            Action::GroupEnd => write!(f, "group_end"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    matchers: Vec<Matcher>,
+    actions: Vec<Action>,
+}
+
+impl Rule {
+    fn parse(line: usize, string: &str) -> Result<Self, ParseEnhancementsError> {
+        let mut matchers = Vec::new();
+        let mut actions = Vec::new();
+
+        for token in string.split_whitespace() {
+            if let Some(action) = Action::parse(token) {
+                actions.push(action);
+            } else if token.contains(':') {
+                if !actions.is_empty() {
+                    return Err(ParseEnhancementsError::MatcherAfterAction(line));
+                }
+                matchers.push(Matcher::parse(line, token)?);
+            } else {
+                return Err(ParseEnhancementsError::UnknownAction(
+                    line,
+                    token.to_string(),
+                ));
+            }
+        }
+
+        if matchers.is_empty() {
+            return Err(ParseEnhancementsError::MissingMatchers(line));
+        } else if actions.is_empty() {
+            return Err(ParseEnhancementsError::MissingActions(line));
+        }
+
+        Ok(Rule { matchers, actions })
+    }
+
+    fn matches(&self, frame: &Frame, platform: Option<&str>) -> bool {
+        self.matchers
+            .iter()
+            .all(|matcher| matcher.matches(frame, platform))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, matcher) in self.matchers.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", matcher)?;
+        }
+        for action in &self.actions {
+            write!(f, " {}", action)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of applying enhancements to a single frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FrameState {
+    /// Overrides the in-app flag of the frame.
+    pub in_app: Option<bool>,
+    /// Overrides whether the frame contributes to grouping.
+    pub group: Option<bool>,
+}
+
+/// The outcome of applying enhancements to a list of frames.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct StacktraceState {
+    /// The state of every frame.
+    pub frames: Vec<FrameState>,
+    /// The index of the outermost frame that contributes to grouping.
+    pub start: Option<usize>,
+    /// The index of the innermost frame that contributes to grouping.
+    pub end: Option<usize>,
+}
+
+impl StacktraceState {
+    /// Checks whether the frame at the given index lies within the group boundaries.
+    pub fn in_range(&self, index: usize) -> bool {
+        self.start.map_or(true, |start| index >= start) && self.end.map_or(true, |end| index <= end)
+    }
+}
+
+/// A parsed set of stacktrace enhancement rules.
+#[derive(Debug, Clone, Default)]
+pub struct Enhancements {
+    rules: Vec<Rule>,
+}
+
+impl Enhancements {
+    /// Returns the number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Checks whether there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Evaluates all rules against a list of frames ordered from outermost to innermost.
+    ///
+    /// Rules are applied in order, so later rules override earlier ones.
+    pub(crate) fn evaluate(&self, frames: &[&Frame], platform: Option<&str>) -> StacktraceState {
+        let mut state = StacktraceState {
+            frames: vec![FrameState::default(); frames.len()],
+            start: None,
+            end: None,
+        };
+
+        for rule in &self.rules {
+            for (index, frame) in frames.iter().enumerate() {
+                if !rule.matches(frame, platform) {
+                    continue;
+                }
+
+                for action in &rule.actions {
+                    match *action {
+                        Action::App(in_app) => state.frames[index].in_app = Some(in_app),
+                        Action::Group(group) => state.frames[index].group = Some(group),
+                        Action::GroupStart => {
+                            state.start = Some(state.start.map_or(index, |s| s.max(index)))
+                        }
+                        Action::GroupEnd => {
+                            state.end = Some(state.end.map_or(index, |e| e.min(index)))
+                        }
+                    }
+                }
+            }
+        }
+
+        state
+    }
+
+    /// Applies the in-app actions of all rules to every stacktrace in the event.
+    ///
+    /// Grouping actions are not persisted in the event; pass the enhancements to the grouping
+    /// functions instead.
+    pub fn apply_to_event(&self, event: &mut Event) {
+        if self.is_empty() {
+            return;
+        }
+
+        let platform = event.platform.value().cloned();
+        let platform = platform.as_ref().map(String::as_str);
+
+        for_each_stacktrace(event, |stacktrace| {
+            let frames = match stacktrace.frames.value_mut() {
+                Some(frames) => frames,
+                None => return,
+            };
+
+            let state = {
+                let frame_refs: Vec<_> = frames.iter().filter_map(Annotated::value).collect();
+                self.evaluate(&frame_refs, platform)
+            };
+
+            let frames = frames.iter_mut().filter_map(Annotated::value_mut);
+            for (frame, frame_state) in frames.zip(state.frames) {
+                let in_app = match frame_state.in_app {
+                    Some(in_app) => in_app,
+                    None => continue,
+                };
+
+                if frame.in_app.value() != Some(&Some(in_app)) {
+                    frame.in_app.set_value(Some(Some(in_app)));
+                    frame
+                        .in_app
+                        .meta_mut()
+                        .remarks_mut()
+                        .push(Remark::new(RemarkType::Substituted, ENHANCEMENTS_RULE_ID));
+                }
+            }
+        });
+    }
+}
+
+impl str::FromStr for Enhancements {
+    type Err = ParseEnhancementsError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+
+        for (index, line) in string.lines().enumerate() {
+            let line = line.splitn(2, '#').next().unwrap_or("").trim();
+            if !line.is_empty() {
+                rules.push(Rule::parse(index + 1, line)?);
+            }
+        }
+
+        Ok(Enhancements { rules })
+    }
+}
+
+impl fmt::Display for Enhancements {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for rule in &self.rules {
+            writeln!(f, "{}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(json: &str) -> Frame {
+        Annotated::<Frame>::from_json(json).unwrap().0.unwrap()
+    }
+
+    fn evaluate(rules: &str, json: &str, platform: &str) -> FrameState {
+        let enhancements: Enhancements = rules.parse().unwrap();
+        let frame = frame(json);
+        enhancements
+            .evaluate(&[&frame], Some(platform))
+            .frames
+            .pop()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let enhancements: Enhancements = "
+            # comment
+            family:native function:std::* -group -app
+
+            !path:**/vendor/** +app  # trailing comment
+        "
+        .parse()
+        .unwrap();
+
+        assert_eq_dbg!(enhancements.len(), 2);
+        assert_eq_str!(
+            enhancements.to_string(),
+            "family:native function:std::* -group -app\n!path:**/vendor/** +app\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq_dbg!(
+            "foo:bar -app".parse::<Enhancements>().unwrap_err(),
+            ParseEnhancementsError::UnknownMatcher(1, "foo".to_string())
+        );
+        assert_eq_dbg!(
+            "\nfunction:foo +bar".parse::<Enhancements>().unwrap_err(),
+            ParseEnhancementsError::UnknownAction(2, "+bar".to_string())
+        );
+        assert_eq_dbg!(
+            "family:cobol -app".parse::<Enhancements>().unwrap_err(),
+            ParseEnhancementsError::UnknownFamily(1, "cobol".to_string())
+        );
+        assert_eq_dbg!(
+            "-app".parse::<Enhancements>().unwrap_err(),
+            ParseEnhancementsError::MissingMatchers(1)
+        );
+        assert_eq_dbg!(
+            "function:foo".parse::<Enhancements>().unwrap_err(),
+            ParseEnhancementsError::MissingActions(1)
+        );
+        assert_eq_dbg!(
+            "function:foo -app module:bar"
+                .parse::<Enhancements>()
+                .unwrap_err(),
+            ParseEnhancementsError::MatcherAfterAction(1)
+        );
+    }
+
+    #[test]
+    fn test_function_and_family() {
+        let rules = "family:native function:std::* -group -app";
+        let json = r#"{"function": "std::panicking::begin_panic"}"#;
+
+        assert_eq_dbg!(
+            evaluate(rules, json, "native"),
+            FrameState {
+                in_app: Some(false),
+                group: Some(false),
+            }
+        );
+        assert_eq_dbg!(evaluate(rules, json, "python"), FrameState::default());
+    }
+
+    #[test]
+    fn test_path() {
+        let rules = "path:**/vendor/** -app";
+
+        let state = evaluate(rules, r#"{"abs_path": "/app/vendor/lib/foo.php"}"#, "php");
+        assert_eq_dbg!(state.in_app, Some(false));
+
+        let state = evaluate(rules, r#"{"abs_path": "C:\\App\\Vendor\\foo.php"}"#, "php");
+        assert_eq_dbg!(state.in_app, Some(false));
+
+        let state = evaluate(rules, r#"{"abs_path": "/app/src/foo.php"}"#, "php");
+        assert_eq_dbg!(state.in_app, None);
+    }
+
+    #[test]
+    fn test_package_basename() {
+        let rules = "package:libsystem_*.dylib -app";
+        let json = r#"{"package": "/usr/lib/system/libsystem_kernel.dylib"}"#;
+        assert_eq_dbg!(evaluate(rules, json, "cocoa").in_app, Some(false));
+    }
+
+    #[test]
+    fn test_negation_and_override() {
+        let rules = "module:* -app\n!module:django.* +app";
+        assert_eq_dbg!(
+            evaluate(rules, r#"{"module": "myapp.views"}"#, "python").in_app,
+            Some(true)
+        );
+        assert_eq_dbg!(
+            evaluate(rules, r#"{"module": "django.core"}"#, "python").in_app,
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_group_range() {
+        let enhancements: Enhancements = "function:main group_start\nfunction:panic group_end"
+            .parse()
+            .unwrap();
+        let frames: Vec<_> = ["_start", "main", "run", "panic", "abort"]
+            .iter()
+            .map(|f| frame(&format!(r#"{{"function": "{}"}}"#, f)))
+            .collect();
+        let frame_refs: Vec<_> = frames.iter().collect();
+
+        let state = enhancements.evaluate(&frame_refs, None);
+        let in_range: Vec<_> = (0..frames.len()).map(|i| state.in_range(i)).collect();
+        assert_eq_dbg!(in_range, vec![false, true, true, true, false]);
+    }
+
+    #[test]
+    fn test_apply_to_event() {
+        let enhancements: Enhancements = "module:vendor.* -app\nmodule:app.* +app".parse().unwrap();
+        let mut event = Annotated::<Event>::from_json(
+            r#"{
+                "exception": {"values": [{"type": "Error", "stacktrace": {"frames": [
+                    {"module": "vendor.lib", "in_app": true},
+                    {"module": "app.views", "in_app": true},
+                    {"module": "other"}
+                ]}}]}
+            }"#,
+        )
+        .unwrap()
+        .0
+        .unwrap();
+
+        enhancements.apply_to_event(&mut event);
+
+        let exceptions = event.exceptions.0.unwrap().values.0.unwrap();
+        let exception = exceptions[0].value().unwrap();
+        let stacktrace = exception.stacktrace.value().unwrap().as_ref().unwrap();
+        let frames = stacktrace.frames.value().unwrap();
+
+        let vendor = &frames[0].value().unwrap().in_app;
+        assert_eq_dbg!(vendor.value(), Some(&Some(false)));
+        assert_eq_dbg!(vendor.meta().remarks().count(), 1);
+
+        let app = &frames[1].value().unwrap().in_app;
+        assert_eq_dbg!(app, &Annotated::from(Some(true)));
+
+        let other = &frames[2].value().unwrap().in_app;
+        assert_eq_dbg!(other, &Annotated::from(None));
+    }
+}
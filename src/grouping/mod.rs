@@ -0,0 +1,623 @@
+//! Computes grouping hashes for events.
+//!
+//! The default grouping builds a tree of components from the most relevant information in the
+//! event: chained exceptions, the stacktrace, template information or the message. Only the first
+//! of these that contributes is used, all others are kept in the tree with an explanation why
+//! they did not contribute. Custom fingerprints can reference the default grouping with the
+//! `{{ default }}` placeholder.
+//!
+//! Stacktrace grouping can be tuned with `Enhancements`, which may override the in-app flag of
+//! frames, exclude frames from grouping or restrict grouping to a range of frames.
+use sha1::{Digest, Sha1};
+
+use protocol::{get_str, Annotated, Event, Exception, Frame, Stacktrace};
+
+mod enhancers;
+
+pub use self::enhancers::*;
+
+/// Context lines longer than this are not considered for grouping.
+const MAX_CONTEXT_LINE_LENGTH: usize = 120;
+
+/// A value of a grouping component.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum GroupingValue {
+    /// A nested component.
+    Component(GroupingComponent),
+    /// A plain text value that is hashed if the component contributes.
+    Text(String),
+}
+
+/// A node in the grouping component tree.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupingComponent {
+    /// The identifier of the component (e.g. `exception` or `function`).
+    pub id: String,
+    /// Whether this component contributes to the grouping hash.
+    pub contributes: bool,
+    /// A human readable explanation why this component contributes or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Text values and child components.
+    pub values: Vec<GroupingValue>,
+}
+
+impl GroupingComponent {
+    /// Creates a new component.
+    ///
+    /// The component contributes if at least one of its values contributes. Otherwise, it
+    /// receives a hint explaining why it does not contribute.
+    pub fn new<S: Into<String>>(id: S, values: Vec<GroupingValue>) -> Self {
+        let contributes = values.iter().any(|value| match *value {
+            GroupingValue::Component(ref component) => component.contributes,
+            GroupingValue::Text(_) => true,
+        });
+
+        let hint = if contributes {
+            None
+        } else if values.is_empty() {
+            Some("contains no values".to_string())
+        } else {
+            Some("contains no contributing values".to_string())
+        };
+
+        GroupingComponent {
+            id: id.into(),
+            contributes,
+            hint,
+            values,
+        }
+    }
+
+    /// Creates a new component with a single text value.
+    pub fn from_text<S: Into<String>, T: Into<String>>(id: S, text: T) -> Self {
+        Self::new(id, vec![GroupingValue::Text(text.into())])
+    }
+
+    /// Sets the hint of this component.
+    pub fn with_hint<S: Into<String>>(mut self, hint: S) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Excludes this component from the hash and explains why.
+    pub fn discard<S: Into<String>>(mut self, hint: S) -> Self {
+        self.contributes = false;
+        self.with_hint(hint)
+    }
+
+    /// Returns all text values that contribute to the hash in tree order.
+    pub fn contributing_values(&self) -> Vec<&str> {
+        let mut rv = Vec::new();
+        self.collect_values(&mut rv);
+        rv
+    }
+
+    fn collect_values<'a>(&'a self, rv: &mut Vec<&'a str>) {
+        if !self.contributes {
+            return;
+        }
+
+        for value in &self.values {
+            match *value {
+                GroupingValue::Component(ref component) => component.collect_values(rv),
+                GroupingValue::Text(ref text) => rv.push(text),
+            }
+        }
+    }
+
+    /// Computes a stable hash over all contributing components and values.
+    ///
+    /// The identifiers of components are hashed along with their values, so that equal values
+    /// in different components result in different hashes. Returns `None` if nothing in the tree
+    /// contributes.
+    pub fn hash(&self) -> Option<String> {
+        if !self.contributes {
+            return None;
+        }
+
+        let mut hasher = Sha1::default();
+        self.hash_into(&mut hasher);
+        Some(format!("{:x}", hasher.result()))
+    }
+
+    fn hash_into(&self, hasher: &mut Sha1) {
+        hash_str(hasher, b'c', &self.id);
+        for value in &self.values {
+            match *value {
+                GroupingValue::Component(ref component) if component.contributes => {
+                    component.hash_into(hasher)
+                }
+                GroupingValue::Component(_) => (),
+                GroupingValue::Text(ref text) => hash_str(hasher, b't', text),
+            }
+        }
+        hasher.input(b"e");
+    }
+}
+
+/// Feeds a tagged and length-prefixed string into the hasher.
+fn hash_str(hasher: &mut Sha1, tag: u8, value: &str) {
+    hasher.input(&[tag]);
+    hasher.input(format!("{}:", value.len()).as_bytes());
+    hasher.input(value.as_bytes());
+}
+
+impl From<GroupingComponent> for GroupingValue {
+    fn from(component: GroupingComponent) -> Self {
+        GroupingValue::Component(component)
+    }
+}
+
+fn is_in_app(frame: &Frame) -> bool {
+    frame.in_app.value() == Some(&Some(true))
+}
+
+fn get_frame_component(
+    frame: &Frame,
+    state: &FrameState,
+    in_range: bool,
+    has_in_app: bool,
+) -> GroupingComponent {
+    let mut values = Vec::new();
+
+    if let Some(module) = get_str(&frame.module) {
+        values.push(GroupingComponent::from_text("module", module).into());
+    } else if let Some(filename) = get_str(&frame.filename) {
+        values.push(GroupingComponent::from_text("filename", filename).into());
+    }
+
+    let function = get_str(&frame.function);
+    if let Some(function) = function {
+        values.push(GroupingComponent::from_text("function", function).into());
+    }
+
+    if let Some(line) = get_str(&frame.current_line) {
+        let component = GroupingComponent::from_text("context_line", line.trim());
+        values.push(
+            if function.is_some() {
+                component.discard("function takes precedence")
+            } else if line.len() > MAX_CONTEXT_LINE_LENGTH {
+                component.discard("discarded because line too long")
+            } else {
+                component
+            }
+            .into(),
+        );
+    }
+
+    let component = GroupingComponent::new("frame", values);
+    let in_app = state.in_app.unwrap_or_else(|| is_in_app(frame));
+    if !in_range {
+        component.discard("outside of the group range")
+    } else if state.group == Some(false) {
+        component.discard("ignored by enhancement rule")
+    } else if state.group.is_none() && has_in_app && !in_app {
+        component.discard("non app frame")
+    } else {
+        component
+    }
+}
+
+fn get_stacktrace_component(
+    stacktrace: &Stacktrace,
+    platform: Option<&str>,
+    enhancements: &Enhancements,
+) -> GroupingComponent {
+    let frames: Vec<_> = stacktrace
+        .frames
+        .value()
+        .into_iter()
+        .flatten()
+        .filter_map(Annotated::value)
+        .collect();
+
+    let state = enhancements.evaluate(&frames, platform);
+    let has_in_app = frames
+        .iter()
+        .zip(&state.frames)
+        .any(|(frame, frame_state)| frame_state.in_app.unwrap_or_else(|| is_in_app(frame)));
+    let values = frames
+        .iter()
+        .zip(&state.frames)
+        .enumerate()
+        .map(|(index, (frame, frame_state))| {
+            get_frame_component(frame, frame_state, state.in_range(index), has_in_app).into()
+        })
+        .collect();
+
+    let component = GroupingComponent::new("stacktrace", values);
+    if !component.contributes {
+        component.with_hint("contains no usable frames")
+    } else if !has_in_app {
+        component.with_hint("contains no in-app frames, all frames are used")
+    } else {
+        component
+    }
+}
+
+fn get_exception_component(
+    exception: &Exception,
+    platform: Option<&str>,
+    enhancements: &Enhancements,
+) -> GroupingComponent {
+    let mut values = Vec::new();
+
+    if let Some(ty) = exception.ty.value() {
+        values.push(GroupingComponent::from_text("type", ty.as_str()).into());
+    }
+
+    let stacktrace = match exception.stacktrace.value() {
+        Some(Some(stacktrace)) => {
+            Some(get_stacktrace_component(stacktrace, platform, enhancements))
+        }
+        _ => None,
+    };
+    let has_stacktrace = stacktrace.as_ref().map_or(false, |s| s.contributes);
+
+    if let Some(stacktrace) = stacktrace {
+        values.push(stacktrace.into());
+    }
+
+    if let Some(value) = get_str(&exception.value) {
+        let component = GroupingComponent::from_text("value", value);
+        values.push(
+            if has_stacktrace {
+                component.discard("stacktrace takes precedence")
+            } else {
+                component
+            }
+            .into(),
+        );
+    }
+
+    GroupingComponent::new("exception", values)
+}
+
+/// Builds the candidate components in order of precedence.
+fn get_candidate_components(event: &Event, enhancements: &Enhancements) -> Vec<GroupingComponent> {
+    let mut candidates = Vec::new();
+    let platform = event.platform.value().map(String::as_str);
+
+    let exceptions = event.exceptions.value().and_then(|e| e.values.value());
+    let exceptions: Vec<_> = exceptions
+        .into_iter()
+        .flatten()
+        .filter_map(Annotated::value)
+        .map(|exception| get_exception_component(exception, platform, enhancements).into())
+        .collect();
+    if !exceptions.is_empty() {
+        candidates.push(GroupingComponent::new("chained_exception", exceptions));
+    }
+
+    if let Some(Some(stacktrace)) = event.stacktrace.value() {
+        candidates.push(get_stacktrace_component(stacktrace, platform, enhancements));
+    }
+
+    if let Some(Some(template)) = event.template_info.value() {
+        let mut values = Vec::new();
+        if let Some(filename) = get_str(&template.filename) {
+            values.push(GroupingComponent::from_text("filename", filename).into());
+        }
+        if let Some(line) = get_str(&template.current_line) {
+            values.push(GroupingComponent::from_text("context_line", line.trim()).into());
+        }
+        candidates.push(GroupingComponent::new("template", values));
+    }
+
+    let logentry = match event.logentry.value() {
+        Some(Some(logentry)) => logentry.message.value().map(String::as_str),
+        _ => None,
+    };
+    if let Some(message) = logentry.or_else(|| get_str(&event.message)) {
+        candidates.push(GroupingComponent::from_text("message", message));
+    }
+
+    candidates
+}
+
+/// Computes the default grouping component tree of an event.
+///
+/// Only the first contributing candidate contributes to the hash, all subsequent candidates are
+/// retained with a hint. Stacktraces are grouped according to the given enhancements.
+pub fn get_default_component(event: &Event, enhancements: &Enhancements) -> GroupingComponent {
+    let mut values = Vec::new();
+    let mut winner: Option<String> = None;
+
+    for candidate in get_candidate_components(event, enhancements) {
+        let candidate = match winner {
+            Some(ref id) => candidate.discard(format!("{} takes precedence", id)),
+            None => candidate,
+        };
+
+        if winner.is_none() && candidate.contributes {
+            winner = Some(candidate.id.clone());
+        }
+
+        values.push(candidate.into());
+    }
+
+    GroupingComponent::new("default", values)
+}
+
+/// Checks whether a fingerprint entry refers to the default grouping.
+fn is_default_fingerprint(value: &str) -> bool {
+    value == "{{ default }}" || value == "{{default}}"
+}
+
+/// Computes the grouping component tree of an event honoring its fingerprint.
+///
+/// Custom fingerprint values are hashed verbatim, while `{{ default }}` is replaced by the
+/// default grouping of the event.
+pub fn get_grouping_component(event: &Event, enhancements: &Enhancements) -> GroupingComponent {
+    let fingerprint = match event.fingerprint.value() {
+        Some(fingerprint) if !fingerprint.is_empty() => fingerprint,
+        _ => return get_default_component(event, enhancements),
+    };
+
+    if fingerprint.len() == 1 && is_default_fingerprint(&fingerprint[0]) {
+        return get_default_component(event, enhancements);
+    }
+
+    let values = fingerprint
+        .iter()
+        .map(|value| {
+            if is_default_fingerprint(value) {
+                get_default_component(event, enhancements).into()
+            } else {
+                GroupingComponent::from_text("custom", value.as_str()).into()
+            }
+        })
+        .collect();
+
+    GroupingComponent::new("fingerprint", values).with_hint("custom fingerprint")
+}
+
+/// Computes the grouping hash of an event.
+///
+/// Returns `None` if the event contains no information suitable for grouping.
+pub fn compute_hash(event: &Event, enhancements: &Enhancements) -> Option<String> {
+    get_grouping_component(event, enhancements).hash()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutils::event;
+
+    fn grouping(event: &Event) -> GroupingComponent {
+        get_grouping_component(event, &Enhancements::default())
+    }
+
+    fn hash(event: &Event) -> Option<String> {
+        compute_hash(event, &Enhancements::default())
+    }
+
+    #[test]
+    fn test_message() {
+        let event = event(r#"{"message": "Hello World!"}"#);
+        let component = grouping(&event);
+        assert_eq_dbg!(component.contributing_values(), vec!["Hello World!"]);
+    }
+
+    #[test]
+    fn test_logentry_precedence() {
+        let event = event(
+            r#"{
+                "message": "Hello Peter!",
+                "logentry": {"message": "Hello %s!", "params": ["Peter"]}
+            }"#,
+        );
+        let component = grouping(&event);
+        assert_eq_dbg!(component.contributing_values(), vec!["Hello %s!"]);
+    }
+
+    #[test]
+    fn test_exception_in_app_frames() {
+        let event = event(
+            r#"{
+                "message": "ignored",
+                "exception": {"values": [{
+                    "type": "ZeroDivisionError",
+                    "value": "division by zero",
+                    "stacktrace": {"frames": [
+                        {"module": "django.core", "function": "handle", "in_app": false},
+                        {"module": "app.views", "function": "index", "in_app": true},
+                        {"filename": "app/utils.py", "context_line": "  1 / 0", "in_app": true}
+                    ]}
+                }]}
+            }"#,
+        );
+
+        let component = grouping(&event);
+        assert_eq_dbg!(
+            component.contributing_values(),
+            vec![
+                "ZeroDivisionError",
+                "app.views",
+                "index",
+                "app/utils.py",
+                "1 / 0",
+            ]
+        );
+
+        let message = match component.values[1] {
+            GroupingValue::Component(ref message) => message,
+            _ => panic!("expected component"),
+        };
+        assert!(!message.contributes);
+        assert_eq_dbg!(
+            message.hint,
+            Some("chained_exception takes precedence".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exception_without_stacktrace() {
+        let event =
+            event(r#"{"exception": {"values": [{"type": "ValueError", "value": "bad value"}]}}"#);
+        let component = grouping(&event);
+        assert_eq_dbg!(
+            component.contributing_values(),
+            vec!["ValueError", "bad value"]
+        );
+    }
+
+    #[test]
+    fn test_stacktrace_without_in_app() {
+        let event = event(
+            r#"{"stacktrace": {"frames": [
+                {"module": "a", "function": "x"},
+                {"module": "b", "function": "y"}
+            ]}}"#,
+        );
+        let component = grouping(&event);
+        assert_eq_dbg!(component.contributing_values(), vec!["a", "x", "b", "y"]);
+    }
+
+    #[test]
+    fn test_template() {
+        let event =
+            event(r#"{"template": {"filename": "index.html", "context_line": "{{ foo }}"}}"#);
+        let component = grouping(&event);
+        assert_eq_dbg!(
+            component.contributing_values(),
+            vec!["index.html", "{{ foo }}"]
+        );
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        let a = event(r#"{"message": "Hello World!", "level": "error"}"#);
+        let b = event(r#"{"message": "Hello World!", "level": "info"}"#);
+        let c = event(r#"{"message": "Hello Peter!"}"#);
+
+        assert_eq_dbg!(hash(&a), hash(&b));
+        assert_ne!(hash(&a), hash(&c));
+        assert_eq_dbg!(hash(&a).unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_no_grouping_info() {
+        assert_eq_dbg!(hash(&event("{}")), None);
+    }
+
+    #[test]
+    fn test_custom_fingerprint() {
+        let a = event(r#"{"message": "a", "fingerprint": ["foo", "bar"]}"#);
+        let b = event(r#"{"message": "b", "fingerprint": ["foo", "bar"]}"#);
+
+        let component = grouping(&a);
+        assert_eq_dbg!(component.contributing_values(), vec!["foo", "bar"]);
+        assert_eq_dbg!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn test_default_in_fingerprint() {
+        let a = event(r#"{"message": "a", "fingerprint": ["{{ default }}", "foo"]}"#);
+        let b = event(r#"{"message": "a", "fingerprint": ["{{default}}", "bar"]}"#);
+        let c = event(r#"{"message": "a"}"#);
+
+        let component = grouping(&a);
+        assert_eq_dbg!(component.contributing_values(), vec!["a", "foo"]);
+        assert_ne!(hash(&a), hash(&b));
+        assert_ne!(hash(&a), hash(&c));
+    }
+
+    #[test]
+    fn test_default_in_fingerprint_hash() {
+        let a = event(r#"{"message": "a", "fingerprint": ["a", "foo"]}"#);
+        let b = event(r#"{"message": "a", "fingerprint": ["{{ default }}", "foo"]}"#);
+
+        let (component_a, component_b) = (grouping(&a), grouping(&b));
+        assert_eq_dbg!(
+            component_a.contributing_values(),
+            component_b.contributing_values()
+        );
+        assert_ne!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn test_frame_module_filename_hash() {
+        let a = event(r#"{"stacktrace": {"frames": [{"module": "foo", "function": "x"}]}}"#);
+        let b = event(r#"{"stacktrace": {"frames": [{"filename": "foo", "function": "x"}]}}"#);
+
+        let (component_a, component_b) = (grouping(&a), grouping(&b));
+        assert_eq_dbg!(
+            component_a.contributing_values(),
+            component_b.contributing_values()
+        );
+        assert_ne!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn test_hint_without_frames() {
+        let event = event(r#"{"message": "a", "stacktrace": {"frames": [{}]}}"#);
+
+        let component = grouping(&event);
+        assert_eq_dbg!(component.contributing_values(), vec!["a"]);
+
+        let stacktrace = match component.values[0] {
+            GroupingValue::Component(ref stacktrace) => stacktrace,
+            _ => panic!("expected component"),
+        };
+        assert!(!stacktrace.contributes);
+        assert_eq_dbg!(
+            stacktrace.hint,
+            Some("contains no usable frames".to_string())
+        );
+
+        let frame = match stacktrace.values[0] {
+            GroupingValue::Component(ref frame) => frame,
+            _ => panic!("expected component"),
+        };
+        assert_eq_dbg!(frame.hint, Some("contains no values".to_string()));
+    }
+
+    #[test]
+    fn test_explicit_default_fingerprint() {
+        let a = event(r#"{"message": "a", "fingerprint": ["{{ default }}"]}"#);
+        let b = event(r#"{"message": "a"}"#);
+        assert_eq_dbg!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn test_enhancements_group() {
+        let event = event(
+            r#"{
+                "platform": "native",
+                "stacktrace": {"frames": [
+                    {"function": "main", "in_app": true},
+                    {"function": "std::panicking::begin_panic", "in_app": true},
+                    {"function": "app::crash"}
+                ]}
+            }"#,
+        );
+
+        let enhancements = "function:std::* -group\nfunction:app::* +app"
+            .parse()
+            .unwrap();
+        let component = get_grouping_component(&event, &enhancements);
+        assert_eq_dbg!(component.contributing_values(), vec!["main", "app::crash"]);
+    }
+
+    #[test]
+    fn test_enhancements_group_range() {
+        let event = event(
+            r#"{"stacktrace": {"frames": [
+                {"function": "_start"},
+                {"function": "main"},
+                {"function": "panic"},
+                {"function": "abort"}
+            ]}}"#,
+        );
+
+        let enhancements = "function:main group_start\nfunction:panic group_end"
+            .parse()
+            .unwrap();
+        let component = get_grouping_component(&event, &enhancements);
+        assert_eq_dbg!(component.contributing_values(), vec!["main", "panic"]);
+        assert_ne!(hash(&event), component.hash());
+    }
+}
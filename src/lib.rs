@@ -14,14 +14,18 @@ extern crate failure_derive;
 #[macro_use]
 extern crate lazy_static;
 extern crate hmac;
+extern crate maxminddb;
 extern crate queryst;
 extern crate regex;
+#[macro_use]
 extern crate serde;
+extern crate serde_cbor;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate sha1;
 extern crate sha2;
+extern crate url;
 extern crate uuid;
 
 #[macro_use]
@@ -34,5 +38,6 @@ extern crate difference;
 #[macro_use]
 mod testutils;
 
+pub mod grouping;
 pub mod processor;
 pub mod protocol;
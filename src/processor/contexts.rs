@@ -0,0 +1,364 @@
+//! Normalization of OS and runtime contexts from their raw descriptions.
+use regex::Regex;
+
+use protocol::{get_str, Annotated, Context, Event, OsContext, RuntimeContext};
+
+use super::utils::fill_derived;
+
+/// The rule id used for context fields derived from the raw description.
+const RAW_DESCRIPTION_RULE_ID: &str = "@raw_description";
+
+lazy_static! {
+    /// Windows as reported by .NET, e.g. `Microsoft Windows NT 10.0.17134.0`.
+    static ref WINDOWS_RE: Regex = Regex::new(
+        r"^Microsoft Windows (?:NT )?(?P<kernel_version>(?P<major>\d+)\.(?P<minor>\d+)\.(?P<build>\d+))(?:\.\d+)?(?: Service Pack \d+)?$"
+    ).unwrap();
+    /// Windows as reported by Unity, e.g. `Windows 10  (10.0.0) 64bit`.
+    static ref UNITY_WINDOWS_RE: Regex = Regex::new(
+        r"^Windows (?P<version>\d+(?:\.\d+)?) +\((?P<kernel_version>\d+\.\d+\.\d+)\)"
+    ).unwrap();
+    /// Kernel version of Apple platforms, e.g. `Darwin Kernel Version 17.7.0: ...`.
+    static ref DARWIN_RE: Regex = Regex::new(
+        r"^Darwin Kernel Version (?P<kernel_version>(?P<major>\d+)\.\d+\.\d+)"
+    ).unwrap();
+    /// macOS as reported by Unity, e.g. `Mac OS X 10.14.6`.
+    static ref MACOS_RE: Regex = Regex::new(
+        r"^Mac OS X (?P<version>\d+\.\d+(?:\.\d+)?)"
+    ).unwrap();
+    /// Other Apple platforms as reported by Unity, e.g. `iOS 12.1`.
+    static ref APPLE_RE: Regex = Regex::new(
+        r"^(?P<name>iOS|iPadOS|tvOS|watchOS) (?P<version>\d+(?:\.\d+){0,2})"
+    ).unwrap();
+    /// Android as reported by Unity, e.g. `Android OS 9 / API-28 (PPR1.180610.011/G960FXXU2CRLI)`.
+    static ref ANDROID_RE: Regex = Regex::new(
+        r"^Android OS (?P<version>[^\s]+) / API-\d+ \((?P<build>[^)/]+)"
+    ).unwrap();
+    /// Linux kernels, e.g. `Linux 4.15.0-1044-aws #46-Ubuntu SMP`.
+    static ref LINUX_RE: Regex = Regex::new(
+        r"^Linux (?P<kernel_version>\d+\.\d+(?:\.\d+)?)"
+    ).unwrap();
+    /// .NET runtimes, e.g. `.NET Framework 4.7.2` or `Mono 5.12.0.226 (2018-02/...)`.
+    static ref RUNTIME_RE: Regex = Regex::new(
+        r"^(?P<name>\.NET(?: Framework| Core| Native)?|Mono) (?P<version>\d+(?:\.\d+)*)"
+    ).unwrap();
+}
+
+/// Structured information parsed from a raw OS description.
+#[derive(Debug, Default, PartialEq)]
+struct OsInfo {
+    name: Option<String>,
+    version: Option<String>,
+    build: Option<String>,
+    kernel_version: Option<String>,
+}
+
+/// Maps a Windows kernel version and build number to the marketing version.
+fn get_windows_version(major: u32, minor: u32, build: u32) -> Option<&'static str> {
+    Some(match (major, minor) {
+        (10, 0) if build >= 22000 => "11",
+        (10, 0) => "10",
+        (6, 3) => "8.1",
+        (6, 2) => "8",
+        (6, 1) => "7",
+        (6, 0) => "Vista",
+        (5, 1) | (5, 2) => "XP",
+        _ => return None,
+    })
+}
+
+/// Maps a Darwin kernel major version to the macOS version.
+fn get_macos_version(darwin_major: u32) -> Option<String> {
+    if darwin_major >= 20 {
+        Some((darwin_major - 9).to_string())
+    } else if darwin_major >= 5 {
+        Some(format!("10.{}", darwin_major - 4))
+    } else {
+        None
+    }
+}
+
+/// Maps a Darwin kernel major version to the iOS major version.
+fn get_ios_version(darwin_major: u32) -> Option<String> {
+    if darwin_major >= 10 {
+        Some((darwin_major - 6).to_string())
+    } else {
+        None
+    }
+}
+
+/// Parses a raw OS description.
+///
+/// The name of the OS, if already known, is used to disambiguate kernel versions shared across
+/// platforms.
+fn parse_os(raw_description: &str, name: Option<&str>) -> Option<OsInfo> {
+    if let Some(captures) = WINDOWS_RE.captures(raw_description) {
+        let major = captures["major"].parse().ok()?;
+        let minor = captures["minor"].parse().ok()?;
+        let build = captures["build"].parse().ok()?;
+
+        return Some(OsInfo {
+            name: Some("Windows".to_string()),
+            version: get_windows_version(major, minor, build).map(str::to_string),
+            build: Some(captures["build"].to_string()),
+            kernel_version: Some(captures["kernel_version"].to_string()),
+        });
+    }
+
+    if let Some(captures) = UNITY_WINDOWS_RE.captures(raw_description) {
+        return Some(OsInfo {
+            name: Some("Windows".to_string()),
+            version: Some(captures["version"].to_string()),
+            build: None,
+            kernel_version: Some(captures["kernel_version"].to_string()),
+        });
+    }
+
+    if let Some(captures) = DARWIN_RE.captures(raw_description) {
+        let major = captures["major"].parse().ok()?;
+        let is_ios = name.map_or(false, |name| name.eq_ignore_ascii_case("ios"));
+
+        return Some(OsInfo {
+            name: Some(if is_ios { "iOS" } else { "macOS" }.to_string()),
+            version: if is_ios {
+                get_ios_version(major)
+            } else {
+                get_macos_version(major)
+            },
+            build: None,
+            kernel_version: Some(captures["kernel_version"].to_string()),
+        });
+    }
+
+    if let Some(captures) = MACOS_RE.captures(raw_description) {
+        return Some(OsInfo {
+            name: Some("macOS".to_string()),
+            version: Some(captures["version"].to_string()),
+            ..Default::default()
+        });
+    }
+
+    if let Some(captures) = APPLE_RE.captures(raw_description) {
+        return Some(OsInfo {
+            name: Some(captures["name"].to_string()),
+            version: Some(captures["version"].to_string()),
+            ..Default::default()
+        });
+    }
+
+    if let Some(captures) = ANDROID_RE.captures(raw_description) {
+        return Some(OsInfo {
+            name: Some("Android".to_string()),
+            version: Some(captures["version"].to_string()),
+            build: Some(captures["build"].to_string()),
+            kernel_version: None,
+        });
+    }
+
+    if let Some(captures) = LINUX_RE.captures(raw_description) {
+        return Some(OsInfo {
+            name: Some("Linux".to_string()),
+            kernel_version: Some(captures["kernel_version"].to_string()),
+            ..Default::default()
+        });
+    }
+
+    None
+}
+
+/// Parses a raw runtime description into name and version.
+fn parse_runtime(raw_description: &str) -> Option<(String, String)> {
+    let captures = RUNTIME_RE.captures(raw_description)?;
+    Some((
+        captures["name"].to_string(),
+        captures["version"].to_string(),
+    ))
+}
+
+fn normalize_os_context(os: &mut OsContext) {
+    let info = match get_str(&os.raw_description) {
+        Some(raw_description) => parse_os(raw_description, get_str(&os.name)),
+        None => return,
+    };
+
+    if let Some(info) = info {
+        fill_derived(&mut os.name, info.name, RAW_DESCRIPTION_RULE_ID);
+        fill_derived(&mut os.version, info.version, RAW_DESCRIPTION_RULE_ID);
+        fill_derived(&mut os.build, info.build, RAW_DESCRIPTION_RULE_ID);
+        fill_derived(
+            &mut os.kernel_version,
+            info.kernel_version,
+            RAW_DESCRIPTION_RULE_ID,
+        );
+    }
+}
+
+fn normalize_runtime_context(runtime: &mut RuntimeContext) {
+    let parsed = get_str(&runtime.raw_description).and_then(parse_runtime);
+    if let Some((name, version)) = parsed {
+        fill_derived(&mut runtime.name, Some(name), RAW_DESCRIPTION_RULE_ID);
+        fill_derived(&mut runtime.version, Some(version), RAW_DESCRIPTION_RULE_ID);
+    }
+}
+
+/// Derives structured OS and runtime information from raw descriptions.
+///
+/// Only missing fields are filled in. The raw description is retained.
+pub(crate) fn normalize_contexts(event: &mut Event) {
+    let contexts = event
+        .contexts
+        .value_mut()
+        .into_iter()
+        .flat_map(|c| c.values_mut());
+    for context in contexts.filter_map(Annotated::value_mut) {
+        match *context {
+            Context::Os(ref mut os) => normalize_os_context(os),
+            Context::Runtime(ref mut runtime) => normalize_runtime_context(runtime),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{Remark, RemarkType};
+
+    fn os(name: &str, version: Option<&str>, build: Option<&str>, kernel: Option<&str>) -> OsInfo {
+        OsInfo {
+            name: Some(name.to_string()),
+            version: version.map(str::to_string),
+            build: build.map(str::to_string),
+            kernel_version: kernel.map(str::to_string),
+        }
+    }
+
+    fn derived(value: &str) -> Annotated<Option<String>> {
+        let mut annotated = Annotated::from(Some(value.to_string()));
+        annotated.meta_mut().remarks_mut().push(Remark::new(
+            RemarkType::Substituted,
+            RAW_DESCRIPTION_RULE_ID,
+        ));
+        annotated
+    }
+
+    #[test]
+    fn test_windows() {
+        assert_eq_dbg!(
+            parse_os("Microsoft Windows NT 10.0.17134.0", None),
+            Some(os("Windows", Some("10"), Some("17134"), Some("10.0.17134")))
+        );
+        assert_eq_dbg!(
+            parse_os("Microsoft Windows NT 10.0.22000.0", None),
+            Some(os("Windows", Some("11"), Some("22000"), Some("10.0.22000")))
+        );
+        assert_eq_dbg!(
+            parse_os("Microsoft Windows NT 6.1.7601 Service Pack 1", None),
+            Some(os("Windows", Some("7"), Some("7601"), Some("6.1.7601")))
+        );
+        assert_eq_dbg!(
+            parse_os("Windows 10  (10.0.0) 64bit", None),
+            Some(os("Windows", Some("10"), None, Some("10.0.0")))
+        );
+    }
+
+    #[test]
+    fn test_darwin() {
+        let raw = "Darwin Kernel Version 17.7.0: Thu Jun 21 22:53:14 PDT 2018; root:xnu-4570.71.2~1/RELEASE_X86_64";
+        assert_eq_dbg!(
+            parse_os(raw, None),
+            Some(os("macOS", Some("10.13"), None, Some("17.7.0")))
+        );
+        assert_eq_dbg!(
+            parse_os("Darwin Kernel Version 20.1.0", None),
+            Some(os("macOS", Some("11"), None, Some("20.1.0")))
+        );
+        assert_eq_dbg!(
+            parse_os("Darwin Kernel Version 18.2.0", Some("iOS")),
+            Some(os("iOS", Some("12"), None, Some("18.2.0")))
+        );
+    }
+
+    #[test]
+    fn test_unity_apple_android() {
+        assert_eq_dbg!(
+            parse_os("Mac OS X 10.14.6", None),
+            Some(os("macOS", Some("10.14.6"), None, None))
+        );
+        assert_eq_dbg!(
+            parse_os("iOS 12.1", None),
+            Some(os("iOS", Some("12.1"), None, None))
+        );
+        assert_eq_dbg!(
+            parse_os(
+                "Android OS 9 / API-28 (PPR1.180610.011/G960FXXU2CRLI)",
+                None
+            ),
+            Some(os("Android", Some("9"), Some("PPR1.180610.011"), None))
+        );
+    }
+
+    #[test]
+    fn test_linux() {
+        assert_eq_dbg!(
+            parse_os("Linux 4.15.0-1044-aws #46-Ubuntu SMP", None),
+            Some(os("Linux", None, None, Some("4.15.0")))
+        );
+        assert_eq_dbg!(parse_os("FreeDOS 1.2", None), None);
+    }
+
+    #[test]
+    fn test_runtime() {
+        assert_eq_dbg!(
+            parse_runtime(".NET Framework 4.7.2"),
+            Some((".NET Framework".to_string(), "4.7.2".to_string()))
+        );
+        assert_eq_dbg!(
+            parse_runtime(".NET Core 4.6.27110.04"),
+            Some((".NET Core".to_string(), "4.6.27110.04".to_string()))
+        );
+        assert_eq_dbg!(
+            parse_runtime("Mono 5.12.0.226 (2018-02/6e5ab3f9b46 Thu Jun 21 08:31:02 EDT 2018)"),
+            Some(("Mono".to_string(), "5.12.0.226".to_string()))
+        );
+        assert_eq_dbg!(parse_runtime("CPython 3.7.1"), None);
+    }
+
+    #[test]
+    fn test_normalize_contexts() {
+        let mut event = Annotated::<Event>::from_json(
+            r#"{
+                "contexts": {
+                    "os": {"type": "os", "version": "10.0", "raw_description": "Microsoft Windows NT 10.0.17134.0"},
+                    "runtime": {"type": "runtime", "raw_description": ".NET Framework 4.7.2"}
+                }
+            }"#,
+        ).unwrap()
+        .0
+        .unwrap();
+
+        normalize_contexts(&mut event);
+        let contexts = event.contexts.value().unwrap();
+
+        match contexts["os"].value() {
+            Some(&Context::Os(ref os)) => {
+                assert_eq_dbg!(os.name, derived("Windows"));
+                assert_eq_dbg!(os.version, Annotated::from(Some("10.0".to_string())));
+                assert_eq_dbg!(os.build, derived("17134"));
+                assert_eq_dbg!(os.kernel_version, derived("10.0.17134"));
+                assert_eq_dbg!(
+                    os.raw_description,
+                    Annotated::from(Some("Microsoft Windows NT 10.0.17134.0".to_string()))
+                );
+            }
+            other => panic!("expected os context, got {:?}", other),
+        }
+
+        match contexts["runtime"].value() {
+            Some(&Context::Runtime(ref runtime)) => {
+                assert_eq_dbg!(runtime.name, derived(".NET Framework"));
+                assert_eq_dbg!(runtime.version, derived("4.7.2"));
+            }
+            other => panic!("expected runtime context, got {:?}", other),
+        }
+    }
+}
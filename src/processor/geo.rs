@@ -0,0 +1,239 @@
+//! Geographical enrichment of events from a local MaxMind database.
+use std::fmt;
+use std::net::{AddrParseError, IpAddr};
+use std::path::Path;
+
+use maxminddb::{self, geoip2, MaxMindDBError};
+
+use protocol::{Event, Geo, MetaError, Remark, RemarkType, Value};
+
+/// The rule id used for geo information derived from the IP address.
+const GEOIP_RULE_ID: &str = "@geoip";
+
+/// Placeholder IP address that instructs the server to infer the address.
+pub(crate) const AUTO_IP_ADDRESS: &str = "{{auto}}";
+
+/// An error returned when opening or querying a GeoIP database.
+#[derive(Debug, Fail)]
+pub enum GeoIpError {
+    /// The database could not be read.
+    #[fail(display = "could not open geoip database")]
+    Open(#[cause] MaxMindDBError),
+    /// The IP address is not valid.
+    #[fail(display = "invalid ip address")]
+    InvalidIp(#[cause] AddrParseError),
+    /// The database could not be queried.
+    #[fail(display = "geoip lookup failed: {}", _0)]
+    Lookup(#[cause] MaxMindDBError),
+}
+
+/// A GeoIP database in the MaxMind DB format, such as GeoIP2 City or GeoLite2 City.
+pub struct GeoIpLookup {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl GeoIpLookup {
+    /// Opens a database from a `.mmdb` file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, GeoIpError> {
+        let reader = maxminddb::Reader::open_readfile(path).map_err(GeoIpError::Open)?;
+        Ok(GeoIpLookup { reader })
+    }
+
+    /// Resolves an IP address into its geographical location.
+    ///
+    /// Returns `Ok(None)` if the database does not contain the address.
+    pub fn lookup(&self, ip_address: &str) -> Result<Option<Geo>, GeoIpError> {
+        let ip_address: IpAddr = ip_address.parse().map_err(GeoIpError::InvalidIp)?;
+
+        let city: geoip2::City = match self.reader.lookup(ip_address) {
+            Ok(city) => city,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return Ok(None),
+            Err(error) => return Err(GeoIpError::Lookup(error)),
+        };
+
+        let country_code = city
+            .country
+            .and_then(|country| country.iso_code)
+            .map(str::to_string);
+
+        let city_name = city
+            .city
+            .and_then(|city| city.names)
+            .and_then(|names| names.get("en").map(|name| name.to_string()));
+
+        let region = city
+            .subdivisions
+            .and_then(|subdivisions| subdivisions.into_iter().next())
+            .and_then(|subdivision| {
+                let iso_code = subdivision.iso_code;
+                subdivision
+                    .names
+                    .and_then(|names| names.get("en").map(|name| name.to_string()))
+                    .or_else(|| iso_code.map(str::to_string))
+            });
+
+        Ok(Some(Geo {
+            country_code: country_code.into(),
+            city: city_name.into(),
+            region: region.into(),
+            other: Default::default(),
+        }))
+    }
+}
+
+impl fmt::Debug for GeoIpLookup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GeoIpLookup")
+            .field("database_type", &self.reader.metadata.database_type)
+            .finish()
+    }
+}
+
+/// Returns the IP address of the user, falling back to `REMOTE_ADDR` of the request.
+fn get_ip_address(event: &Event) -> Option<&str> {
+    let user_ip = event
+        .user
+        .value()
+        .and_then(Option::as_ref)
+        .and_then(|user| user.ip_address.value())
+        .and_then(Option::as_ref)
+        .map(String::as_str)
+        .filter(|ip| *ip != AUTO_IP_ADDRESS);
+
+    user_ip.or_else(|| {
+        let request = event.request.value()?.as_ref()?;
+        match request.env.value()?.get("REMOTE_ADDR")?.value() {
+            Some(&Value::String(ref ip)) => Some(ip.as_str()),
+            _ => None,
+        }
+    })
+}
+
+/// Fills the event's geo information from its IP address.
+///
+/// Explicit geo information is never overwritten. Errors are recorded in the meta data of the
+/// geo field. This must run before PII stripping, which may remove the IP address.
+pub(crate) fn normalize_geo(event: &mut Event, lookup: &GeoIpLookup) {
+    if event.geo.value().map_or(true, Option::is_some) {
+        return;
+    }
+
+    let result = match get_ip_address(event) {
+        Some(ip_address) => lookup.lookup(ip_address),
+        None => return,
+    };
+
+    match result {
+        Ok(Some(geo)) => {
+            event.geo.set_value(Some(Some(geo)));
+            event
+                .geo
+                .meta_mut()
+                .remarks_mut()
+                .push(Remark::new(RemarkType::Substituted, GEOIP_RULE_ID));
+        }
+        Ok(None) => (),
+        Err(error) => event
+            .geo
+            .meta_mut()
+            .errors_mut()
+            .push(MetaError::invalid(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{Annotated, Meta};
+    use testutils::event;
+
+    fn lookup() -> GeoIpLookup {
+        GeoIpLookup::open(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/marshal-geo-test.mmdb"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let geo = lookup().lookup("89.160.20.112").unwrap().unwrap();
+        assert_eq_dbg!(geo.country_code, Annotated::from(Some("SE".to_string())));
+        assert_eq_dbg!(geo.city, Annotated::from(Some("Linköping".to_string())));
+        assert_eq_dbg!(
+            geo.region,
+            Annotated::from(Some("Östergötland County".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_lookup_partial() {
+        let geo = lookup().lookup("2.125.160.216").unwrap().unwrap();
+        assert_eq_dbg!(geo.country_code, Annotated::from(Some("GB".to_string())));
+        assert_eq_dbg!(geo.city, Annotated::from(None));
+        assert_eq_dbg!(geo.region, Annotated::from(None));
+    }
+
+    #[test]
+    fn test_lookup_not_found() {
+        assert_eq_dbg!(lookup().lookup("127.0.0.1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_missing() {
+        assert!(GeoIpLookup::open("/does/not/exist.mmdb").is_err());
+    }
+
+    #[test]
+    fn test_user_ip() {
+        let mut event = event(r#"{"user": {"ip_address": "81.2.69.142"}}"#);
+        normalize_geo(&mut event, &lookup());
+
+        let geo = event.geo.value().unwrap().as_ref().unwrap();
+        assert_eq_dbg!(geo.city, Annotated::from(Some("London".to_string())));
+        assert_eq_dbg!(geo.region, Annotated::from(Some("England".to_string())));
+        assert_eq_str!(
+            event.geo.meta().remarks().next().unwrap().rule_id(),
+            GEOIP_RULE_ID
+        );
+    }
+
+    #[test]
+    fn test_remote_addr() {
+        let mut event = event(
+            r#"{
+                "user": {"ip_address": "{{auto}}"},
+                "request": {"env": {"REMOTE_ADDR": "89.160.20.112"}}
+            }"#,
+        );
+        normalize_geo(&mut event, &lookup());
+
+        let geo = event.geo.value().unwrap().as_ref().unwrap();
+        assert_eq_dbg!(geo.country_code, Annotated::from(Some("SE".to_string())));
+    }
+
+    #[test]
+    fn test_keeps_explicit_geo() {
+        let mut event = event(
+            r#"{
+                "user": {"ip_address": "81.2.69.142"},
+                "geo": {"country_code": "AT"}
+            }"#,
+        );
+        normalize_geo(&mut event, &lookup());
+
+        let geo = event.geo.value().unwrap().as_ref().unwrap();
+        assert_eq_dbg!(geo.country_code, Annotated::from(Some("AT".to_string())));
+        assert_eq_dbg!(geo.city, Annotated::from(None));
+    }
+
+    #[test]
+    fn test_invalid_ip() {
+        let mut event = event(r#"{"user": {"ip_address": "not an ip"}}"#);
+        normalize_geo(&mut event, &lookup());
+        assert_eq_dbg!(
+            event.geo,
+            Annotated::new(None, Meta::from_error(MetaError::invalid("invalid ip address")))
+        );
+    }
+}
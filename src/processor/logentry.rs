@@ -0,0 +1,699 @@
+//! Formatting of log entry messages.
+//!
+//! Log messages can use one of two placeholder styles:
+//!
+//! - printf style as used by C, Python and many other languages: `%s`, `%d`, `%.2f` as well as
+//!   Python's named `%(name)s` placeholders.
+//! - Brace style as used by .NET and Python's `str.format`: `{0}`, `{}` and `{name}`. Alignment
+//!   (`{0,-10}`) is supported, format specifiers after a colon are ignored.
+//!
+//! Named placeholders are looked up in the parameters if they consist of a single object. In
+//! brace style, named placeholders are otherwise bound to parameters in order of appearance like
+//! in structured logging libraries. Brace style is assumed if the message contains brace
+//! placeholders but no printf placeholders.
+use regex::{Captures, Regex};
+use serde_json;
+
+use processor::{PiiKind, ProcessAnnotatedValue, Processor, ValueInfo};
+use protocol::{Annotated, LogEntry, Map, MetaError, Value};
+
+use super::utils::is_missing;
+
+/// Placeholder for parameters that have been removed, e.g. during PII stripping.
+const FILTERED_PARAM: &str = "[Filtered]";
+
+/// Maximum width, precision and alignment of a placeholder.
+///
+/// Messages are untrusted, so larger values are clamped to avoid excessive allocations.
+const MAX_WIDTH: usize = 1024;
+
+lazy_static! {
+    static ref PRINTF_RE: Regex = Regex::new(
+        r"(?x)
+        %
+        (?:\((?P<name>[^)]*)\))?
+        (?P<flags>[-+\ 0\#]*)
+        (?P<width>\d*)
+        (?:\.(?P<precision>\d*))?
+        (?:hh|h|ll|l|L|q|j|z|t)?
+        (?P<conversion>[diouxXeEfFgGcrsa@%])
+    "
+    )
+    .unwrap();
+    static ref BRACE_RE: Regex = Regex::new(
+        r"(?x)
+        \{\{ | \}\} |
+        \{
+            (?P<key>[^{}:,]*)
+            (?:,\s*(?P<alignment>-?\d+))?
+            (?::[^{}]*)?
+        \}
+    "
+    )
+    .unwrap();
+}
+
+/// An error used when formatting a log message.
+#[derive(Debug, Fail, Clone, PartialEq, Eq)]
+pub enum FormatLogEntryError {
+    /// A placeholder refers to a parameter that does not exist.
+    #[fail(display = "missing parameter {}", _0)]
+    MissingParam(String),
+    /// The message contains a malformed placeholder.
+    #[fail(display = "invalid placeholder at offset {}", _0)]
+    InvalidPlaceholder(usize),
+    /// A parameter cannot be formatted with the requested conversion.
+    #[fail(display = "cannot format parameter as '%{}'", _0)]
+    InvalidConversion(char),
+}
+
+/// Lookup of positional and named parameters.
+struct Params<'a> {
+    positional: &'a [Annotated<Value>],
+    named: Option<&'a Map<Value>>,
+    next: usize,
+}
+
+impl<'a> Params<'a> {
+    fn new(params: &'a [Annotated<Value>]) -> Self {
+        let named = match params.first().and_then(Annotated::value) {
+            Some(&Value::Map(ref map)) if params.len() == 1 => Some(map),
+            _ => None,
+        };
+
+        Params {
+            positional: params,
+            named,
+            next: 0,
+        }
+    }
+
+    fn next(&mut self) -> Result<&'a Annotated<Value>, FormatLogEntryError> {
+        let index = self.next;
+        self.next += 1;
+        self.index(index)
+    }
+
+    fn index(&self, index: usize) -> Result<&'a Annotated<Value>, FormatLogEntryError> {
+        self.positional
+            .get(index)
+            .ok_or_else(|| FormatLogEntryError::MissingParam(index.to_string()))
+    }
+
+    fn named(&self, name: &str) -> Result<&'a Annotated<Value>, FormatLogEntryError> {
+        self.named
+            .and_then(|map| map.get(name))
+            .ok_or_else(|| FormatLogEntryError::MissingParam(name.to_string()))
+    }
+}
+
+/// Parses a width or precision and clamps it to `MAX_WIDTH`.
+///
+/// Numbers that are too large for `usize` are clamped as well. An empty string yields zero.
+fn parse_width(string: &str) -> usize {
+    if string.is_empty() {
+        return 0;
+    }
+
+    string.parse().unwrap_or(MAX_WIDTH).min(MAX_WIDTH)
+}
+
+/// Formatting options of a single placeholder.
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn from_captures(captures: &Captures) -> Self {
+        let flags = captures.name("flags").map_or("", |m| m.as_str());
+        Spec {
+            left: flags.contains('-'),
+            zero: flags.contains('0'),
+            plus: flags.contains('+'),
+            space: flags.contains(' '),
+            alternate: flags.contains('#'),
+            width: captures
+                .name("width")
+                .map_or(0, |m| parse_width(m.as_str())),
+            precision: captures.name("precision").map(|m| parse_width(m.as_str())),
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    /// Pads the value to the configured width.
+    ///
+    /// Zero padding is only applied to numbers and inserted between the prefix and the body.
+    fn pad(&self, prefix: &str, body: &str, numeric: bool) -> String {
+        let len = prefix.chars().count() + body.chars().count();
+        if len >= self.width {
+            return format!("{}{}", prefix, body);
+        }
+
+        let fill = self.width - len;
+        if self.left {
+            format!("{}{}{}", prefix, body, " ".repeat(fill))
+        } else if self.zero && numeric {
+            format!("{}{}{}", prefix, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), prefix, body)
+        }
+    }
+}
+
+/// Renders a parameter as plain string.
+fn value_to_string(value: &Annotated<Value>) -> String {
+    match value.value() {
+        None => FILTERED_PARAM.to_string(),
+        Some(&Value::Null) => "null".to_string(),
+        Some(&Value::Bool(b)) => b.to_string(),
+        Some(&Value::U64(u)) => u.to_string(),
+        Some(&Value::I64(i)) => i.to_string(),
+        Some(&Value::F64(f)) => f.to_string(),
+        Some(&Value::String(ref s)) => s.clone(),
+        Some(value) => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// Converts a parameter into an integer, returned as sign and magnitude.
+fn value_to_int(value: &Annotated<Value>) -> Option<(bool, u64)> {
+    match value.value() {
+        Some(&Value::Bool(b)) => Some((false, b as u64)),
+        Some(&Value::U64(u)) => Some((false, u)),
+        Some(&Value::I64(i)) => Some((i < 0, i.wrapping_abs() as u64)),
+        Some(&Value::F64(f)) if f.is_finite() => Some((f < 0.0, f.abs().trunc() as u64)),
+        _ => None,
+    }
+}
+
+/// Converts a parameter into a float.
+fn value_to_float(value: &Annotated<Value>) -> Option<f64> {
+    match value.value() {
+        Some(&Value::Bool(b)) => Some(if b { 1.0 } else { 0.0 }),
+        Some(&Value::U64(u)) => Some(u as f64),
+        Some(&Value::I64(i)) => Some(i as f64),
+        Some(&Value::F64(f)) => Some(f),
+        _ => None,
+    }
+}
+
+/// Formats a positive float in scientific notation with a sign and at least two exponent digits.
+fn format_exponent(f: f64, precision: usize, upper: bool) -> String {
+    let formatted = format!("{:.*e}", precision, f);
+    let (mantissa, exponent) = formatted.split_at(formatted.find('e').unwrap_or(0));
+    let exponent: i32 = exponent[1..].parse().unwrap_or(0);
+
+    format!(
+        "{}{}{}{:02}",
+        mantissa,
+        if upper { 'E' } else { 'e' },
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+/// Formats a positive float using either fixed or scientific notation depending on its exponent.
+fn format_general(f: f64, precision: usize, upper: bool, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let exponent = if f == 0.0 {
+        0
+    } else {
+        let formatted = format!("{:.*e}", precision - 1, f);
+        formatted[formatted.find('e').unwrap_or(0) + 1..]
+            .parse()
+            .unwrap_or(0)
+    };
+
+    let formatted = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(f, precision - 1, upper)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, f)
+    };
+
+    if alternate || !formatted.contains('.') {
+        return formatted;
+    }
+
+    let split = formatted
+        .find(|c| c == 'e' || c == 'E')
+        .unwrap_or(formatted.len());
+    let (mantissa, exponent) = formatted.split_at(split);
+    let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", mantissa, exponent)
+}
+
+/// Formats a single parameter for a printf placeholder.
+fn format_printf_value(
+    value: &Annotated<Value>,
+    conversion: char,
+    spec: &Spec,
+) -> Result<String, FormatLogEntryError> {
+    let invalid = || FormatLogEntryError::InvalidConversion(conversion);
+
+    Ok(match conversion {
+        's' | 'r' | 'a' | '@' => {
+            let string = value_to_string(value);
+            let string = match spec.precision {
+                Some(precision) => string.chars().take(precision).collect(),
+                None => string,
+            };
+            spec.pad("", &string, false)
+        }
+        'd' | 'i' | 'u' => {
+            let (negative, magnitude) = value_to_int(value).ok_or_else(invalid)?;
+            spec.pad(spec.sign(negative), &magnitude.to_string(), true)
+        }
+        'x' | 'X' | 'o' => {
+            let (negative, magnitude) = value_to_int(value).ok_or_else(invalid)?;
+            let (body, prefix) = match conversion {
+                'x' => (format!("{:x}", magnitude), "0x"),
+                'X' => (format!("{:X}", magnitude), "0X"),
+                _ => (format!("{:o}", magnitude), "0o"),
+            };
+            let mut full_prefix = spec.sign(negative).to_string();
+            if spec.alternate {
+                full_prefix.push_str(prefix);
+            }
+            spec.pad(&full_prefix, &body, true)
+        }
+        'c' => {
+            let c = match value.value() {
+                Some(&Value::String(ref s)) if s.chars().count() == 1 => s.clone(),
+                _ => value_to_int(value)
+                    .and_then(|(negative, magnitude)| {
+                        if negative || magnitude > u64::from(u32::max_value()) {
+                            None
+                        } else {
+                            ::std::char::from_u32(magnitude as u32)
+                        }
+                    })
+                    .ok_or_else(invalid)?
+                    .to_string(),
+            };
+            spec.pad("", &c, false)
+        }
+        _ => {
+            let f = value_to_float(value).ok_or_else(invalid)?;
+            let upper = conversion.is_uppercase();
+            let precision = spec.precision.unwrap_or(6);
+
+            let body = if f.is_nan() {
+                "nan".to_string()
+            } else if f.is_infinite() {
+                "inf".to_string()
+            } else {
+                match conversion {
+                    'e' | 'E' => format_exponent(f.abs(), precision, upper),
+                    'g' | 'G' => format_general(f.abs(), precision, upper, spec.alternate),
+                    _ => format!("{:.*}", precision, f.abs()),
+                }
+            };
+
+            let body = if upper { body.to_uppercase() } else { body };
+            spec.pad(spec.sign(f < 0.0), &body, f.is_finite())
+        }
+    })
+}
+
+/// Appends literal text and fails if it contains an unmatched special character.
+fn push_literal(
+    rv: &mut String,
+    literal: &str,
+    offset: usize,
+    special: &[char],
+) -> Result<(), FormatLogEntryError> {
+    if let Some(pos) = literal.find(special) {
+        return Err(FormatLogEntryError::InvalidPlaceholder(offset + pos));
+    }
+
+    rv.push_str(literal);
+    Ok(())
+}
+
+fn format_printf(message: &str, params: &mut Params) -> Result<String, FormatLogEntryError> {
+    let mut rv = String::with_capacity(message.len());
+    let mut last = 0;
+
+    for captures in PRINTF_RE.captures_iter(message) {
+        let placeholder = captures.get(0).unwrap();
+        push_literal(&mut rv, &message[last..placeholder.start()], last, &['%'])?;
+        last = placeholder.end();
+
+        let conversion = captures["conversion"].chars().next().unwrap_or('s');
+        if conversion == '%' {
+            rv.push('%');
+            continue;
+        }
+
+        let param = match captures.name("name") {
+            Some(name) => params.named(name.as_str())?,
+            None => params.next()?,
+        };
+
+        let spec = Spec::from_captures(&captures);
+        rv.push_str(&format_printf_value(param, conversion, &spec)?);
+    }
+
+    push_literal(&mut rv, &message[last..], last, &['%'])?;
+    Ok(rv)
+}
+
+fn format_braces(message: &str, params: &mut Params) -> Result<String, FormatLogEntryError> {
+    let mut rv = String::with_capacity(message.len());
+    let mut last = 0;
+
+    for captures in BRACE_RE.captures_iter(message) {
+        let placeholder = captures.get(0).unwrap();
+        push_literal(
+            &mut rv,
+            &message[last..placeholder.start()],
+            last,
+            &['{', '}'],
+        )?;
+        last = placeholder.end();
+
+        // Escaped braces (`{{` and `}}`) do not have a key.
+        let key = match captures.name("key") {
+            Some(key) => key
+                .as_str()
+                .trim()
+                .trim_start_matches(|c| c == '@' || c == '$'),
+            None => {
+                rv.push_str(&placeholder.as_str()[..1]);
+                continue;
+            }
+        };
+
+        let param = if key.is_empty() {
+            params.next()?
+        } else if let Ok(index) = key.parse() {
+            params.index(index)?
+        } else if params.named.is_some() {
+            params.named(key)?
+        } else {
+            params.next()?
+        };
+
+        let alignment = captures.name("alignment").map_or("", |m| m.as_str());
+        let spec = Spec {
+            left: alignment.starts_with('-'),
+            width: parse_width(alignment.trim_start_matches('-')),
+            ..Default::default()
+        };
+
+        rv.push_str(&spec.pad("", &value_to_string(param), false));
+    }
+
+    push_literal(&mut rv, &message[last..], last, &['{', '}'])?;
+    Ok(rv)
+}
+
+fn is_brace_style(message: &str) -> bool {
+    let has_printf = PRINTF_RE
+        .captures_iter(message)
+        .any(|captures| &captures["conversion"] != "%");
+
+    !has_printf
+        && BRACE_RE
+            .captures_iter(message)
+            .any(|captures| captures.name("key").is_some())
+}
+
+/// Interpolates parameters into a log message.
+///
+/// The placeholder style is detected from the message. If there are no parameters, the message
+/// is returned verbatim.
+pub fn format_message(
+    message: &str,
+    params: &[Annotated<Value>],
+) -> Result<String, FormatLogEntryError> {
+    if params.is_empty() {
+        return Ok(message.to_string());
+    }
+
+    let mut params = Params::new(params);
+    if is_brace_style(message) {
+        format_braces(message, &mut params)
+    } else {
+        format_printf(message, &mut params)
+    }
+}
+
+/// Renders the message of a log entry into its `formatted` field.
+///
+/// A formatted message sent by the client is retained. If the message cannot be formatted, the
+/// error is recorded in the meta data of `formatted` and the message remains untouched.
+///
+/// Parameters are scrubbed with the given processor before they are interpolated, so that PII
+/// does not leak into the formatted message. Removed parameters are rendered as `[Filtered]`.
+/// The parameters stored in the log entry are not modified.
+pub fn format_logentry(logentry: &mut LogEntry, processor: &Processor) {
+    let formatted = &mut logentry.formatted;
+    if !is_missing(formatted) {
+        return;
+    }
+
+    let message = match logentry.message.value() {
+        Some(message) => message,
+        None => return,
+    };
+
+    let info = ValueInfo {
+        pii_kind: Some(PiiKind::Databag),
+        cap: None,
+    };
+    let params =
+        ProcessAnnotatedValue::process_annotated_value(logentry.params.clone(), processor, &info);
+    let result = format_message(message, params.value().map_or(&[][..], Vec::as_slice));
+
+    match result {
+        Ok(message) => formatted.set_value(Some(Some(message))),
+        Err(error) => formatted
+            .meta_mut()
+            .errors_mut()
+            .push(MetaError::invalid(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::PiiProcessor;
+    use protocol::{Meta, Remark, RemarkType};
+
+    struct NoopProcessor;
+
+    impl Processor for NoopProcessor {}
+
+    fn format(message: &str, params: &str) -> Result<String, FormatLogEntryError> {
+        let json = format!(r#"{{"message": "", "params": {}}}"#, params);
+        let params = logentry(&json).params.0.unwrap();
+        format_message(message, &params)
+    }
+
+    fn logentry(json: &str) -> LogEntry {
+        Annotated::<LogEntry>::from_json(json).unwrap().0.unwrap()
+    }
+
+    #[test]
+    fn test_printf() {
+        assert_eq_str!(
+            format("Hello, %s! You are %d.", r#"["Peter", 42]"#).unwrap(),
+            "Hello, Peter! You are 42."
+        );
+        assert_eq_str!(
+            format("%5.1f%% of %-4s|", r#"[12.345, "ab"]"#).unwrap(),
+            " 12.3% of ab  |"
+        );
+        assert_eq_str!(
+            format("%05d %+d %#x %o", "[-42, 7, 255, 8]").unwrap(),
+            "-0042 +7 0xff 10"
+        );
+        assert_eq_str!(
+            format("%e %g %G %c", r#"[1234.5, 0.0001, 1e20, 65]"#).unwrap(),
+            "1.234500e+03 0.0001 1E+20 A"
+        );
+        assert_eq_str!(format("%@ %lu", r#"["obj", 1]"#).unwrap(), "obj 1");
+    }
+
+    #[test]
+    fn test_printf_named() {
+        assert_eq_str!(
+            format(
+                "%(user)s has %(count)d items",
+                r#"[{"user": "Peter", "count": 3}]"#
+            )
+            .unwrap(),
+            "Peter has 3 items"
+        );
+    }
+
+    #[test]
+    fn test_braces() {
+        assert_eq_str!(
+            format("{1} before {0}", r#"["a", "b"]"#).unwrap(),
+            "b before a"
+        );
+        assert_eq_str!(format("{} and {}", "[1, 2]").unwrap(), "1 and 2");
+        assert_eq_str!(
+            format("[{0,5}|{1,-5}] {{literal}}", r#"["ab", "cd"]"#).unwrap(),
+            "[   ab|cd   ] {literal}"
+        );
+        assert_eq_str!(format("{0:N2}", "[1.5]").unwrap(), "1.5");
+    }
+
+    #[test]
+    fn test_braces_named() {
+        assert_eq_str!(
+            format("User {user} logged in", r#"[{"user": "Peter"}]"#).unwrap(),
+            "User Peter logged in"
+        );
+        assert_eq_str!(
+            format("User {UserId} bought {@Item}", r#"[42, {"sku": "A1"}]"#).unwrap(),
+            r#"User 42 bought {"sku":"A1"}"#
+        );
+    }
+
+    #[test]
+    fn test_huge_width() {
+        let right = format!("{}a|", " ".repeat(MAX_WIDTH - 1));
+        let left = format!("a{}|", " ".repeat(MAX_WIDTH - 1));
+
+        assert_eq_str!(format("%999999999999s|", r#"["a"]"#).unwrap(), right);
+        assert_eq_str!(
+            format("%99999999999999999999999s|", r#"["a"]"#).unwrap(),
+            right
+        );
+        assert_eq_str!(format("{0,999999999999}|", r#"["a"]"#).unwrap(), right);
+        assert_eq_str!(
+            format("{0,-9223372036854775808}|", r#"["a"]"#).unwrap(),
+            left
+        );
+        assert_eq!(
+            format("%.999999999f", "[1.5]").unwrap().len(),
+            "1.".len() + MAX_WIDTH
+        );
+    }
+
+    #[test]
+    fn test_no_params() {
+        assert_eq_str!(format("100% {0}", "[]").unwrap(), "100% {0}");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq_dbg!(
+            format("%s %s", r#"["a"]"#),
+            Err(FormatLogEntryError::MissingParam("1".to_string()))
+        );
+        assert_eq_dbg!(
+            format("%(user)s", r#"["a"]"#),
+            Err(FormatLogEntryError::MissingParam("user".to_string()))
+        );
+        assert_eq_dbg!(
+            format("%d", r#"["a"]"#),
+            Err(FormatLogEntryError::InvalidConversion('d'))
+        );
+        assert_eq_dbg!(
+            format("50%", r#"["a"]"#),
+            Err(FormatLogEntryError::InvalidPlaceholder(2))
+        );
+        assert_eq_dbg!(
+            format("{0} }", r#"["a"]"#),
+            Err(FormatLogEntryError::InvalidPlaceholder(4))
+        );
+    }
+
+    #[test]
+    fn test_format_logentry() {
+        let mut entry = logentry(r#"{"message": "Hello, %s!", "params": ["World"]}"#);
+        format_logentry(&mut entry, &NoopProcessor);
+        assert_eq_dbg!(
+            entry.formatted,
+            Annotated::from(Some("Hello, World!".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_format_logentry_keeps_formatted() {
+        let mut entry = logentry(
+            r#"{"message": "Hello, %s!", "params": ["World"], "formatted": "Hello, client!"}"#,
+        );
+        format_logentry(&mut entry, &NoopProcessor);
+        assert_eq_dbg!(
+            entry.formatted,
+            Annotated::from(Some("Hello, client!".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_format_logentry_error() {
+        let mut entry = logentry(r#"{"message": "Hello, %s and %s!", "params": ["World"]}"#);
+        format_logentry(&mut entry, &NoopProcessor);
+        assert_eq_dbg!(
+            entry.formatted,
+            Annotated::new(
+                None,
+                Meta::from_error(MetaError::invalid("missing parameter 1"))
+            )
+        );
+        assert_eq_dbg!(
+            entry.message,
+            Annotated::from("Hello, %s and %s!".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_scrubbed_params() {
+        struct EmailProcessor;
+
+        impl PiiProcessor for EmailProcessor {
+            fn pii_process_value(
+                &self,
+                value: Annotated<Value>,
+                kind: PiiKind,
+            ) -> Annotated<Value> {
+                match (value, kind) {
+                    (Annotated(Some(Value::String(ref s)), _), PiiKind::Databag)
+                        if s.contains('@') =>
+                    {
+                        Annotated::from(Value::String("[email]".to_string()))
+                    }
+                    (Annotated(Some(Value::U64(_)), meta), PiiKind::Databag) => {
+                        Annotated(Some(Value::Null), meta)
+                            .with_removed_value(Remark::new(RemarkType::Removed, "@remove"))
+                    }
+                    (value, _) => value,
+                }
+            }
+        }
+
+        let mut entry =
+            logentry(r#"{"message": "Mail from %s for %s", "params": ["foo@example.com", 42]}"#);
+        let params = entry.params.clone();
+
+        format_logentry(&mut entry, &EmailProcessor);
+        assert_eq_dbg!(
+            entry.formatted,
+            Annotated::from(Some("Mail from [email] for [Filtered]".to_string()))
+        );
+        assert_eq_dbg!(entry.params, params);
+    }
+}
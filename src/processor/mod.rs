@@ -1,11 +1,31 @@
 //! Implements a processing system for the protocol.
 
 mod builtin;
+mod contexts;
+mod geo;
+mod logentry;
+mod normalize;
 mod pii;
+mod request;
 mod rule;
+mod select;
+mod stacktrace;
+mod tags;
+mod trim;
+mod useragent;
+mod utils;
+mod visit;
 
 pub mod chunks;
 
 pub use self::builtin::BUILTIN_RULES;
+pub use self::geo::{GeoIpError, GeoIpLookup};
+pub use self::logentry::*;
+pub use self::normalize::*;
 pub use self::pii::*;
 pub use self::rule::*;
+pub use self::select::*;
+pub use self::trim::TrimTarget;
+pub use self::visit::*;
+
+pub(crate) use self::stacktrace::for_each_stacktrace;
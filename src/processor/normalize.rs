@@ -0,0 +1,880 @@
+//! Normalization of events after parsing.
+use std::collections::BTreeMap;
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use protocol::{
+    datetime_to_value, Annotated, ErrorKind, Event, Frame, Level, LogEntry, Meta, Remark,
+    RemarkType, Stacktrace,
+};
+
+use super::geo::{self, GeoIpLookup};
+use super::logentry::format_logentry;
+use super::pii::Processor;
+use super::trim::{self, TrimTarget};
+use super::utils::is_missing;
+use super::{contexts, request, stacktrace, tags, useragent};
+
+/// The rule id used for remarks emitted by the normalization processor.
+const NORMALIZE_RULE_ID: &str = "@normalize";
+
+/// The rule id used for timestamps shifted by clock drift correction.
+const CLOCK_DRIFT_RULE_ID: &str = "@clock_drift";
+
+/// The rule id used for timestamps that were clamped to the received time.
+const TIMESTAMP_RULE_ID: &str = "@timestamp";
+
+/// A processor that leaves values untouched.
+///
+/// Log entry parameters are interpolated without scrubbing during normalization. PII stripping
+/// runs afterwards and processes the formatted message as well.
+struct NoopProcessor;
+
+impl Processor for NoopProcessor {}
+
+/// A source for the current time.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A clock that returns the current system time.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A fixed point in time can be used as a clock that never advances.
+impl Clock for DateTime<Utc> {
+    fn now(&self) -> DateTime<Utc> {
+        *self
+    }
+}
+
+/// Configuration for event normalization.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct NormalizeConfig {
+    /// The minimum drift between the client's and the server's clock that is corrected.
+    pub min_clock_drift_secs: u64,
+    /// The maximum number of seconds a timestamp may lie in the future of the received time.
+    pub max_secs_in_future: u64,
+    /// The maximum number of seconds a timestamp may lie in the past of the received time.
+    pub max_secs_in_past: u64,
+    /// Module prefixes of frames that are always considered in-app.
+    pub in_app_include: Vec<String>,
+    /// Module prefixes of frames that are never considered in-app.
+    pub in_app_exclude: Vec<String>,
+    /// The maximum number of frames per stacktrace for platforms without a specific limit.
+    pub max_frames: usize,
+    /// The maximum number of frames per stacktrace by platform.
+    pub max_frames_per_platform: BTreeMap<String, usize>,
+    /// The maximum number of frames across all stacktraces of an event.
+    pub max_total_frames: usize,
+    /// Whether to fill the user's IP address from `REMOTE_ADDR` or `X-Forwarded-For`.
+    pub infer_ip_address: bool,
+    /// The maximum estimated size of an event in bytes of JSON, including meta data.
+    ///
+    /// Larger events are trimmed in the order of `trim_order`. If `None`, events are not trimmed.
+    pub max_event_size: Option<usize>,
+    /// The order in which parts of an event are trimmed to fit into `max_event_size`.
+    pub trim_order: Vec<TrimTarget>,
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> Self {
+        NormalizeConfig {
+            min_clock_drift_secs: 55 * 60,
+            max_secs_in_future: 60,
+            max_secs_in_past: 30 * 24 * 3600,
+            in_app_include: Vec::new(),
+            in_app_exclude: Vec::new(),
+            max_frames: 250,
+            max_frames_per_platform: BTreeMap::new(),
+            max_total_frames: 1000,
+            infer_ip_address: false,
+            max_event_size: None,
+            trim_order: TrimTarget::default_order(),
+        }
+    }
+}
+
+/// A processor that fills in defaults and derives fields of a parsed event.
+///
+/// Every change made to the event is recorded as a remark on the affected field.
+#[derive(Debug, Default)]
+pub struct NormalizeProcessor<C = SystemClock> {
+    clock: C,
+    config: NormalizeConfig,
+    sent_at: Option<DateTime<Utc>>,
+    geoip: Option<GeoIpLookup>,
+}
+
+impl NormalizeProcessor<SystemClock> {
+    /// Creates a new normalization processor using the system clock.
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl<C: Clock> NormalizeProcessor<C> {
+    /// Creates a new normalization processor using the given clock.
+    pub fn with_clock(clock: C) -> Self {
+        NormalizeProcessor {
+            clock,
+            config: Default::default(),
+            sent_at: None,
+            geoip: None,
+        }
+    }
+
+    /// Returns a reference to the config of the processor.
+    pub fn config(&self) -> &NormalizeConfig {
+        &self.config
+    }
+
+    /// Replaces the config of the processor.
+    pub fn set_config(&mut self, config: NormalizeConfig) {
+        self.config = config;
+    }
+
+    /// Sets the time at which the client sent the event.
+    ///
+    /// This is usually taken from `Auth::timestamp`. If set, the difference to the received time
+    /// is considered clock drift and all timestamps in the event are corrected accordingly.
+    pub fn set_sent_at(&mut self, sent_at: Option<DateTime<Utc>>) {
+        self.sent_at = sent_at;
+    }
+
+    /// Sets the GeoIP database used to derive the location of the user.
+    ///
+    /// If set, the user's IP address is resolved into `Event::geo`. Since PII stripping may
+    /// remove IP addresses, normalization must run before PII stripping.
+    pub fn set_geoip(&mut self, geoip: Option<GeoIpLookup>) {
+        self.geoip = geoip;
+    }
+
+    /// Normalizes an event.
+    pub fn process_event(&self, mut event: Annotated<Event>) -> Annotated<Event> {
+        if let Some(event) = event.value_mut() {
+            self.normalize_event(event);
+        }
+
+        if let Some(max_event_size) = self.config.max_event_size {
+            trim::trim_event(&mut event, max_event_size, &self.config.trim_order);
+        }
+
+        event
+    }
+
+    fn normalize_event(&self, event: &mut Event) {
+        let now = self.clock.now();
+
+        fill_default(&mut event.id, Uuid::new_v4);
+        set_value(&mut event.received, now);
+        self.normalize_timestamps(event, now);
+        fill_default(&mut event.timestamp, || now);
+        tags::normalize_tags(event);
+        fill_default(&mut event.level, || Level::Error);
+
+        if is_missing(&event.logentry) {
+            if let Some(Some(message)) = event.message.value().cloned() {
+                fill_default(&mut event.logentry, || LogEntry {
+                    message: message.into(),
+                    params: Default::default(),
+                    formatted: Default::default(),
+                    other: Default::default(),
+                });
+            }
+        }
+
+        if let Some(logentry) = event.logentry.value_mut().and_then(Option::as_mut) {
+            let was_missing = is_missing(&logentry.formatted);
+            format_logentry(logentry, &NoopProcessor);
+            if was_missing && !is_missing(&logentry.formatted) {
+                logentry
+                    .formatted
+                    .meta_mut()
+                    .remarks_mut()
+                    .push(remark(RemarkType::Substituted));
+            }
+        }
+
+        let platform = event.platform.value().cloned();
+        stacktrace::for_each_stacktrace(event, |stacktrace| {
+            stacktrace::normalize_in_app(
+                stacktrace,
+                platform.as_ref().map(String::as_str),
+                &self.config.in_app_include,
+                &self.config.in_app_exclude,
+            );
+        });
+
+        self.trim_stacktraces(event);
+
+        if is_missing(&event.culprit) {
+            let platform = event.platform.value().map(String::as_str);
+            if let Some(culprit) =
+                compute_culprit(event).map(|frame| format_culprit(frame, platform))
+            {
+                fill_default(&mut event.culprit, || culprit);
+            }
+        }
+
+        if let Some(request) = event.request.value_mut().and_then(Option::as_mut) {
+            request::normalize_request(request);
+        }
+
+        if self.config.infer_ip_address {
+            request::infer_ip_address(event);
+        }
+
+        if let Some(ref geoip) = self.geoip {
+            geo::normalize_geo(event, geoip);
+        }
+
+        contexts::normalize_contexts(event);
+        useragent::normalize_user_agent(event);
+
+        normalize_string(&mut event.environment);
+        normalize_string(&mut event.release);
+    }
+
+    /// Limits the number of frames in all stacktraces of the event.
+    fn trim_stacktraces(&self, event: &mut Event) {
+        let limit = event
+            .platform
+            .value()
+            .and_then(|platform| self.config.max_frames_per_platform.get(platform))
+            .cloned()
+            .unwrap_or(self.config.max_frames);
+
+        let mut lengths = Vec::new();
+        stacktrace::for_each_stacktrace(event, |stacktrace| {
+            lengths.push(stacktrace.frames.value().map_or(0, Vec::len));
+        });
+
+        let allowances =
+            stacktrace::frame_allowances(&lengths, limit, self.config.max_total_frames);
+        let mut allowances = allowances.into_iter();
+        stacktrace::for_each_stacktrace(event, |stacktrace| {
+            if let Some(allowance) = allowances.next() {
+                stacktrace::trim_frames(stacktrace, allowance);
+            }
+        });
+    }
+
+    /// Corrects clock drift and clamps the timestamps of the event and its breadcrumbs.
+    fn normalize_timestamps(&self, event: &mut Event, received: DateTime<Utc>) {
+        let drift = self
+            .sent_at
+            .map(|sent_at| received.signed_duration_since(sent_at))
+            .filter(|drift| drift.num_seconds().abs() as u64 >= self.config.min_clock_drift_secs);
+
+        if let Annotated(Some(Some(ref mut timestamp)), ref mut meta) = event.timestamp {
+            self.normalize_timestamp(timestamp, meta, received, drift);
+        }
+
+        let breadcrumbs = event
+            .breadcrumbs
+            .value_mut()
+            .and_then(|b| b.values.value_mut());
+
+        for breadcrumb in breadcrumbs.into_iter().flatten() {
+            if let Some(breadcrumb) = breadcrumb.value_mut() {
+                if let Annotated(Some(ref mut timestamp), ref mut meta) = breadcrumb.timestamp {
+                    self.normalize_timestamp(timestamp, meta, received, drift);
+                }
+            }
+        }
+    }
+
+    /// Shifts a single timestamp by the clock drift and clamps it to the configured limits.
+    ///
+    /// If the timestamp cannot be shifted without leaving the supported range of dates, it is
+    /// replaced with the received time like any other timestamp outside of the limits.
+    fn normalize_timestamp(
+        &self,
+        timestamp: &mut DateTime<Utc>,
+        meta: &mut Meta,
+        received: DateTime<Utc>,
+        drift: Option<Duration>,
+    ) {
+        let original = *timestamp;
+
+        let shifted = match drift {
+            Some(drift) => {
+                meta.remarks_mut()
+                    .push(Remark::new(RemarkType::Substituted, CLOCK_DRIFT_RULE_ID));
+                original.checked_add_signed(drift)
+            }
+            None => Some(original),
+        };
+
+        // Limits that exceed the supported range of dates do not apply.
+        let max_future = seconds(self.config.max_secs_in_future)
+            .and_then(|limit| received.checked_add_signed(limit));
+        let max_past = seconds(self.config.max_secs_in_past)
+            .and_then(|limit| received.checked_sub_signed(limit));
+
+        let error = match shifted {
+            Some(shifted) if max_future.map_or(false, |max| shifted > max) => {
+                Some(ErrorKind::FutureTimestamp)
+            }
+            Some(shifted) if max_past.map_or(false, |max| shifted < max) => {
+                Some(ErrorKind::PastTimestamp)
+            }
+            Some(shifted) => {
+                *timestamp = shifted;
+                None
+            }
+            None if drift.map_or(false, |drift| drift > Duration::zero()) => {
+                Some(ErrorKind::FutureTimestamp)
+            }
+            None => Some(ErrorKind::PastTimestamp),
+        };
+
+        if let Some(error) = error {
+            *timestamp = received;
+            meta.errors_mut().push(error.into());
+            meta.remarks_mut()
+                .push(Remark::new(RemarkType::Substituted, TIMESTAMP_RULE_ID));
+        }
+
+        if *timestamp != original && meta.original_value().is_none() {
+            meta.set_original_value(Some(datetime_to_value(&original)));
+        }
+    }
+}
+
+/// Converts a number of seconds into a duration, unless it exceeds the supported range.
+fn seconds(secs: u64) -> Option<Duration> {
+    Duration::from_std(StdDuration::from_secs(secs)).ok()
+}
+
+/// Creates the remark that is attached to all fields changed by normalization.
+fn remark(ty: RemarkType) -> Remark {
+    Remark::new(ty, NORMALIZE_RULE_ID)
+}
+
+/// Fills in a value if it is missing and records a remark.
+fn fill_default<T, F>(annotated: &mut Annotated<Option<T>>, f: F)
+where
+    F: FnOnce() -> T,
+{
+    if is_missing(annotated) {
+        annotated.set_value(Some(Some(f())));
+        annotated
+            .meta_mut()
+            .remarks_mut()
+            .push(remark(RemarkType::Substituted));
+    }
+}
+
+/// Unconditionally sets a value and records a remark if an existing value was replaced.
+fn set_value<T: PartialEq>(annotated: &mut Annotated<Option<T>>, value: T) {
+    let replaced = match annotated.value() {
+        Some(Some(ref old)) => *old != value,
+        _ => false,
+    };
+
+    annotated.set_value(Some(Some(value)));
+    if replaced {
+        annotated
+            .meta_mut()
+            .remarks_mut()
+            .push(remark(RemarkType::Substituted));
+    }
+}
+
+/// Trims surrounding whitespace from a string and removes it if it is empty.
+fn normalize_string(annotated: &mut Annotated<Option<String>>) {
+    let trimmed = match annotated.value() {
+        Some(Some(ref value)) if value.trim() != value => value.trim().to_string(),
+        Some(Some(ref value)) if value.is_empty() => String::new(),
+        _ => return,
+    };
+
+    let ty = if trimmed.is_empty() {
+        annotated.set_value(Some(None));
+        RemarkType::Removed
+    } else {
+        annotated.set_value(Some(Some(trimmed)));
+        RemarkType::Substituted
+    };
+
+    annotated.meta_mut().remarks_mut().push(remark(ty));
+}
+
+/// Returns the frames of a stacktrace.
+fn get_frames(stacktrace: &Annotated<Option<Stacktrace>>) -> Option<&[Annotated<Frame>]> {
+    match stacktrace.value() {
+        Some(Some(stacktrace)) => stacktrace.frames.value().map(Vec::as_slice),
+        _ => None,
+    }
+}
+
+/// Returns the stacktrace most relevant to the event's culprit.
+///
+/// This is the stacktrace of the last exception, falling back to the event stacktrace and the
+/// stacktrace of the crashed thread.
+fn get_culprit_frames(event: &Event) -> Option<&[Annotated<Frame>]> {
+    let exceptions = event.exceptions.value().and_then(|v| v.values.value());
+    if let Some(exception) = exceptions.and_then(|e| e.last()).and_then(Annotated::value) {
+        if let Some(frames) = get_frames(&exception.stacktrace) {
+            return Some(frames);
+        }
+    }
+
+    if let Some(frames) = get_frames(&event.stacktrace) {
+        return Some(frames);
+    }
+
+    let threads = event.threads.value().and_then(|v| v.values.value());
+    threads
+        .into_iter()
+        .flatten()
+        .filter_map(Annotated::value)
+        .find(|thread| thread.crashed.value().cloned().unwrap_or(false))
+        .and_then(|thread| get_frames(&thread.stacktrace))
+}
+
+/// Returns the top most in-app frame.
+///
+/// If no frame is marked in-app, this falls back to the top most frame that is not explicitly
+/// excluded and finally to the top most frame.
+fn compute_culprit(event: &Event) -> Option<&Frame> {
+    let frames = get_culprit_frames(event)?;
+    let mut frames = frames.iter().rev().filter_map(Annotated::value);
+
+    frames
+        .clone()
+        .find(|frame| frame.in_app.value() == Some(&Some(true)))
+        .or_else(|| {
+            frames
+                .clone()
+                .find(|frame| frame.in_app.value() != Some(&Some(false)))
+        })
+        .or_else(|| frames.next())
+}
+
+/// Formats a culprit string from a frame.
+fn format_culprit(frame: &Frame, platform: Option<&str>) -> String {
+    let module = frame.module.value().and_then(Option::as_ref);
+    let filename = frame.filename.value().and_then(Option::as_ref);
+    let location = module.or(filename).map(String::as_str).unwrap_or("?");
+    let function = frame
+        .function
+        .value()
+        .and_then(Option::as_ref)
+        .map(String::as_str)
+        .unwrap_or("?");
+
+    match platform {
+        Some("javascript") | Some("node") => format!("{}({})", function, location),
+        _ => format!("{} in {}", location, function),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use protocol::{Meta, MetaError};
+
+    fn processor() -> NormalizeProcessor<DateTime<Utc>> {
+        NormalizeProcessor::with_clock(Utc.ymd(2000, 1, 1).and_hms(0, 0, 0))
+    }
+
+    fn normalize(json: &str) -> Event {
+        let event = Annotated::<Event>::from_json(json).unwrap();
+        processor().process_event(event).0.unwrap()
+    }
+
+    fn substituted<T>(value: T) -> Annotated<Option<T>> {
+        let mut meta = Meta::default();
+        meta.remarks_mut().push(remark(RemarkType::Substituted));
+        Annotated::new(Some(value), meta)
+    }
+
+    #[test]
+    fn test_fills_defaults() {
+        let event = normalize("{}");
+        let now = Utc.ymd(2000, 1, 1).and_hms(0, 0, 0);
+
+        assert!(event.id.value().unwrap().is_some());
+        assert_eq_dbg!(event.id.meta().remarks().count(), 1);
+        assert_eq_dbg!(event.received, Annotated::from(Some(now)));
+        assert_eq_dbg!(event.timestamp, substituted(now));
+        assert_eq_dbg!(event.level, substituted(Level::Error));
+        assert_eq_dbg!(event.logentry, Annotated::from(None));
+        assert_eq_dbg!(event.culprit, Annotated::from(None));
+    }
+
+    #[test]
+    fn test_keeps_existing_values() {
+        let event = normalize(
+            r#"{
+                "event_id": "52df9022835246eeb317dbd739ccd059",
+                "timestamp": 946684700,
+                "received": 946684700,
+                "level": "debug",
+                "culprit": "myculprit"
+            }"#,
+        );
+
+        assert_eq_dbg!(
+            event.id,
+            Annotated::from(Some(
+                "52df9022-8352-46ee-b317-dbd739ccd059".parse().unwrap()
+            ))
+        );
+        assert_eq_dbg!(
+            event.timestamp,
+            Annotated::from(Some(Utc.ymd(1999, 12, 31).and_hms(23, 58, 20)))
+        );
+        assert_eq_dbg!(
+            event.received,
+            substituted(Utc.ymd(2000, 1, 1).and_hms(0, 0, 0))
+        );
+        assert_eq_dbg!(event.level, Annotated::from(Some(Level::Debug)));
+        assert_eq_dbg!(
+            event.culprit,
+            Annotated::from(Some("myculprit".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_keeps_invalid_values() {
+        let event = normalize(r#"{"event_id": "invalid", "level": "invalid"}"#);
+
+        assert!(event.id.value().map_or(true, Option::is_none));
+        assert!(event.id.meta().has_errors());
+        assert_eq_dbg!(event.id.meta().remarks().count(), 0);
+        assert!(event.level.value().map_or(true, Option::is_none));
+        assert!(event.level.meta().has_errors());
+        assert_eq_dbg!(event.level.meta().remarks().count(), 0);
+    }
+
+    #[test]
+    fn test_logentry_from_message() {
+        let event = normalize(r#"{"message": "Hello World!"}"#);
+
+        assert_eq_dbg!(
+            event.logentry,
+            substituted(LogEntry {
+                message: "Hello World!".to_string().into(),
+                params: Default::default(),
+                formatted: substituted("Hello World!".to_string()),
+                other: Default::default(),
+            })
+        );
+        assert_eq_dbg!(
+            event.message,
+            Annotated::from(Some("Hello World!".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_logentry_formatted() {
+        let event = normalize(
+            r#"{"logentry": {"message": "Hello, %s! You are %d.", "params": ["Peter", 42]}}"#,
+        );
+        let logentry = event.logentry.value().unwrap().as_ref().unwrap();
+
+        assert_eq_dbg!(
+            logentry.formatted,
+            substituted("Hello, Peter! You are 42.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_logentry_keeps_formatted() {
+        let event = normalize(
+            r#"{"logentry": {"message": "Hello, %s!", "params": ["Peter"], "formatted": "Hi"}}"#,
+        );
+        let logentry = event.logentry.value().unwrap().as_ref().unwrap();
+
+        assert_eq_dbg!(logentry.formatted, Annotated::from(Some("Hi".to_string())));
+    }
+
+    #[test]
+    fn test_culprit_from_in_app_frame() {
+        let event = normalize(
+            r#"{
+                "exception": {"values": [{
+                    "type": "ZeroDivisionError",
+                    "stacktrace": {"frames": [
+                        {"module": "app.views", "function": "index", "in_app": true},
+                        {"module": "django.core", "function": "handle", "in_app": false}
+                    ]}
+                }]}
+            }"#,
+        );
+
+        assert_eq_dbg!(event.culprit, substituted("app.views in index".to_string()));
+    }
+
+    #[test]
+    fn test_culprit_from_detected_in_app() {
+        let event = normalize(
+            r#"{
+                "platform": "python",
+                "exception": {"values": [{
+                    "type": "ZeroDivisionError",
+                    "stacktrace": {"frames": [
+                        {"module": "app.views", "function": "index"},
+                        {"module": "app.utils", "function": "divide"},
+                        {
+                            "abs_path": "/usr/lib/python3/site-packages/django/core.py",
+                            "module": "django.core",
+                            "function": "handle"
+                        }
+                    ]}
+                }]}
+            }"#,
+        );
+
+        assert_eq_dbg!(
+            event.culprit,
+            substituted("app.utils in divide".to_string())
+        );
+    }
+
+    #[test]
+    fn test_culprit_from_in_app_include() {
+        let event = Annotated::<Event>::from_json(
+            r#"{
+                "stacktrace": {"frames": [
+                    {"module": "app.views", "function": "index"},
+                    {"module": "lib.core", "function": "handle"}
+                ]}
+            }"#,
+        )
+        .unwrap();
+
+        let mut processor = processor();
+        processor.set_config(NormalizeConfig {
+            in_app_include: vec!["app.".to_string()],
+            ..Default::default()
+        });
+        let event = processor.process_event(event).0.unwrap();
+
+        assert_eq_dbg!(event.culprit, substituted("app.views in index".to_string()));
+    }
+
+    #[test]
+    fn test_trim_stacktraces() {
+        let frames: Vec<_> = (0..100).map(|_| "{}").collect();
+        let stacktrace = format!(r#"{{"stacktrace": {{"frames": [{}]}}}}"#, frames.join(","));
+        let json = format!(
+            r#"{{
+                "platform": "cocoa",
+                "exception": {{"values": [{{"type": "Error", {0}}}]}},
+                "threads": {{"values": [{{{0}}}, {{{0}}}]}}
+            }}"#,
+            &stacktrace[1..stacktrace.len() - 1]
+        );
+        let event = Annotated::<Event>::from_json(&json).unwrap();
+
+        let mut processor = processor();
+        processor.set_config(NormalizeConfig {
+            max_frames_per_platform: {
+                let mut limits = BTreeMap::new();
+                limits.insert("cocoa".to_string(), 80);
+                limits
+            },
+            max_total_frames: 150,
+            ..Default::default()
+        });
+        let event = processor.process_event(event).0.unwrap();
+
+        let exceptions = event.exceptions.0.unwrap().values.0.unwrap();
+        let exception = exceptions[0].value().unwrap();
+        let stacktrace = exception.stacktrace.value().unwrap().as_ref().unwrap();
+        assert_eq_dbg!(stacktrace.frames.value().unwrap().len(), 50);
+        assert_eq_dbg!(stacktrace.frames.meta().original_length(), Some(100));
+        assert_eq_dbg!(stacktrace.frames_omitted, Annotated::from(Some((25, 75))));
+
+        let threads = event.threads.0.unwrap().values.0.unwrap();
+        for thread in &threads {
+            let thread = thread.value().unwrap();
+            let stacktrace = thread.stacktrace.value().unwrap().as_ref().unwrap();
+            assert_eq_dbg!(stacktrace.frames.value().unwrap().len(), 50);
+        }
+    }
+
+    #[test]
+    fn test_culprit_javascript() {
+        let event = normalize(
+            r#"{
+                "platform": "javascript",
+                "stacktrace": {"frames": [
+                    {"filename": "app.js", "function": "render"}
+                ]}
+            }"#,
+        );
+
+        assert_eq_dbg!(event.culprit, substituted("render(app.js)".to_string()));
+    }
+
+    #[test]
+    fn test_clock_drift() {
+        let event = Annotated::<Event>::from_json(
+            r#"{
+                "timestamp": 946677540,
+                "breadcrumbs": [{"timestamp": 946677530}]
+            }"#,
+        )
+        .unwrap();
+
+        // The client's clock is two hours behind
+        let mut processor = processor();
+        processor.set_sent_at(Some(Utc.ymd(1999, 12, 31).and_hms(22, 0, 0)));
+        let event = processor.process_event(event).0.unwrap();
+
+        let mut meta = Meta::default();
+        meta.remarks_mut()
+            .push(Remark::new(RemarkType::Substituted, CLOCK_DRIFT_RULE_ID));
+        meta.set_original_value(Some(946677540.into()));
+        assert_eq_dbg!(
+            event.timestamp,
+            Annotated::new(Some(Utc.ymd(1999, 12, 31).and_hms(23, 59, 0)), meta)
+        );
+
+        let breadcrumbs = event.breadcrumbs.0.unwrap().values.0.unwrap();
+        let timestamp = &breadcrumbs[0].value().unwrap().timestamp;
+        assert_eq_dbg!(
+            timestamp.value(),
+            Some(&Utc.ymd(1999, 12, 31).and_hms(23, 58, 50))
+        );
+        assert_eq_dbg!(timestamp.meta().original_value(), Some(&946677530.into()));
+    }
+
+    #[test]
+    fn test_small_clock_drift_ignored() {
+        let event = Annotated::<Event>::from_json(r#"{"timestamp": 946684790}"#).unwrap();
+
+        let mut processor = processor();
+        processor.set_sent_at(Some(Utc.ymd(1999, 12, 31).and_hms(23, 59, 50)));
+        let event = processor.process_event(event).0.unwrap();
+
+        assert_eq_dbg!(
+            event.timestamp,
+            Annotated::from(Some(Utc.ymd(1999, 12, 31).and_hms(23, 59, 50)))
+        );
+    }
+
+    #[test]
+    fn test_clamp_future_timestamp() {
+        let event = normalize(r#"{"timestamp": 946688400}"#);
+
+        let mut meta = Meta::from_error(ErrorKind::FutureTimestamp);
+        meta.remarks_mut()
+            .push(Remark::new(RemarkType::Substituted, TIMESTAMP_RULE_ID));
+        meta.set_original_value(Some(946688400.into()));
+        assert_eq_dbg!(
+            event.timestamp,
+            Annotated::new(Some(Utc.ymd(2000, 1, 1).and_hms(0, 0, 0)), meta)
+        );
+    }
+
+    #[test]
+    fn test_clock_drift_out_of_range() {
+        let event = Annotated::<Event>::from_json(r#"{"timestamp": 8000000000000}"#).unwrap();
+
+        let mut processor = processor();
+        processor.set_sent_at(Some(Utc.ymd(-200000, 1, 1).and_hms(0, 0, 0)));
+        let event = processor.process_event(event).0.unwrap();
+
+        let mut meta = Meta::from_error(ErrorKind::FutureTimestamp);
+        meta.remarks_mut()
+            .push(Remark::new(RemarkType::Substituted, CLOCK_DRIFT_RULE_ID));
+        meta.remarks_mut()
+            .push(Remark::new(RemarkType::Substituted, TIMESTAMP_RULE_ID));
+        meta.set_original_value(Some(8000000000000i64.into()));
+        assert_eq_dbg!(
+            event.timestamp,
+            Annotated::new(Some(Utc.ymd(2000, 1, 1).and_hms(0, 0, 0)), meta)
+        );
+    }
+
+    #[test]
+    fn test_unbounded_timestamp_limits() {
+        let event = Annotated::<Event>::from_json(r#"{"timestamp": 946684800}"#).unwrap();
+
+        let mut processor = processor();
+        processor.set_config(NormalizeConfig {
+            max_secs_in_future: u64::max_value(),
+            max_secs_in_past: u64::max_value(),
+            ..Default::default()
+        });
+        let event = processor.process_event(event).0.unwrap();
+
+        assert_eq_dbg!(
+            event.timestamp,
+            Annotated::from(Some(Utc.ymd(2000, 1, 1).and_hms(0, 0, 0)))
+        );
+    }
+
+    #[test]
+    fn test_trim_event() {
+        let json = format!(r#"{{"extra": {{"large": "{}"}}}}"#, "x".repeat(1000));
+        let event = Annotated::<Event>::from_json(&json).unwrap();
+
+        let mut processor = processor();
+        processor.set_config(NormalizeConfig {
+            max_event_size: Some(1000),
+            ..Default::default()
+        });
+        let event = processor.process_event(event);
+
+        assert!(event.estimate_size() <= 1000);
+        let extra = event.value().unwrap().extra.value().unwrap();
+        assert!(extra["large"].value().is_none());
+    }
+
+    #[test]
+    fn test_clamp_past_timestamp() {
+        let event = Annotated::<Event>::from_json(r#"{"timestamp": 946684800.5}"#).unwrap();
+
+        let mut processor = NormalizeProcessor::with_clock(Utc.ymd(2000, 1, 2).and_hms(0, 0, 0));
+        processor.set_config(NormalizeConfig {
+            max_secs_in_past: 3600,
+            ..Default::default()
+        });
+        let event = processor.process_event(event).0.unwrap();
+
+        assert_eq_dbg!(
+            event.timestamp.value(),
+            Some(&Some(Utc.ymd(2000, 1, 2).and_hms(0, 0, 0)))
+        );
+        assert_eq_dbg!(
+            event.timestamp.meta().errors().cloned().collect::<Vec<_>>(),
+            vec![MetaError::from(ErrorKind::PastTimestamp)]
+        );
+        assert_eq_dbg!(
+            event.timestamp.meta().original_value(),
+            Some(&946684800.5.into())
+        );
+    }
+
+    #[test]
+    fn test_normalize_strings() {
+        let event = normalize(r#"{"environment": " production\n", "release": "  "}"#);
+
+        assert_eq_dbg!(event.environment, substituted("production".to_string()));
+        assert_eq_dbg!(event.release, {
+            let mut meta = Meta::default();
+            meta.remarks_mut().push(remark(RemarkType::Removed));
+            Annotated::new(None, meta)
+        });
+    }
+}
@@ -1,6 +1,8 @@
 //! Implements a processing system for the protocol.
 use std::collections::BTreeMap;
 
+use serde_json;
+
 use protocol::{Annotated, Array, Map, Meta, Value, Values};
 
 use super::chunks::{self, Chunk};
@@ -142,6 +144,19 @@ pub trait Processor {
             other @ Annotated(None, _) => other,
         }
     }
+
+    /// Processes the original value of a field that failed to deserialize.
+    ///
+    /// Invalid fields retain their original value in the meta data. It is passed here along with
+    /// the value info of the invalid field.
+    fn process_original_value(
+        &self,
+        annotated: Annotated<Value>,
+        info: &ValueInfo,
+    ) -> Annotated<Value> {
+        let _info = info;
+        annotated
+    }
 }
 
 /// A trait implemented for annotated types that support processing.
@@ -156,6 +171,36 @@ pub trait ProcessAnnotatedValue {
         Self: Sized;
 }
 
+/// Processes the original value retained in the meta data of an invalid annotated value.
+///
+/// Implementations of `ProcessAnnotatedValue` call this for annotated values without a value, so
+/// that processors can handle the original value like regular data.
+pub fn process_invalid_value<T>(
+    mut annotated: Annotated<T>,
+    processor: &Processor,
+    info: &ValueInfo,
+) -> Annotated<T> {
+    if annotated.value().is_some() {
+        return annotated;
+    }
+
+    let original = match annotated.meta_mut().original_value.take() {
+        Some(original) => original,
+        None => return annotated,
+    };
+
+    let original = match serde_json::from_value(original) {
+        Ok(original) => processor.process_original_value(original, info),
+        Err(_) => return annotated,
+    };
+
+    let original = serde_json::to_value(&original)
+        .ok()
+        .filter(|v| !v.is_null());
+    annotated.meta_mut().set_original_value(original);
+    annotated
+}
+
 /// Helper trait for pii processing.
 pub trait PiiProcessor {
     /// This is invoked with chunked data for strings.
@@ -243,6 +288,26 @@ impl<T: PiiProcessor> Processor for T {
     impl_primitive_pii_process!(u64, U64, process_u64);
     impl_primitive_pii_process!(i64, I64, process_i64);
     impl_primitive_pii_process!(f64, F64, process_f64);
+
+    fn process_original_value(
+        &self,
+        annotated: Annotated<Value>,
+        info: &ValueInfo,
+    ) -> Annotated<Value> {
+        // The original value does not necessarily match the structure of the field. Unless it is
+        // a primitive of a field with known PII, it is treated as an arbitrary data bag.
+        let pii_kind = match (annotated.value(), info.pii_kind) {
+            (Some(&Value::Array(_)), _) | (Some(&Value::Map(_)), _) | (_, None) => PiiKind::Databag,
+            (_, Some(pii_kind)) => pii_kind,
+        };
+
+        let info = ValueInfo {
+            pii_kind: Some(pii_kind),
+            cap: info.cap,
+        };
+
+        self.process_value(annotated, &info)
+    }
 }
 
 macro_rules! impl_primitive_process {
@@ -253,9 +318,10 @@ macro_rules! impl_primitive_process {
                 processor: &Processor,
                 info: &ValueInfo,
             ) -> Annotated<$ty> {
-                processor
+                let annotated = processor
                     .$func(annotated.map(From::from), info)
-                    .map(|x| x as $ty)
+                    .map(|x| x as $ty);
+                process_invalid_value(annotated, processor, info)
             }
         }
     };
@@ -284,7 +350,7 @@ impl<T: ProcessAnnotatedValue> ProcessAnnotatedValue for Option<T> {
                 info,
             ).map(Some),
             other @ Annotated(Some(None), _) => other,
-            other @ Annotated(None, _) => other,
+            other @ Annotated(None, _) => process_invalid_value(other, processor, info),
         }
     }
 }
@@ -301,7 +367,7 @@ impl<T: ProcessAnnotatedValue> ProcessAnnotatedValue for Box<T> {
                 processor,
                 info,
             ).map(Box::new),
-            other @ Annotated(None, _) => other,
+            other @ Annotated(None, _) => process_invalid_value(other, processor, info),
         }
     }
 }
@@ -312,14 +378,15 @@ impl<T: ProcessAnnotatedValue> ProcessAnnotatedValue for Values<T> {
         processor: &Processor,
         info: &ValueInfo,
     ) -> Annotated<Self> {
-        annotated.map(|Values { values, other }| Values {
+        let annotated = annotated.map(|Values { values, other }| Values {
             values: ProcessAnnotatedValue::process_annotated_value(
                 values,
                 processor,
                 &info.derive(),
             ),
             other: ProcessAnnotatedValue::process_annotated_value(other, processor, &info.derive()),
-        })
+        });
+        process_invalid_value(annotated, processor, info)
     }
 }
 
@@ -329,13 +396,14 @@ impl<T: ProcessAnnotatedValue> ProcessAnnotatedValue for Array<T> {
         processor: &Processor,
         info: &ValueInfo,
     ) -> Annotated<Self> {
-        annotated.map(|value| {
+        let annotated = annotated.map(|value| {
             value
                 .into_iter()
                 .map(|item| {
                     ProcessAnnotatedValue::process_annotated_value(item, processor, &info.derive())
                 }).collect()
-        })
+        });
+        process_invalid_value(annotated, processor, info)
     }
 }
 
@@ -345,7 +413,7 @@ impl<T: ProcessAnnotatedValue> ProcessAnnotatedValue for Map<T> {
         processor: &Processor,
         info: &ValueInfo,
     ) -> Annotated<Self> {
-        annotated.map(|value| {
+        let annotated = annotated.map(|value| {
             value
                 .into_iter()
                 .map(|(key, value)| {
@@ -358,7 +426,8 @@ impl<T: ProcessAnnotatedValue> ProcessAnnotatedValue for Map<T> {
                         ),
                     )
                 }).collect()
-        })
+        });
+        process_invalid_value(annotated, processor, info)
     }
 }
 
@@ -366,7 +435,7 @@ impl<T: ProcessAnnotatedValue> ProcessAnnotatedValue for Map<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use protocol::{Remark, RemarkType};
+    use protocol::{MetaError, Remark, RemarkType};
 
     #[test]
     fn test_basic_processing() {
@@ -450,4 +519,58 @@ mod tests {
         assert!(id.value().is_none());
         assert_eq_str!(id.meta().remarks().next().unwrap().rule_id(), "@id-removed");
     }
+
+    #[test]
+    fn test_pii_original_value() {
+        #[derive(ProcessAnnotatedValue)]
+        struct Event {
+            #[process_annotated_value(pii_kind = "id")]
+            id: Annotated<u32>,
+            #[process_annotated_value]
+            count: Annotated<u32>,
+            level: Annotated<String>,
+        }
+
+        struct MyPiiProcessor;
+
+        impl PiiProcessor for MyPiiProcessor {
+            fn pii_process_value(
+                &self,
+                annotated: Annotated<Value>,
+                pii_kind: PiiKind,
+            ) -> Annotated<Value> {
+                match (annotated, pii_kind) {
+                    (annotated, PiiKind::Id) => annotated
+                        .with_removed_value(Remark::new(RemarkType::Removed, "@id-removed")),
+                    (annotated, PiiKind::Databag) => annotated.map(|_| Value::String("[x]".into())),
+                    (annotated, _) => annotated,
+                }
+            }
+        }
+
+        let event = Annotated::from(Event {
+            id: Annotated::from_error(MetaError::expected("u32")).with_original_value("me".into()),
+            count: Annotated::from_error(MetaError::expected("u32"))
+                .with_original_value(serde_json::from_str(r#"["secret"]"#).unwrap()),
+            level: Annotated::from_error(MetaError::expected("a string"))
+                .with_original_value(serde_json::from_str(r#"{"password":"hunter2"}"#).unwrap()),
+        });
+
+        let new_event = ProcessAnnotatedValue::process_annotated_value(
+            event,
+            &MyPiiProcessor,
+            &ValueInfo::default(),
+        );
+        let new_event = new_event.0.unwrap();
+
+        assert_eq_dbg!(new_event.id.meta().original_value(), None);
+        assert_eq_dbg!(
+            new_event.count.meta().original_value(),
+            Some(&serde_json::from_str(r#"["[x]"]"#).unwrap())
+        );
+        assert_eq_dbg!(
+            new_event.level.meta().original_value(),
+            Some(&serde_json::from_str(r#"{"password":"[x]"}"#).unwrap())
+        );
+    }
 }
@@ -0,0 +1,820 @@
+//! Normalization of HTTP request information.
+use std::net::IpAddr;
+
+use protocol::{
+    Annotated, Cookies, DeserializeLimits, Event, Map, Query, Remark, RemarkType, Request, User,
+    Value,
+};
+
+use super::geo::AUTO_IP_ADDRESS;
+
+/// The rule id used for remarks emitted by request normalization.
+const REQUEST_RULE_ID: &str = "@request";
+
+fn remark(ty: RemarkType) -> Remark {
+    Remark::new(ty, REQUEST_RULE_ID)
+}
+
+/// Splits a URL into the base URL, the query string and the fragment.
+fn split_url(url: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (url, fragment) = match url.find('#') {
+        Some(index) => (&url[..index], Some(&url[index + 1..])),
+        None => (url, None),
+    };
+
+    let (url, query) = match url.find('?') {
+        Some(index) => (&url[..index], Some(&url[index + 1..])),
+        None => (url, None),
+    };
+
+    (url, query, fragment)
+}
+
+/// Moves the query string and fragment out of the URL.
+///
+/// Values that are already set explicitly take precedence over those in the URL. If the query
+/// string cannot be parsed or the URL conflicts with explicit values, the URL is left untouched so
+/// that no data is lost.
+fn normalize_url(request: &mut Request) {
+    let (url, query, fragment) = match request.url.value() {
+        Some(Some(ref url)) => match split_url(url) {
+            (_, None, None) => return,
+            (url, query, fragment) => (
+                url.to_string(),
+                query.map(str::to_string),
+                fragment
+                    .filter(|fragment| !fragment.is_empty())
+                    .map(str::to_string),
+            ),
+        },
+        _ => return,
+    };
+
+    let parsed = match query.map(|query| Query::parse(&query)) {
+        Some(Ok(parsed)) => Some(parsed),
+        Some(Err(_)) => return,
+        None => None,
+    };
+
+    let query_string = match request.query_string.value_mut() {
+        Some(query_string) => query_string,
+        None => return,
+    };
+
+    let query_conflicts = parsed.as_ref().map_or(false, |parsed| {
+        parsed.0.iter().any(|(key, value)| {
+            query_string
+                .0
+                .get(key)
+                .map_or(false, |existing| existing.value() != value.value())
+        })
+    });
+
+    let fragment_conflicts = match (request.fragment.value(), fragment.as_ref()) {
+        (Some(Some(ref existing)), Some(fragment)) => existing != fragment,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+
+    if query_conflicts || fragment_conflicts {
+        return;
+    }
+
+    if let Some(parsed) = parsed {
+        let mut changed = false;
+        for (key, value) in parsed.0 {
+            query_string.0.entry(key).or_insert_with(|| {
+                changed = true;
+                value
+            });
+        }
+
+        if changed {
+            request
+                .query_string
+                .meta_mut()
+                .remarks_mut()
+                .push(remark(RemarkType::Substituted));
+        }
+    }
+
+    if let Some(fragment) = fragment {
+        if request.fragment.value().map_or(false, Option::is_none) {
+            request.fragment.set_value(Some(Some(fragment)));
+            request
+                .fragment
+                .meta_mut()
+                .remarks_mut()
+                .push(remark(RemarkType::Substituted));
+        }
+    }
+
+    request.url.set_value(Some(Some(url)));
+    request
+        .url
+        .meta_mut()
+        .remarks_mut()
+        .push(remark(RemarkType::Substituted));
+}
+
+/// Moves the contents of all `Cookie` headers into the cookies.
+///
+/// Explicit cookies take precedence over those in the headers. The headers are only removed if
+/// all of their cookies could be moved, and left untouched if any of them cannot be parsed.
+fn normalize_cookies(request: &mut Request) {
+    let mut parsed = Vec::new();
+    if let Some(headers) = request.headers.value() {
+        for header in headers.get_all("Cookie") {
+            match header.value().map(|header| Cookies::parse(header)) {
+                Some(Ok(cookies)) => parsed.push(cookies),
+                _ => return,
+            }
+        }
+    }
+
+    if parsed.is_empty() {
+        return;
+    }
+
+    let cookies = match request.cookies.value_mut() {
+        Some(cookies) => cookies,
+        None => return,
+    };
+
+    let mut changed = false;
+    let mut conflicts = false;
+    for (name, value) in parsed.into_iter().flat_map(|cookies| cookies.0) {
+        match cookies.0.get(&name) {
+            Some(existing) => conflicts |= existing.value() != value.value(),
+            None => {
+                cookies.0.insert(name, value);
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        request
+            .cookies
+            .meta_mut()
+            .remarks_mut()
+            .push(remark(RemarkType::Substituted));
+    }
+
+    if conflicts {
+        return;
+    }
+
+    if let Some(headers) = request.headers.value_mut() {
+        headers.remove("Cookie");
+    }
+
+    request
+        .headers
+        .meta_mut()
+        .remarks_mut()
+        .push(remark(RemarkType::Removed));
+}
+
+/// Normalizes the HTTP method to uppercase or infers it from the request body.
+fn normalize_method(request: &mut Request) {
+    let method = match request.method.value() {
+        Some(Some(ref method)) => {
+            let uppercase = method.to_uppercase();
+            if uppercase == *method {
+                return;
+            }
+            uppercase
+        }
+        Some(None) => match (request.data.value(), request.url.value()) {
+            (Some(Some(_)), _) => "POST".to_string(),
+            (_, Some(Some(_))) => "GET".to_string(),
+            _ => return,
+        },
+        None => return,
+    };
+
+    request.method.set_value(Some(Some(method)));
+    request
+        .method
+        .meta_mut()
+        .remarks_mut()
+        .push(remark(RemarkType::Substituted));
+}
+
+/// Returns the value of a parameter in a header, such as the boundary in `Content-Type`.
+fn get_header_param<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header
+        .split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let mut parts = param.splitn(2, '=');
+            let key = parts.next()?.trim();
+            let value = parts.next()?.trim();
+            if key.eq_ignore_ascii_case(name) {
+                Some(value.trim_matches('"'))
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+/// Removes a single leading line break.
+fn strip_leading_line_break(s: &str) -> &str {
+    if s.starts_with("\r\n") {
+        &s[2..]
+    } else if s.starts_with('\n') {
+        &s[1..]
+    } else {
+        s
+    }
+}
+
+/// Parses a `multipart/form-data` body into a map of field names.
+///
+/// Only the names of fields are retained and their values are `null`, since bodies may contain
+/// large or binary values. Uploaded files are replaced by a placeholder containing their file name.
+fn parse_multipart(body: &str, boundary: &str) -> Option<Value> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = body.split(delimiter.as_str());
+
+    // Everything before the first delimiter is a preamble and ignored.
+    parts.next()?;
+
+    let mut fields = Map::new();
+    for part in parts {
+        if part.starts_with("--") {
+            return Some(Value::Map(fields));
+        }
+
+        let part = strip_leading_line_break(part);
+        let headers = match part.find("\r\n\r\n") {
+            Some(index) => &part[..index],
+            None => &part[..part.find("\n\n")?],
+        };
+
+        let disposition = headers
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                let name = parts.next()?.trim();
+                if name.eq_ignore_ascii_case("content-disposition") {
+                    parts.next().map(str::trim)
+                } else {
+                    None
+                }
+            })
+            .next()?;
+
+        let name = get_header_param(disposition, "name")?;
+        let value = match get_header_param(disposition, "filename") {
+            Some(filename) => Value::String(format!("[file: {}]", filename)),
+            None => Value::Null,
+        };
+
+        fields.insert(name.to_string(), value.into());
+    }
+
+    // The closing delimiter is missing, so the body was likely truncated.
+    None
+}
+
+/// Parses a JSON body, enforcing the default deserialization limits.
+///
+/// Values exceeding the limits are truncated or dropped and receive an error in their meta data.
+/// Returns `None` if the body is not valid JSON.
+fn parse_json(body: &str) -> Option<Annotated<Value>> {
+    Annotated::<Value>::from_json_with_limits(body, DeserializeLimits::default())
+        .ok()
+        .filter(|value| value.value().is_some())
+}
+
+/// Parses a request body according to its content type.
+///
+/// Without a content type, the body is parsed if it looks like a JSON object or array. Returns
+/// the parsed value and the inferred content type.
+fn parse_data(body: &str, content_type: Option<&str>) -> Option<(Annotated<Value>, &'static str)> {
+    let mime_type = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime_type| mime_type.trim().to_lowercase());
+
+    match mime_type.as_ref().map(String::as_str) {
+        Some("application/x-www-form-urlencoded") => Query::parse(body).ok().map(|query| {
+            (
+                Annotated::from(Value::Map(query.0)),
+                "application/x-www-form-urlencoded",
+            )
+        }),
+        Some("multipart/form-data") => {
+            let boundary = get_header_param(content_type?, "boundary")?;
+            parse_multipart(body, boundary)
+                .map(|value| (Annotated::from(value), "multipart/form-data"))
+        }
+        Some(mime_type) if mime_type == "application/json" || mime_type.ends_with("+json") => {
+            parse_json(body).map(|value| (value, "application/json"))
+        }
+        Some(_) => None,
+        None => match parse_json(body) {
+            Some(value @ Annotated(Some(Value::Map(_)), _))
+            | Some(value @ Annotated(Some(Value::Array(_)), _)) => {
+                Some((value, "application/json"))
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Decodes a raw request body into structured data based on the `Content-Type` header.
+///
+/// The raw body is kept if it cannot be parsed. Errors from parsing, such as exceeded limits, are
+/// added to the meta data of the request data.
+fn normalize_data(request: &mut Request) {
+    let content_type = request
+        .headers
+        .value()
+        .and_then(|headers| headers.get("Content-Type"))
+        .and_then(Annotated::value)
+        .map(String::as_str);
+
+    let (Annotated(value, meta), inferred_content_type) = match request.data.value() {
+        Some(&Some(Value::String(ref body))) => match parse_data(body, content_type) {
+            Some(parsed) => parsed,
+            None => return,
+        },
+        _ => return,
+    };
+
+    request.data.set_value(Some(value));
+    let data_meta = request.data.meta_mut();
+    data_meta.errors_mut().extend(meta.errors().cloned());
+    if data_meta.original_length().is_none() {
+        data_meta.set_original_length(meta.original_length().map(|length| length as u32));
+    }
+    data_meta
+        .remarks_mut()
+        .push(remark(RemarkType::Substituted));
+
+    if request
+        .inferred_content_type
+        .value()
+        .map_or(false, Option::is_none)
+    {
+        request
+            .inferred_content_type
+            .set_value(Some(Some(inferred_content_type.to_string())));
+    }
+}
+
+/// Derives query string, fragment, cookies, data and method of a request.
+///
+/// Data moved into a dedicated field is removed from its original location. All changes are
+/// recorded as remarks on the affected fields.
+pub(crate) fn normalize_request(request: &mut Request) {
+    normalize_url(request);
+    normalize_cookies(request);
+    normalize_data(request);
+    normalize_method(request);
+}
+
+/// Returns the IP address of the client that sent the request.
+///
+/// This is `REMOTE_ADDR` from the environment, falling back to the first address in the
+/// `X-Forwarded-For` header.
+fn get_client_ip(request: &Request) -> Option<String> {
+    let is_valid = |ip: &&str| ip.parse::<IpAddr>().is_ok();
+
+    let remote_addr = request
+        .env
+        .value()
+        .and_then(|env| env.get("REMOTE_ADDR"))
+        .and_then(|value| match value.value() {
+            Some(&Value::String(ref ip)) => Some(ip.trim()),
+            _ => None,
+        })
+        .filter(is_valid);
+
+    let forwarded_for = || {
+        request
+            .headers
+            .value()
+            .and_then(|headers| headers.get("X-Forwarded-For"))
+            .and_then(Annotated::value)
+            .and_then(|header| header.split(',').next())
+            .map(str::trim)
+            .filter(is_valid)
+    };
+
+    remote_addr.or_else(forwarded_for).map(str::to_string)
+}
+
+/// Fills the user's IP address from the request if it is missing or set to `{{auto}}`.
+pub(crate) fn infer_ip_address(event: &mut Event) {
+    let ip_address = match event.request.value() {
+        Some(Some(ref request)) => match get_client_ip(request) {
+            Some(ip_address) => ip_address,
+            None => return,
+        },
+        _ => return,
+    };
+
+    let user = match event.user.value_mut() {
+        Some(user) => user.get_or_insert_with(User::default),
+        None => return,
+    };
+
+    let is_missing = match user.ip_address.value() {
+        Some(Some(ref ip_address)) => ip_address == AUTO_IP_ADDRESS,
+        Some(None) => true,
+        None => false,
+    };
+
+    if is_missing {
+        user.ip_address.set_value(Some(Some(ip_address)));
+        user.ip_address
+            .meta_mut()
+            .remarks_mut()
+            .push(remark(RemarkType::Substituted));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{ErrorKind, MetaError};
+    use testutils::event;
+
+    fn parse_request(json: &str) -> Request {
+        Annotated::<Request>::from_json(json).unwrap().0.unwrap()
+    }
+
+    fn normalized(value: &str) -> Annotated<Option<String>> {
+        let mut annotated = Annotated::from(Some(value.to_string()));
+        annotated
+            .meta_mut()
+            .remarks_mut()
+            .push(remark(RemarkType::Substituted));
+        annotated
+    }
+
+    fn user_ip(event: &Event) -> &Annotated<Option<String>> {
+        &event.user.value().unwrap().as_ref().unwrap().ip_address
+    }
+
+    #[test]
+    fn test_split_url() {
+        assert_eq_dbg!(split_url("/path"), ("/path", None, None));
+        assert_eq_dbg!(
+            split_url("https://example.org/path?a=b#top"),
+            ("https://example.org/path", Some("a=b"), Some("top"))
+        );
+        assert_eq_dbg!(split_url("/path#a?b"), ("/path", None, Some("a?b")));
+    }
+
+    #[test]
+    fn test_url() {
+        let mut request = parse_request(
+            r#"{
+                "url": "https://example.org/search?q=foo&page=2#results",
+                "query_string": "page=2"
+            }"#,
+        );
+        normalize_request(&mut request);
+
+        assert_eq_dbg!(request.url, normalized("https://example.org/search"));
+        assert_eq_dbg!(request.fragment, normalized("results"));
+
+        let query = request.query_string.value().unwrap();
+        assert_eq_dbg!(
+            query.0["q"],
+            Annotated::from(Value::String("foo".to_string()))
+        );
+        assert_eq_dbg!(
+            query.0["page"],
+            Annotated::from(Value::String("2".to_string()))
+        );
+        assert_eq_str!(
+            request
+                .query_string
+                .meta()
+                .remarks()
+                .next()
+                .unwrap()
+                .rule_id(),
+            REQUEST_RULE_ID
+        );
+    }
+
+    #[test]
+    fn test_url_conflict() {
+        let mut request = parse_request(
+            r#"{
+                "url": "https://example.org/search?q=foo&page=1",
+                "query_string": "page=2"
+            }"#,
+        );
+        normalize_request(&mut request);
+
+        assert_eq_dbg!(
+            request.url,
+            Annotated::from(Some("https://example.org/search?q=foo&page=1".to_string()))
+        );
+
+        let query = request.query_string.value().unwrap();
+        assert!(!query.0.contains_key("q"));
+        assert_eq_dbg!(
+            query.0["page"],
+            Annotated::from(Value::String("2".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_url_fragment_conflict() {
+        let mut request =
+            parse_request(r#"{"url": "https://example.org/#top", "fragment": "bottom"}"#);
+        normalize_request(&mut request);
+
+        assert_eq_dbg!(
+            request.url,
+            Annotated::from(Some("https://example.org/#top".to_string()))
+        );
+        assert_eq_dbg!(
+            request.fragment,
+            Annotated::from(Some("bottom".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_url_without_query() {
+        let mut request = parse_request(r#"{"url": "https://example.org/"}"#);
+        normalize_request(&mut request);
+
+        assert_eq_dbg!(
+            request.url,
+            Annotated::from(Some("https://example.org/".to_string()))
+        );
+        assert!(request.query_string.meta().is_empty());
+    }
+
+    #[test]
+    fn test_cookies() {
+        let mut request = parse_request(
+            r#"{
+                "cookies": {"foo": "bar"},
+                "headers": {"Cookie": "foo=bar; session=1", "Accept": "*/*"}
+            }"#,
+        );
+        normalize_request(&mut request);
+
+        let cookies = request.cookies.value().unwrap();
+        assert_eq_dbg!(cookies.0["foo"], Annotated::from("bar".to_string()));
+        assert_eq_dbg!(cookies.0["session"], Annotated::from("1".to_string()));
+
+        let headers = request.headers.value().unwrap();
+        assert!(!headers.contains("Cookie"));
+        assert!(headers.contains("Accept"));
+        assert_eq_dbg!(
+            request.headers.meta().remarks().next().unwrap().ty(),
+            RemarkType::Removed
+        );
+    }
+
+    #[test]
+    fn test_cookies_conflict() {
+        let mut request = parse_request(
+            r#"{
+                "cookies": {"foo": "explicit"},
+                "headers": {"Cookie": "foo=bar; session=1"}
+            }"#,
+        );
+        normalize_request(&mut request);
+
+        let cookies = request.cookies.value().unwrap();
+        assert_eq_dbg!(cookies.0["foo"], Annotated::from("explicit".to_string()));
+        assert_eq_dbg!(cookies.0["session"], Annotated::from("1".to_string()));
+
+        assert!(request.headers.value().unwrap().contains("Cookie"));
+        assert!(request.headers.meta().is_empty());
+    }
+
+    fn data_map(request: &Request) -> &Map<Value> {
+        match request.data.value() {
+            Some(&Some(Value::Map(ref map))) => map,
+            other => panic!("expected map, got {:?}", other),
+        }
+    }
+
+    fn string(value: &str) -> Annotated<Value> {
+        Annotated::from(Value::String(value.to_string()))
+    }
+
+    #[test]
+    fn test_data_json() {
+        let mut request = parse_request(
+            r#"{
+                "data": "{\"foo\": [1, 2]}",
+                "headers": {"Content-Type": "application/json; charset=utf-8"}
+            }"#,
+        );
+        normalize_request(&mut request);
+
+        assert!(data_map(&request).contains_key("foo"));
+        assert_eq_dbg!(
+            request.inferred_content_type,
+            Annotated::from(Some("application/json".to_string()))
+        );
+        assert_eq_str!(
+            request.data.meta().remarks().next().unwrap().rule_id(),
+            REQUEST_RULE_ID
+        );
+    }
+
+    #[test]
+    fn test_data_json_limits() {
+        let body = format!("[{}0]", "0,".repeat(10_000));
+        let mut request = parse_request("{}");
+        request.data.set_value(Some(Some(Value::String(body))));
+        normalize_request(&mut request);
+
+        match request.data.value() {
+            Some(&Some(Value::Array(ref array))) => assert_eq_dbg!(array.len(), 10_000),
+            other => panic!("expected array, got {:?}", other),
+        }
+
+        let meta = request.data.meta();
+        assert_eq_dbg!(
+            meta.errors().map(MetaError::kind).collect::<Vec<_>>(),
+            vec![&ErrorKind::TooManyValues]
+        );
+        assert_eq_dbg!(meta.original_length(), Some(10_001));
+    }
+
+    #[test]
+    fn test_data_json_without_content_type() {
+        let mut request = parse_request(r#"{"data": "{\"foo\": \"bar\"}"}"#);
+        normalize_request(&mut request);
+        assert_eq_dbg!(data_map(&request)["foo"], string("bar"));
+
+        let mut request = parse_request(r#"{"data": "42"}"#);
+        normalize_request(&mut request);
+        assert_eq_dbg!(
+            request.data,
+            Annotated::from(Some(Value::String("42".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_data_form() {
+        let mut request = parse_request(
+            r#"{
+                "data": "foo=bar&baz=1",
+                "headers": {"Content-Type": "application/x-www-form-urlencoded"}
+            }"#,
+        );
+        normalize_request(&mut request);
+
+        let data = data_map(&request);
+        assert_eq_dbg!(data["foo"], string("bar"));
+        assert_eq_dbg!(data["baz"], string("1"));
+        assert_eq_dbg!(
+            request.inferred_content_type,
+            Annotated::from(Some("application/x-www-form-urlencoded".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_data_multipart() {
+        let body = "--XyZ\r\n\
+                    Content-Disposition: form-data; name=\"comment\"\r\n\
+                    \r\n\
+                    hello world\r\n\
+                    --XyZ\r\n\
+                    Content-Disposition: form-data; name=\"upload\"; filename=\"cat.png\"\r\n\
+                    Content-Type: image/png\r\n\
+                    \r\n\
+                    \x01\x02\x03\r\n\
+                    --XyZ--\r\n";
+
+        let mut request = Request {
+            data: Annotated::from(Some(Value::String(body.to_string()))),
+            ..parse_request(r#"{"headers": {"Content-Type": "multipart/form-data; boundary=XyZ"}}"#)
+        };
+        normalize_request(&mut request);
+
+        let data = data_map(&request);
+        assert_eq_dbg!(data["comment"], Annotated::from(Value::Null));
+        assert_eq_dbg!(data["upload"], string("[file: cat.png]"));
+        assert_eq_dbg!(
+            request.inferred_content_type,
+            Annotated::from(Some("multipart/form-data".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_data_invalid() {
+        let mut request = parse_request(
+            r#"{
+                "data": "{\"foo\": ",
+                "headers": {"Content-Type": "application/json"}
+            }"#,
+        );
+        normalize_request(&mut request);
+
+        assert_eq_dbg!(
+            request.data,
+            Annotated::from(Some(Value::String("{\"foo\": ".to_string())))
+        );
+        assert_eq_dbg!(request.inferred_content_type, Annotated::from(None));
+    }
+
+    #[test]
+    fn test_multiple_cookie_headers() {
+        let mut request = parse_request(
+            r#"{"headers": [["Cookie", "foo=bar"], ["Accept", "*/*"], ["Cookie", "session=1"]]}"#,
+        );
+        normalize_request(&mut request);
+
+        let cookies = request.cookies.value().unwrap();
+        assert_eq_dbg!(cookies.0["foo"], Annotated::from("bar".to_string()));
+        assert_eq_dbg!(cookies.0["session"], Annotated::from("1".to_string()));
+        assert_eq_dbg!(request.headers.value().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_method() {
+        let mut request = parse_request(r#"{"url": "/", "method": "post"}"#);
+        normalize_request(&mut request);
+        assert_eq_dbg!(request.method, normalized("POST"));
+
+        let mut request = parse_request(r#"{"url": "/", "data": {"a": 1}}"#);
+        normalize_request(&mut request);
+        assert_eq_dbg!(request.method, normalized("POST"));
+
+        let mut request = parse_request(r#"{"url": "/"}"#);
+        normalize_request(&mut request);
+        assert_eq_dbg!(request.method, normalized("GET"));
+
+        let mut request = parse_request(r#"{"env": {}}"#);
+        normalize_request(&mut request);
+        assert_eq_dbg!(request.method, Annotated::from(None));
+    }
+
+    #[test]
+    fn test_ip_from_remote_addr() {
+        let mut event = event(
+            r#"{
+                "user": {"ip_address": "{{auto}}"},
+                "request": {
+                    "env": {"REMOTE_ADDR": "213.47.147.207"},
+                    "headers": {"X-Forwarded-For": "10.0.0.1"}
+                }
+            }"#,
+        );
+        infer_ip_address(&mut event);
+        assert_eq_dbg!(*user_ip(&event), normalized("213.47.147.207"));
+    }
+
+    #[test]
+    fn test_ip_from_forwarded_for() {
+        let mut event =
+            event(r#"{"request": {"headers": {"X-Forwarded-For": "81.2.69.142, 10.0.0.1"}}}"#);
+        infer_ip_address(&mut event);
+        assert_eq_dbg!(*user_ip(&event), normalized("81.2.69.142"));
+    }
+
+    #[test]
+    fn test_ip_keeps_explicit() {
+        let mut event = event(
+            r#"{
+                "user": {"ip_address": "127.0.0.1"},
+                "request": {"env": {"REMOTE_ADDR": "213.47.147.207"}}
+            }"#,
+        );
+        infer_ip_address(&mut event);
+        assert_eq_dbg!(
+            *user_ip(&event),
+            Annotated::from(Some("127.0.0.1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_ip_invalid() {
+        let mut event = event(r#"{"request": {"env": {"REMOTE_ADDR": "unknown"}}}"#);
+        infer_ip_address(&mut event);
+        assert_eq_dbg!(event.user, Annotated::from(None));
+    }
+
+    #[test]
+    fn test_ip_invalid_remote_addr_falls_back() {
+        let mut event = event(
+            r#"{
+                "request": {
+                    "env": {"REMOTE_ADDR": "unknown"},
+                    "headers": {"X-Forwarded-For": "81.2.69.142"}
+                }
+            }"#,
+        );
+        infer_ip_address(&mut event);
+        assert_eq_dbg!(*user_ip(&event), normalized("81.2.69.142"));
+    }
+}
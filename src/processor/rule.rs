@@ -966,6 +966,7 @@ mod tests {
                 ],
                 errors: vec![],
                 original_length: Some(142),
+                original_value: None,
                 path: None,
             }
         );
@@ -978,6 +979,7 @@ mod tests {
                 remarks: vec![Remark::new(RemarkType::Removed, "remove_foo")],
                 errors: vec![],
                 original_length: None,
+                original_value: None,
                 path: None,
             }
         );
@@ -990,6 +992,7 @@ mod tests {
                 remarks: vec![Remark::new(RemarkType::Removed, "remove_ip")],
                 errors: vec![],
                 original_length: None,
+                original_value: None,
                 path: None,
             }
         );
@@ -1124,6 +1127,7 @@ mod tests {
                 ],
                 errors: vec![],
                 original_length: Some(62),
+                original_value: None,
                 path: None,
             }
         );
@@ -1236,6 +1240,7 @@ mod tests {
                 ],
                 errors: vec![],
                 original_length: Some(62),
+                original_value: None,
                 path: None,
             }
         );
@@ -0,0 +1,922 @@
+//! Implements path selectors for reading and writing values in annotated trees.
+//!
+//! A selector is a dot-separated path such as `exception.values.*.stacktrace.frames.*.function`.
+//! Each segment is a field name, a map key or an array index, and `*` matches all children of a
+//! value. Keys that are not fields of a struct are looked up in its flattened `other` map.
+//!
+//! There is no escape syntax for segments, so map keys containing a `.` or consisting of a single
+//! `*` cannot be selected individually. They are only matched by wildcards.
+use std::any::Any;
+use std::collections::Bound;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use debugid::DebugId;
+use uuid::Uuid;
+
+use protocol::{Addr, Annotated, Array, Level, Map, Meta, RegVal, ThreadId, Value, Values};
+
+use super::visit::{format_path, PathItem};
+
+/// An error returned when parsing or applying a selector.
+#[derive(Debug, Fail, PartialEq)]
+pub enum SelectorError {
+    /// The selector contains an empty segment.
+    #[fail(display = "invalid selector")]
+    InvalidSelector,
+    /// The path contains a wildcard where only concrete paths are allowed.
+    #[fail(display = "wildcards are not allowed in paths")]
+    Wildcard,
+    /// An intermediate value could not be created.
+    #[fail(display = "cannot create value at path")]
+    CannotCreate,
+    /// The value at the path has a different type.
+    #[fail(display = "value at path has a different type")]
+    TypeMismatch,
+}
+
+/// A segment of a selector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// A field name, map key or array index.
+    Key(String),
+    /// Matches all children.
+    Wildcard,
+}
+
+/// A parsed selector for values in an annotated tree.
+///
+/// The selector `.` matches the root value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    segments: Vec<Segment>,
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if string == "." {
+            return Ok(Selector {
+                segments: Vec::new(),
+            });
+        }
+
+        let segments = string
+            .split('.')
+            .map(|segment| match segment {
+                "" => Err(SelectorError::InvalidSelector),
+                "*" => Ok(Segment::Wildcard),
+                key => Ok(Segment::Key(key.to_string())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Selector { segments })
+    }
+}
+
+/// A type-erased annotated value that can be traversed by selectors.
+pub trait AnyAnnotated {
+    /// Returns the meta data of this value.
+    fn meta(&self) -> &Meta;
+
+    /// Returns a mutable reference to the meta data of this value.
+    fn meta_mut(&mut self) -> &mut Meta;
+
+    /// Returns the actual value, if set.
+    fn value(&self) -> Option<&SelectAnnotatedValue>;
+
+    /// Returns a mutable reference to the actual value, if set.
+    fn value_mut(&mut self) -> Option<&mut SelectAnnotatedValue>;
+
+    /// Returns a mutable reference to the actual value, creating an empty value if none is set.
+    fn value_or_insert(&mut self) -> Option<&mut SelectAnnotatedValue>;
+
+    /// Takes the actual value out of this annotated value, leaving no value.
+    ///
+    /// This is used to roll back `Annotated::set_path` with `restore_value`.
+    fn take_value(&mut self) -> Box<Any>;
+
+    /// Restores a value previously returned by `take_value`.
+    fn restore_value(&mut self, value: Box<Any>);
+
+    /// Returns this value as `Any` to downcast it to `Annotated<T>`.
+    fn as_any(&self) -> &Any;
+
+    /// Returns this value as mutable `Any` to downcast it to `Annotated<T>`.
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<'a> AnyAnnotated + 'a {
+    /// Returns a reference to the annotated value if it is of type `Annotated<T>`.
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&Annotated<T>> {
+        self.as_any().downcast_ref()
+    }
+
+    /// Returns a mutable reference to the annotated value if it is of type `Annotated<T>`.
+    pub fn downcast_mut<T: 'static>(&mut self) -> Option<&mut Annotated<T>> {
+        self.as_any_mut().downcast_mut()
+    }
+}
+
+impl<T: SelectAnnotatedValue + 'static> AnyAnnotated for Annotated<T> {
+    fn meta(&self) -> &Meta {
+        &self.1
+    }
+
+    fn meta_mut(&mut self) -> &mut Meta {
+        &mut self.1
+    }
+
+    fn value(&self) -> Option<&SelectAnnotatedValue> {
+        self.0.as_ref().map(|x| x as &SelectAnnotatedValue)
+    }
+
+    fn value_mut(&mut self) -> Option<&mut SelectAnnotatedValue> {
+        self.0.as_mut().map(|x| x as &mut SelectAnnotatedValue)
+    }
+
+    fn value_or_insert(&mut self) -> Option<&mut SelectAnnotatedValue> {
+        if self.0.is_none() {
+            self.0 = T::create();
+        }
+        AnyAnnotated::value_mut(self)
+    }
+
+    fn take_value(&mut self) -> Box<Any> {
+        Box::new(self.0.take())
+    }
+
+    fn restore_value(&mut self, value: Box<Any>) {
+        if let Ok(value) = value.downcast::<Option<T>>() {
+            self.0 = *value;
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+/// A trait implemented for types whose children can be selected by key.
+pub trait SelectAnnotatedValue {
+    /// Returns the child with the given key along with its path item.
+    fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+        let _key = key;
+        None
+    }
+
+    /// Returns a mutable reference to the child with the given key along with its path item.
+    fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+        let _key = key;
+        None
+    }
+
+    /// Returns all children along with their path items.
+    fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+        Vec::new()
+    }
+
+    /// Returns mutable references to all children along with their path items.
+    fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+        Vec::new()
+    }
+
+    /// Returns the child with the given key, inserting an empty child if possible.
+    fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+        self.select_child_mut(key).map(|(_, child)| child)
+    }
+
+    /// Removes the child with the given key, if it can be removed.
+    ///
+    /// This is used to roll back `insert_child` in `Annotated::set_path`.
+    fn remove_child(&mut self, key: &str) {
+        let _key = key;
+    }
+
+    /// Returns `true` if `insert_child` has to create this value before inserting children.
+    ///
+    /// This is the case for empty options.
+    fn needs_create(&self) -> bool {
+        false
+    }
+
+    /// Creates an empty value that can hold children.
+    ///
+    /// This is used to create intermediate values in `Annotated::set_path`.
+    fn create() -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
+
+macro_rules! impl_primitive_select {
+    ($($ty:ty),*) => {
+        $(
+            impl SelectAnnotatedValue for $ty {}
+        )*
+    };
+}
+
+impl_primitive_select!(bool, u32, i32, u64, i64, f32, f64, String);
+impl_primitive_select!(Addr, RegVal, Level, ThreadId, Uuid, DebugId, DateTime<Utc>);
+impl_primitive_select!((u64, u64), Vec<String>);
+
+impl<T: SelectAnnotatedValue> SelectAnnotatedValue for Option<T> {
+    fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+        self.as_ref().and_then(|x| x.select_child(key))
+    }
+
+    fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+        self.as_mut().and_then(|x| x.select_child_mut(key))
+    }
+
+    fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+        self.as_ref()
+            .map(|x| x.select_children())
+            .unwrap_or_default()
+    }
+
+    fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+        self.as_mut()
+            .map(|x| x.select_children_mut())
+            .unwrap_or_default()
+    }
+
+    fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+        if self.is_none() {
+            *self = T::create();
+        }
+        self.as_mut().and_then(|x| x.insert_child(key))
+    }
+
+    fn remove_child(&mut self, key: &str) {
+        if let Some(ref mut x) = *self {
+            x.remove_child(key);
+        }
+    }
+
+    fn needs_create(&self) -> bool {
+        self.is_none()
+    }
+
+    fn create() -> Option<Self> {
+        Some(T::create())
+    }
+}
+
+impl<T: SelectAnnotatedValue> SelectAnnotatedValue for Box<T> {
+    fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+        (**self).select_child(key)
+    }
+
+    fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+        (**self).select_child_mut(key)
+    }
+
+    fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+        (**self).select_children()
+    }
+
+    fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+        (**self).select_children_mut()
+    }
+
+    fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+        (**self).insert_child(key)
+    }
+
+    fn remove_child(&mut self, key: &str) {
+        (**self).remove_child(key)
+    }
+
+    fn needs_create(&self) -> bool {
+        (**self).needs_create()
+    }
+
+    fn create() -> Option<Self> {
+        T::create().map(Box::new)
+    }
+}
+
+impl<T: SelectAnnotatedValue + 'static> SelectAnnotatedValue for Values<T> {
+    fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+        match key {
+            "values" => Some((PathItem::Key("values"), &self.values as &AnyAnnotated)),
+            _ => AnyAnnotated::value(&self.other).and_then(|x| x.select_child(key)),
+        }
+    }
+
+    fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+        match key {
+            "values" => Some((
+                PathItem::Key("values"),
+                &mut self.values as &mut AnyAnnotated,
+            )),
+            _ => AnyAnnotated::value_mut(&mut self.other).and_then(|x| x.select_child_mut(key)),
+        }
+    }
+
+    fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+        let mut children = vec![(PathItem::Key("values"), &self.values as &AnyAnnotated)];
+        if let Some(other) = AnyAnnotated::value(&self.other) {
+            children.extend(other.select_children());
+        }
+        children
+    }
+
+    fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+        let mut children = vec![(
+            PathItem::Key("values"),
+            &mut self.values as &mut AnyAnnotated,
+        )];
+        if let Some(other) = AnyAnnotated::value_mut(&mut self.other) {
+            children.extend(other.select_children_mut());
+        }
+        children
+    }
+
+    fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+        match key {
+            "values" => Some(&mut self.values as &mut AnyAnnotated),
+            _ => AnyAnnotated::value_or_insert(&mut self.other).and_then(|x| x.insert_child(key)),
+        }
+    }
+
+    fn remove_child(&mut self, key: &str) {
+        if let Some(other) = AnyAnnotated::value_mut(&mut self.other) {
+            other.remove_child(key);
+        }
+    }
+
+    fn create() -> Option<Self> {
+        Some(Values::new())
+    }
+}
+
+impl<T: SelectAnnotatedValue + 'static> SelectAnnotatedValue for Array<T> {
+    fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+        let index: usize = key.parse().ok()?;
+        let child = self.get(index)?;
+        Some((PathItem::Index(index), child as &AnyAnnotated))
+    }
+
+    fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+        let index: usize = key.parse().ok()?;
+        let child = self.get_mut(index)?;
+        Some((PathItem::Index(index), child as &mut AnyAnnotated))
+    }
+
+    fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+        self.iter()
+            .enumerate()
+            .map(|(index, child)| (PathItem::Index(index), child as &AnyAnnotated))
+            .collect()
+    }
+
+    fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+        self.iter_mut()
+            .enumerate()
+            .map(|(index, child)| (PathItem::Index(index), child as &mut AnyAnnotated))
+            .collect()
+    }
+
+    /// Returns the element at the given index, appending an element if the index is the length
+    /// of the array.
+    fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+        let index: usize = key.parse().ok()?;
+        if index == self.len() {
+            self.push(Annotated::empty());
+        }
+        self.get_mut(index).map(|child| child as &mut AnyAnnotated)
+    }
+
+    fn remove_child(&mut self, key: &str) {
+        if let Ok(index) = key.parse::<usize>() {
+            if index < self.len() {
+                self.remove(index);
+            }
+        }
+    }
+
+    fn create() -> Option<Self> {
+        Some(Array::new())
+    }
+}
+
+impl<T: SelectAnnotatedValue + 'static> SelectAnnotatedValue for Map<T> {
+    fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+        self.range::<str, _>((Bound::Included(key), Bound::Included(key)))
+            .next()
+            .map(|(key, child)| (PathItem::Key(key), child as &AnyAnnotated))
+    }
+
+    fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+        self.range_mut::<str, _>((Bound::Included(key), Bound::Included(key)))
+            .next()
+            .map(|(key, child)| (PathItem::Key(key), child as &mut AnyAnnotated))
+    }
+
+    fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+        self.iter()
+            .map(|(key, child)| (PathItem::Key(key), child as &AnyAnnotated))
+            .collect()
+    }
+
+    fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+        self.iter_mut()
+            .map(|(key, child)| (PathItem::Key(key), child as &mut AnyAnnotated))
+            .collect()
+    }
+
+    fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+        let child = self.entry(key.to_string()).or_insert_with(Annotated::empty);
+        Some(child as &mut AnyAnnotated)
+    }
+
+    fn remove_child(&mut self, key: &str) {
+        self.remove(key);
+    }
+
+    fn create() -> Option<Self> {
+        Some(Map::new())
+    }
+}
+
+impl SelectAnnotatedValue for Value {
+    fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+        match *self {
+            Value::Array(ref items) => items.select_child(key),
+            Value::Map(ref map) => map.select_child(key),
+            _ => None,
+        }
+    }
+
+    fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+        match *self {
+            Value::Array(ref mut items) => items.select_child_mut(key),
+            Value::Map(ref mut map) => map.select_child_mut(key),
+            _ => None,
+        }
+    }
+
+    fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+        match *self {
+            Value::Array(ref items) => items.select_children(),
+            Value::Map(ref map) => map.select_children(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+        match *self {
+            Value::Array(ref mut items) => items.select_children_mut(),
+            Value::Map(ref mut map) => map.select_children_mut(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+        match *self {
+            Value::Array(ref mut items) => items.insert_child(key),
+            Value::Map(ref mut map) => map.insert_child(key),
+            _ => None,
+        }
+    }
+
+    fn remove_child(&mut self, key: &str) {
+        match *self {
+            Value::Array(ref mut items) => items.remove_child(key),
+            Value::Map(ref mut map) => map.remove_child(key),
+            _ => (),
+        }
+    }
+
+    /// Creates an empty map, which is used for intermediate values.
+    fn create() -> Option<Self> {
+        Some(Value::Map(Map::new()))
+    }
+}
+
+/// A value matched by a selector.
+pub struct Selected<'a> {
+    /// The concrete path to the value.
+    pub path: Vec<PathItem<'a>>,
+    /// The matched value.
+    pub value: &'a AnyAnnotated,
+}
+
+impl<'a> Selected<'a> {
+    /// Returns the path as dot-separated string.
+    pub fn path_string(&self) -> String {
+        format_path(&self.path)
+    }
+}
+
+/// A mutable value matched by a selector.
+pub struct SelectedMut<'a> {
+    /// The concrete path to the value.
+    pub path: Vec<PathItem<'a>>,
+    /// The matched value.
+    pub value: &'a mut AnyAnnotated,
+}
+
+impl<'a> SelectedMut<'a> {
+    /// Returns the path as dot-separated string.
+    pub fn path_string(&self) -> String {
+        format_path(&self.path)
+    }
+}
+
+fn select_recursive<'a>(
+    value: &'a AnyAnnotated,
+    path: Vec<PathItem<'a>>,
+    segments: &[Segment],
+    selected: &mut Vec<Selected<'a>>,
+) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            selected.push(Selected { path, value });
+            return;
+        }
+    };
+
+    let value = match value.value() {
+        Some(value) => value,
+        None => return,
+    };
+
+    match *segment {
+        Segment::Key(ref key) => {
+            if let Some((item, child)) = value.select_child(key) {
+                let mut path = path;
+                path.push(item);
+                select_recursive(child, path, rest, selected);
+            }
+        }
+        Segment::Wildcard => {
+            for (item, child) in value.select_children() {
+                let mut path = path.clone();
+                path.push(item);
+                select_recursive(child, path, rest, selected);
+            }
+        }
+    }
+}
+
+fn select_recursive_mut<'a>(
+    value: &'a mut AnyAnnotated,
+    path: Vec<PathItem<'a>>,
+    segments: &[Segment],
+    selected: &mut Vec<SelectedMut<'a>>,
+) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            selected.push(SelectedMut { path, value });
+            return;
+        }
+    };
+
+    let value = match value.value_mut() {
+        Some(value) => value,
+        None => return,
+    };
+
+    match *segment {
+        Segment::Key(ref key) => {
+            if let Some((item, child)) = value.select_child_mut(key) {
+                let mut path = path;
+                path.push(item);
+                select_recursive_mut(child, path, rest, selected);
+            }
+        }
+        Segment::Wildcard => {
+            for (item, child) in value.select_children_mut() {
+                let mut path = path.clone();
+                path.push(item);
+                select_recursive_mut(child, path, rest, selected);
+            }
+        }
+    }
+}
+
+/// Returns the keys of a path, which must not contain wildcards.
+fn path_keys(path: &str) -> Result<Vec<String>, SelectorError> {
+    let selector: Selector = path.parse()?;
+    selector
+        .segments
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Key(key) => Ok(key),
+            Segment::Wildcard => Err(SelectorError::Wildcard),
+        })
+        .collect()
+}
+
+impl<T: SelectAnnotatedValue + 'static> Annotated<T> {
+    /// Returns all values matching the selector along with their concrete paths.
+    pub fn select(&self, selector: &str) -> Result<Vec<Selected>, SelectorError> {
+        let selector: Selector = selector.parse()?;
+        let mut selected = Vec::new();
+        select_recursive(self, Vec::new(), &selector.segments, &mut selected);
+        Ok(selected)
+    }
+
+    /// Returns mutable references to all values matching the selector along with their concrete
+    /// paths.
+    pub fn select_mut(&mut self, selector: &str) -> Result<Vec<SelectedMut>, SelectorError> {
+        let selector: Selector = selector.parse()?;
+        let mut selected = Vec::new();
+        select_recursive_mut(self, Vec::new(), &selector.segments, &mut selected);
+        Ok(selected)
+    }
+
+    /// Returns the value at the given path.
+    ///
+    /// Returns `None` if the path does not exist or contains wildcards.
+    pub fn get_path(&self, path: &str) -> Option<&AnyAnnotated> {
+        let mut value = self as &AnyAnnotated;
+        for key in path_keys(path).ok()? {
+            value = value.value()?.select_child(&key)?.1;
+        }
+        Some(value)
+    }
+
+    /// Returns a mutable reference to the value at the given path.
+    ///
+    /// Returns `None` if the path does not exist or contains wildcards.
+    pub fn get_path_mut(&mut self, path: &str) -> Option<&mut AnyAnnotated> {
+        let mut value = self as &mut AnyAnnotated;
+        for key in path_keys(path).ok()? {
+            value = value.value_mut()?.select_child_mut(&key)?.1;
+        }
+        Some(value)
+    }
+
+    /// Sets the value at the given path, creating intermediate values as needed.
+    ///
+    /// The value must have the same type as the field at the path. Missing struct fields, map
+    /// entries and array elements at the end of an array are created on the way. If the value
+    /// cannot be set, all intermediate values are removed again and this value is left unchanged.
+    pub fn set_path<V: 'static>(
+        &mut self,
+        path: &str,
+        annotated: Annotated<V>,
+    ) -> Result<(), SelectorError> {
+        let keys = path_keys(path)?;
+        let (depth, change) = first_change(self, &keys);
+
+        let mut value = self as &mut AnyAnnotated;
+        for key in &keys[..depth] {
+            value = value
+                .value_mut()
+                .and_then(|value| value.select_child_mut(key))
+                .ok_or(SelectorError::CannotCreate)?
+                .1;
+        }
+
+        match change {
+            Some(Change::Value) => {
+                let original = value.take_value();
+                let result = insert_path(value, &keys[depth..], annotated);
+                if result.is_err() {
+                    value.restore_value(original);
+                }
+                result
+            }
+            Some(Change::Child) => {
+                let result = insert_path(value, &keys[depth..], annotated);
+                if result.is_err() {
+                    if let Some(value) = value.value_mut() {
+                        value.remove_child(&keys[depth]);
+                    }
+                }
+                result
+            }
+            None => insert_path(value, &[], annotated),
+        }
+    }
+}
+
+/// The first change to an annotated tree when inserting a path.
+enum Change {
+    /// The value has to be created.
+    Value,
+    /// The value exists, but the child has to be inserted.
+    Child,
+}
+
+/// Returns the depth of the first value that changes when inserting the given path.
+///
+/// All further changes are made below this value, so undoing this change rolls back the entire
+/// insertion. Returns `None` if the full path already exists.
+fn first_change(value: &AnyAnnotated, keys: &[String]) -> (usize, Option<Change>) {
+    let mut value = value;
+    for (depth, key) in keys.iter().enumerate() {
+        let inner = match value.value() {
+            Some(inner) if !inner.needs_create() => inner,
+            _ => return (depth, Some(Change::Value)),
+        };
+
+        value = match inner.select_child(key) {
+            Some((_, child)) => child,
+            None => return (depth, Some(Change::Child)),
+        };
+    }
+
+    (keys.len(), None)
+}
+
+/// Sets the value at the given keys below `value`, creating intermediate values as needed.
+fn insert_path<V: 'static>(
+    value: &mut AnyAnnotated,
+    keys: &[String],
+    annotated: Annotated<V>,
+) -> Result<(), SelectorError> {
+    let mut value = value;
+    for key in keys {
+        value = value
+            .value_or_insert()
+            .and_then(|value| value.insert_child(key))
+            .ok_or(SelectorError::CannotCreate)?;
+    }
+
+    match value.downcast_mut::<V>() {
+        Some(target) => {
+            *target = annotated;
+            Ok(())
+        }
+        None => Err(SelectorError::TypeMismatch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutils::event;
+
+    fn paths(selected: &[Selected]) -> Vec<String> {
+        selected.iter().map(Selected::path_string).collect()
+    }
+
+    #[test]
+    fn test_parse_selector() {
+        assert!(".".parse::<Selector>().is_ok());
+        assert_eq_dbg!(
+            "foo..bar".parse::<Selector>(),
+            Err(SelectorError::InvalidSelector)
+        );
+        assert_eq_dbg!("".parse::<Selector>(), Err(SelectorError::InvalidSelector));
+    }
+
+    #[test]
+    fn test_select_wildcards() {
+        let event = Annotated::from(event(
+            r#"{
+                "exception": {"values": [
+                    {"type": "A", "stacktrace": {"frames": [{"function": "a1"}, {"function": "a2"}]}},
+                    {"type": "B", "stacktrace": {"frames": [{"function": "b1"}]}}
+                ]}
+            }"#,
+        ));
+
+        let selected = event
+            .select("exception.values.*.stacktrace.frames.*.function")
+            .unwrap();
+        assert_eq_dbg!(
+            paths(&selected),
+            vec![
+                "exception.values.0.stacktrace.frames.0.function",
+                "exception.values.0.stacktrace.frames.1.function",
+                "exception.values.1.stacktrace.frames.0.function",
+            ]
+        );
+
+        let function = selected[2].value.downcast_ref::<Option<String>>().unwrap();
+        assert_eq_str!(function.value().unwrap().as_ref().unwrap(), "b1");
+    }
+
+    #[test]
+    fn test_select_other_and_values() {
+        let event = Annotated::from(event(r#"{"custom": {"nested": [1, {"deep": true}]}}"#));
+
+        let selected = event.select("custom.nested.1.deep").unwrap();
+        assert_eq_dbg!(paths(&selected), vec!["custom.nested.1.deep"]);
+        assert_eq_dbg!(
+            selected[0].value.downcast_ref::<Value>().unwrap().value(),
+            Some(&Value::Bool(true))
+        );
+
+        assert!(event.select("custom.missing").unwrap().is_empty());
+        assert!(event.get_path("custom.nested.2").is_none());
+    }
+
+    #[test]
+    fn test_select_mut() {
+        let mut event = Annotated::from(event(r#"{"tags": {"a": "1", "b": "2"}}"#));
+
+        for selected in event.select_mut("tags.*").unwrap() {
+            let tag = selected.value.downcast_mut::<String>().unwrap();
+            tag.set_value(Some("redacted".to_string()));
+        }
+
+        let value = event.get_path("tags.b").unwrap();
+        assert_eq_dbg!(
+            value.downcast_ref::<String>().unwrap().value(),
+            Some(&"redacted".to_string())
+        );
+    }
+
+    #[test]
+    fn test_set_path() {
+        let mut event = Annotated::from(event("{}"));
+
+        event
+            .set_path(
+                "request.env.REMOTE_ADDR",
+                Annotated::from(Value::String("127.0.0.1".to_string())),
+            )
+            .unwrap();
+        event
+            .set_path("extra.foo.bar", Annotated::from(Value::U64(42)))
+            .unwrap();
+        event
+            .set_path("user.id", Annotated::from(Some("42".to_string())))
+            .unwrap();
+
+        let event = event.value().unwrap();
+        let request = event.request.value().unwrap().as_ref().unwrap();
+        assert_eq_dbg!(
+            request.env.value().unwrap()["REMOTE_ADDR"],
+            Annotated::from(Value::String("127.0.0.1".to_string()))
+        );
+        assert_eq_str!(
+            event.extra.value().unwrap()["foo"].to_json().unwrap(),
+            r#"{"bar":42}"#
+        );
+
+        let user = event.user.value().unwrap().as_ref().unwrap();
+        assert_eq_str!(user.id.value().unwrap().as_ref().unwrap(), "42");
+    }
+
+    #[test]
+    fn test_set_path_errors() {
+        let mut event = Annotated::from(event(r#"{"message": "hello"}"#));
+
+        assert_eq_dbg!(
+            event.set_path("message", Annotated::from(42u64)),
+            Err(SelectorError::TypeMismatch)
+        );
+        assert_eq_dbg!(
+            event.set_path("message.foo", Annotated::from(42u64)),
+            Err(SelectorError::CannotCreate)
+        );
+        assert_eq_dbg!(
+            event.set_path("tags.*", Annotated::from("a".to_string())),
+            Err(SelectorError::Wildcard)
+        );
+    }
+
+    #[test]
+    fn test_set_path_rollback() {
+        let json = r#"{"extra":{"list":[]},"tags":{"a":"1"}}"#;
+        let mut event = Annotated::from(event(json));
+        let original = event.clone();
+
+        assert_eq_dbg!(
+            event.set_path("user.id", Annotated::from(42u64)),
+            Err(SelectorError::TypeMismatch)
+        );
+        assert_eq_dbg!(
+            event.set_path("request.env.foo.bar", Annotated::from(42u64)),
+            Err(SelectorError::TypeMismatch)
+        );
+        assert_eq_dbg!(
+            event.set_path("extra.foo.bar", Annotated::from(42u64)),
+            Err(SelectorError::TypeMismatch)
+        );
+        assert_eq_dbg!(
+            event.set_path("extra.list.0.foo", Annotated::from(42u64)),
+            Err(SelectorError::TypeMismatch)
+        );
+        assert_eq_dbg!(
+            event.set_path("custom.foo", Annotated::from(42u64)),
+            Err(SelectorError::TypeMismatch)
+        );
+        assert_eq_dbg!(
+            event.set_path("tags.b.c", Annotated::from("x".to_string())),
+            Err(SelectorError::CannotCreate)
+        );
+
+        assert_eq_dbg!(event, original);
+        assert_eq_str!(event.to_json().unwrap(), original.to_json().unwrap());
+    }
+}
@@ -0,0 +1,368 @@
+//! Normalization of stacktraces and frames.
+use protocol::{get_str, Annotated, Event, Frame, Remark, RemarkType, Stacktrace};
+
+/// The rule id used for frames marked in-app or not by the normalizer.
+const IN_APP_RULE_ID: &str = "@in_app";
+
+/// Path prefixes of system libraries and frameworks on Apple platforms.
+static COCOA_SYSTEM_PREFIXES: &[&str] =
+    &["/System/Library/", "/usr/lib/", "/Library/", "/Developer/"];
+
+/// Module prefixes of the .NET framework.
+static DOTNET_SYSTEM_PREFIXES: &[&str] = &["System.", "Microsoft."];
+
+/// Invokes the callback for every stacktrace in the event.
+///
+/// This covers the event stacktrace as well as stacktraces of all exceptions and threads, but
+/// not raw stacktraces.
+pub(crate) fn for_each_stacktrace<F>(event: &mut Event, mut f: F)
+where
+    F: FnMut(&mut Stacktrace),
+{
+    if let Some(Some(stacktrace)) = event.stacktrace.value_mut() {
+        f(stacktrace);
+    }
+
+    let exceptions = event
+        .exceptions
+        .value_mut()
+        .and_then(|e| e.values.value_mut());
+    for exception in exceptions.into_iter().flatten() {
+        if let Some(exception) = exception.value_mut() {
+            if let Some(Some(stacktrace)) = exception.stacktrace.value_mut() {
+                f(stacktrace);
+            }
+        }
+    }
+
+    let threads = event.threads.value_mut().and_then(|t| t.values.value_mut());
+    for thread in threads.into_iter().flatten() {
+        if let Some(thread) = thread.value_mut() {
+            if let Some(Some(stacktrace)) = thread.stacktrace.value_mut() {
+                f(stacktrace);
+            }
+        }
+    }
+}
+
+fn matches_prefix(value: &str, prefixes: &[String]) -> bool {
+    prefixes
+        .iter()
+        .any(|prefix| value.starts_with(prefix.as_str()))
+}
+
+/// Determines whether a frame is in-app based on platform specific heuristics.
+fn in_app_heuristic(frame: &Frame, platform: Option<&str>) -> Option<bool> {
+    let path = get_str(&frame.abs_path).or_else(|| get_str(&frame.filename));
+
+    match platform {
+        Some("cocoa") | Some("objc") | Some("swift") | Some("native") => {
+            let package = get_str(&frame.package)?;
+            if COCOA_SYSTEM_PREFIXES.iter().any(|p| package.starts_with(p))
+                || package.contains("/Frameworks/")
+            {
+                Some(false)
+            } else if package.contains("/Bundle/Application/") || package.contains(".app/") {
+                Some(true)
+            } else {
+                None
+            }
+        }
+        Some("javascript") | Some("node") => {
+            if path?.contains("/node_modules/") {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        Some("python") => {
+            let path = path?;
+            if path.contains("/site-packages/") || path.contains("/dist-packages/") {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        Some("csharp") => {
+            let module = get_str(&frame.module)?;
+            if DOTNET_SYSTEM_PREFIXES.iter().any(|p| module.starts_with(p)) {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Determines whether a frame is in-app.
+///
+/// Explicit include prefixes take precedence over exclude prefixes, which in turn take precedence
+/// over built-in platform heuristics. Prefixes are matched against the module and fall back to
+/// the package if the frame has no module.
+fn compute_in_app(
+    frame: &Frame,
+    platform: Option<&str>,
+    include: &[String],
+    exclude: &[String],
+) -> Option<bool> {
+    if let Some(module) = get_str(&frame.module).or_else(|| get_str(&frame.package)) {
+        if matches_prefix(module, include) {
+            return Some(true);
+        } else if matches_prefix(module, exclude) {
+            return Some(false);
+        }
+    }
+
+    in_app_heuristic(frame, platform)
+}
+
+/// Marks frames in-app that do not have an explicit `in_app` flag set by the client.
+pub(crate) fn normalize_in_app(
+    stacktrace: &mut Stacktrace,
+    platform: Option<&str>,
+    include: &[String],
+    exclude: &[String],
+) {
+    let frames = stacktrace.frames.value_mut().into_iter().flatten();
+    for frame in frames.filter_map(Annotated::value_mut) {
+        if frame.in_app.value().map_or(false, Option::is_some) {
+            continue;
+        }
+
+        if let Some(in_app) = compute_in_app(frame, platform, include, exclude) {
+            frame.in_app.set_value(Some(Some(in_app)));
+            frame
+                .in_app
+                .meta_mut()
+                .remarks_mut()
+                .push(Remark::new(RemarkType::Substituted, IN_APP_RULE_ID));
+        }
+    }
+}
+
+/// Distributes a global frame budget fairly across stacktraces.
+///
+/// Every stacktrace receives at most `limit` frames. Stacktraces with fewer frames than their
+/// fair share leave the remainder of the budget to larger stacktraces.
+pub(crate) fn frame_allowances(lengths: &[usize], limit: usize, budget: usize) -> Vec<usize> {
+    let mut indices: Vec<_> = (0..lengths.len()).collect();
+    indices.sort_by_key(|&i| lengths[i]);
+
+    let mut allowances = vec![0; lengths.len()];
+    let mut remaining = budget;
+    for (count, &index) in indices.iter().enumerate() {
+        let share = remaining / (lengths.len() - count);
+        let allowance = lengths[index].min(limit).min(share);
+        allowances[index] = allowance;
+        remaining -= allowance;
+    }
+
+    allowances
+}
+
+/// Removes frames from the middle of a stacktrace if it exceeds the limit.
+///
+/// The outermost and innermost frames are retained. The range of removed frames is written to
+/// `frames_omitted` and the original number of frames is recorded in the meta data.
+///
+/// If the client already omitted frames, the removed frames are moved next to that gap so that
+/// both can be recorded as a single range of the original stacktrace.
+pub(crate) fn trim_frames(stacktrace: &mut Stacktrace, limit: usize) {
+    let existing = match stacktrace.frames_omitted.value() {
+        Some(&Some((start, end))) if start <= end => Some((start as usize, end as usize)),
+        _ => None,
+    };
+
+    let omitted = match stacktrace.frames {
+        Annotated(Some(ref mut frames), ref mut meta) if frames.len() > limit => {
+            let original_length = frames.len();
+            let removed = original_length - limit;
+            let mut start = limit / 2;
+            if let Some((gap, _)) = existing {
+                let gap = gap.min(original_length);
+                start = start.max(gap.saturating_sub(removed)).min(gap).min(limit);
+            }
+            let end = start + removed;
+            frames.drain(start..end);
+
+            if meta.original_length().is_none() {
+                meta.set_original_length(Some(original_length as u32));
+            }
+
+            match existing {
+                Some((gap_start, gap_end)) => (start as u64, (end + gap_end - gap_start) as u64),
+                None => (start as u64, end as u64),
+            }
+        }
+        _ => return,
+    };
+
+    stacktrace.frames_omitted.set_value(Some(Some(omitted)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(json: &str) -> Frame {
+        Annotated::<Frame>::from_json(json).unwrap().0.unwrap()
+    }
+
+    fn in_app(frame: &Frame, platform: &str, include: &[&str], exclude: &[&str]) -> Option<bool> {
+        let include: Vec<_> = include.iter().map(|s| s.to_string()).collect();
+        let exclude: Vec<_> = exclude.iter().map(|s| s.to_string()).collect();
+        compute_in_app(frame, Some(platform), &include, &exclude)
+    }
+
+    #[test]
+    fn test_include_exclude() {
+        let frame = frame(r#"{"module": "myapp.views"}"#);
+        assert_eq_dbg!(in_app(&frame, "python", &["myapp"], &[]), Some(true));
+        assert_eq_dbg!(in_app(&frame, "python", &[], &["myapp."]), Some(false));
+        assert_eq_dbg!(in_app(&frame, "python", &["myapp"], &["myapp"]), Some(true));
+        assert_eq_dbg!(in_app(&frame, "python", &["other"], &[]), None);
+    }
+
+    #[test]
+    fn test_cocoa() {
+        let system = frame(r#"{"package": "/System/Library/Frameworks/UIKit.framework/UIKit"}"#);
+        assert_eq_dbg!(in_app(&system, "cocoa", &[], &[]), Some(false));
+
+        let app =
+            frame(r#"{"package": "/private/var/containers/Bundle/Application/AB12/Foo.app/Foo"}"#);
+        assert_eq_dbg!(in_app(&app, "cocoa", &[], &[]), Some(true));
+
+        let framework = frame(
+            r#"{"package": "/private/var/containers/Bundle/Application/AB12/Foo.app/Frameworks/Bar.framework/Bar"}"#,
+        );
+        assert_eq_dbg!(in_app(&framework, "cocoa", &[], &[]), Some(false));
+    }
+
+    #[test]
+    fn test_javascript() {
+        let vendor = frame(r#"{"abs_path": "/app/node_modules/express/lib/router.js"}"#);
+        assert_eq_dbg!(in_app(&vendor, "node", &[], &[]), Some(false));
+
+        let app = frame(r#"{"abs_path": "/app/src/index.js"}"#);
+        assert_eq_dbg!(in_app(&app, "javascript", &[], &[]), None);
+    }
+
+    #[test]
+    fn test_python() {
+        let vendor = frame(r#"{"abs_path": "/usr/lib/python3/site-packages/django/core.py"}"#);
+        assert_eq_dbg!(in_app(&vendor, "python", &[], &[]), Some(false));
+    }
+
+    #[test]
+    fn test_dotnet() {
+        let system = frame(r#"{"module": "System.Threading.Tasks"}"#);
+        assert_eq_dbg!(in_app(&system, "csharp", &[], &[]), Some(false));
+
+        let microsoft = frame(r#"{"module": "Microsoft.AspNetCore.Mvc"}"#);
+        assert_eq_dbg!(in_app(&microsoft, "csharp", &[], &[]), Some(false));
+    }
+
+    #[test]
+    fn test_frame_allowances() {
+        assert_eq_dbg!(frame_allowances(&[10, 20], 100, 1000), vec![10, 20]);
+        assert_eq_dbg!(frame_allowances(&[10, 200], 100, 1000), vec![10, 100]);
+        assert_eq_dbg!(
+            frame_allowances(&[500, 10, 500], 1000, 210),
+            vec![100, 10, 100]
+        );
+        assert_eq_dbg!(frame_allowances(&[], 100, 1000), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_trim_frames() {
+        let frames: Vec<_> = (0..10).map(|i| format!(r#"{{"lineno": {}}}"#, i)).collect();
+        let json = format!(r#"{{"frames": [{}]}}"#, frames.join(","));
+        let mut stacktrace = Annotated::<Stacktrace>::from_json(&json)
+            .unwrap()
+            .0
+            .unwrap();
+
+        trim_frames(&mut stacktrace, 5);
+
+        let lines: Vec<_> = stacktrace
+            .frames
+            .value()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.value().unwrap().line.value().cloned().unwrap())
+            .collect();
+        assert_eq_dbg!(lines, vec![Some(0), Some(1), Some(7), Some(8), Some(9)]);
+        assert_eq_dbg!(stacktrace.frames.meta().original_length(), Some(10));
+        assert_eq_dbg!(stacktrace.frames_omitted, Annotated::from(Some((2, 7))));
+    }
+
+    #[test]
+    fn test_trim_frames_merges_omitted() {
+        let frames: Vec<_> = (0..10).map(|i| format!(r#"{{"lineno": {}}}"#, i)).collect();
+        let json = format!(
+            r#"{{"frames": [{}], "frames_omitted": [8, 20]}}"#,
+            frames.join(",")
+        );
+        let mut stacktrace = Annotated::<Stacktrace>::from_json(&json)
+            .unwrap()
+            .0
+            .unwrap();
+
+        trim_frames(&mut stacktrace, 5);
+
+        let lines: Vec<_> = stacktrace
+            .frames
+            .value()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.value().unwrap().line.value().cloned().unwrap())
+            .collect();
+        assert_eq_dbg!(lines, vec![Some(0), Some(1), Some(2), Some(8), Some(9)]);
+        assert_eq_dbg!(stacktrace.frames_omitted, Annotated::from(Some((3, 20))));
+    }
+
+    #[test]
+    fn test_trim_frames_below_limit() {
+        let mut stacktrace = Annotated::<Stacktrace>::from_json(r#"{"frames": [{}, {}]}"#)
+            .unwrap()
+            .0
+            .unwrap();
+
+        trim_frames(&mut stacktrace, 2);
+
+        assert_eq_dbg!(stacktrace.frames.value().unwrap().len(), 2);
+        assert_eq_dbg!(stacktrace.frames.meta().original_length(), None);
+        assert_eq_dbg!(stacktrace.frames_omitted, Annotated::from(None));
+    }
+
+    #[test]
+    fn test_keeps_explicit_in_app() {
+        let mut stacktrace = Annotated::<Stacktrace>::from_json(
+            r#"{"frames": [
+                {"module": "System.IO", "in_app": true},
+                {"module": "System.Linq"}
+            ]}"#,
+        )
+        .unwrap()
+        .0
+        .unwrap();
+
+        normalize_in_app(&mut stacktrace, Some("csharp"), &[], &[]);
+
+        let frames = stacktrace.frames.value().unwrap();
+        assert_eq_dbg!(
+            frames[0].value().unwrap().in_app,
+            Annotated::from(Some(true))
+        );
+        assert_eq_dbg!(
+            frames[1].value().unwrap().in_app,
+            Annotated::new(Some(false), {
+                let mut meta = ::protocol::Meta::default();
+                meta.remarks_mut()
+                    .push(Remark::new(RemarkType::Substituted, IN_APP_RULE_ID));
+                meta
+            })
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Validation of event tags.
+use std::mem;
+
+use protocol::{Annotated, ErrorKind, Event, Level, Map, MetaError, Remark, RemarkType, User};
+
+/// The rule id used for remarks emitted by tag validation.
+const TAGS_RULE_ID: &str = "@tags";
+
+/// The maximum number of characters in a tag key.
+const MAX_TAG_KEY_LENGTH: usize = 32;
+
+/// The maximum number of characters in a tag value.
+const MAX_TAG_VALUE_LENGTH: usize = 200;
+
+fn remark(ty: RemarkType) -> Remark {
+    Remark::new(ty, TAGS_RULE_ID)
+}
+
+/// The outcome of moving a reserved tag into its field.
+enum Reserved {
+    /// The tag key is not reserved.
+    No,
+    /// The tag was moved into its field, or the field already holds the same value.
+    Moved,
+    /// The field already holds a different value.
+    Conflict,
+}
+
+/// Returns whether a character is allowed in tag keys.
+fn is_valid_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ':' || c == '-'
+}
+
+/// Validates a tag value and returns the error if it is invalid.
+fn validate_value(value: &str) -> Option<MetaError> {
+    if value.chars().count() > MAX_TAG_VALUE_LENGTH {
+        Some(ErrorKind::ValueTooLong.into())
+    } else if value.trim().is_empty() || value.contains('\n') {
+        Some(MetaError::invalid("invalid tag value"))
+    } else {
+        None
+    }
+}
+
+/// Sets a field to the value of a reserved tag unless it already holds a different value.
+fn move_to<T: PartialEq>(annotated: &mut Annotated<Option<T>>, value: T) -> Reserved {
+    let existing = match annotated.value() {
+        Some(&Some(ref existing)) => Some(*existing == value),
+        Some(&None) => None,
+        None => return Reserved::Conflict,
+    };
+
+    match existing {
+        Some(true) => Reserved::Moved,
+        Some(false) => Reserved::Conflict,
+        None => {
+            annotated.set_value(Some(Some(value)));
+            annotated
+                .meta_mut()
+                .remarks_mut()
+                .push(remark(RemarkType::Substituted));
+            Reserved::Moved
+        }
+    }
+}
+
+/// Moves the value of the `user` tag into a field of the user.
+///
+/// The value may be prefixed with the field it describes, such as `email:jane@example.org`.
+/// Without a prefix, it is treated as the user id.
+fn move_to_user(user: &mut User, value: &str) -> Reserved {
+    let mut parts = value.splitn(2, ':');
+    let (field, value) = match (parts.next(), parts.next()) {
+        (Some("id"), Some(value)) => (&mut user.id, value),
+        (Some("email"), Some(value)) => (&mut user.email, value),
+        (Some("username"), Some(value)) => (&mut user.username, value),
+        (Some("ip"), Some(value)) => (&mut user.ip_address, value),
+        _ => (&mut user.id, value),
+    };
+
+    move_to(field, value.to_string())
+}
+
+/// Moves the `user` tag into the user interface.
+///
+/// The user is only created if the event does not have one and the value is moved.
+fn move_user_tag(event: &mut Event, value: &str) -> Reserved {
+    let user = match event.user.value_mut() {
+        Some(user) => user,
+        None => return Reserved::Conflict,
+    };
+
+    if let Some(ref mut user) = *user {
+        return move_to_user(user, value);
+    }
+
+    let mut new_user = User::default();
+    let reserved = move_to_user(&mut new_user, value);
+    if let Reserved::Moved = reserved {
+        *user = Some(new_user);
+    }
+
+    reserved
+}
+
+/// Moves a tag with a reserved key into the corresponding field of the event.
+fn move_reserved_tag(event: &mut Event, key: &str, value: &str) -> Reserved {
+    match key {
+        "release" => move_to(&mut event.release, value.to_string()),
+        "dist" => move_to(&mut event.dist, value.to_string()),
+        "environment" => move_to(&mut event.environment, value.to_string()),
+        "server_name" => move_to(&mut event.server_name, value.to_string()),
+        "level" => match value.parse::<Level>() {
+            Ok(level) => move_to(&mut event.level, level),
+            Err(_) => Reserved::Conflict,
+        },
+        "user" => move_user_tag(event, value),
+        _ => Reserved::No,
+    }
+}
+
+/// Removes the value of an invalid tag and records the error.
+fn drop_tag(mut value: Annotated<String>, error: MetaError) -> Annotated<String> {
+    value.set_value(None);
+    value.meta_mut().errors_mut().push(error);
+    value
+}
+
+/// Creates an error for a tag key that cannot be used.
+fn invalid_key(reason: &str) -> MetaError {
+    MetaError::new(ErrorKind::InvalidAttribute).with_data("reason", reason)
+}
+
+/// Validates the tags of an event.
+///
+/// Tags with reserved keys such as `release` or `environment` are moved into their fields. Tags
+/// with invalid keys or values are dropped, leaving an error in place of the value. Keys with
+/// invalid characters are renamed by replacing those characters with underscores, and the error
+/// is recorded on the renamed tag. If the new key is already taken, the tag is dropped and the
+/// error is recorded on the tags.
+pub(crate) fn normalize_tags(event: &mut Event) {
+    let tags = match event.tags.value_mut() {
+        Some(tags) => mem::replace(tags, Map::new()),
+        None => return,
+    };
+
+    let mut normalized = Map::new();
+    let mut renamed = Vec::new();
+
+    for (key, value) in tags {
+        let error = match value.value() {
+            Some(string) => validate_value(string),
+            None => {
+                normalized.insert(key, value);
+                continue;
+            }
+        };
+
+        if let Some(error) = error {
+            normalized.insert(key, drop_tag(value, error));
+            continue;
+        }
+
+        if key.is_empty() || key.chars().count() > MAX_TAG_KEY_LENGTH {
+            normalized.insert(key, drop_tag(value, invalid_key("invalid tag key")));
+            continue;
+        }
+
+        if !key.chars().all(is_valid_key_char) {
+            let new_key = key
+                .chars()
+                .map(|c| if is_valid_key_char(c) { c } else { '_' })
+                .collect::<String>();
+            renamed.push((new_key, value));
+            continue;
+        }
+
+        let reserved = match value.value() {
+            Some(string) => move_reserved_tag(event, &key, string),
+            None => Reserved::No,
+        };
+
+        match reserved {
+            Reserved::No => {
+                normalized.insert(key, value);
+            }
+            Reserved::Moved => (),
+            Reserved::Conflict => {
+                normalized.insert(key, drop_tag(value, invalid_key("reserved tag key")));
+            }
+        }
+    }
+
+    for (key, mut value) in renamed {
+        let error = invalid_key("invalid characters in tag key");
+        if normalized.contains_key(&key) {
+            event.tags.meta_mut().errors_mut().push(error);
+            continue;
+        }
+
+        value.meta_mut().errors_mut().push(error);
+        value
+            .meta_mut()
+            .remarks_mut()
+            .push(remark(RemarkType::Substituted));
+        normalized.insert(key, value);
+    }
+
+    event.tags.set_value(Some(normalized));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutils::event;
+
+    fn tags(event: &Event) -> &Map<String> {
+        event.tags.value().unwrap()
+    }
+
+    fn dropped(error: MetaError) -> Annotated<String> {
+        drop_tag(Annotated::empty(), error)
+    }
+
+    #[test]
+    fn test_valid_tags() {
+        let mut event = event(r#"{"tags": {"browser": "Firefox", "os.name": "Mac OS X"}}"#);
+        normalize_tags(&mut event);
+
+        assert_eq_dbg!(
+            tags(&event)["browser"],
+            Annotated::from("Firefox".to_string())
+        );
+        assert_eq_dbg!(
+            tags(&event)["os.name"],
+            Annotated::from("Mac OS X".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let long_value = "x".repeat(201);
+        let mut event = event(&format!(
+            r#"{{"tags": {{"long": "{}", "multiline": "a\nb", "empty": " "}}}}"#,
+            long_value
+        ));
+        normalize_tags(&mut event);
+
+        assert_eq_dbg!(
+            tags(&event)["long"],
+            dropped(ErrorKind::ValueTooLong.into())
+        );
+        assert_eq_dbg!(
+            tags(&event)["multiline"],
+            dropped(MetaError::invalid("invalid tag value"))
+        );
+        assert_eq_dbg!(
+            tags(&event)["empty"],
+            dropped(MetaError::invalid("invalid tag value"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let mut event = event(
+            r#"{"tags": {
+                "this-key-is-far-too-long-to-be-a-tag-key": "value",
+                "my tag": "value"
+            }}"#,
+        );
+        normalize_tags(&mut event);
+
+        let tags = tags(&event);
+        assert_eq_dbg!(
+            tags["this-key-is-far-too-long-to-be-a-tag-key"],
+            dropped(invalid_key("invalid tag key"))
+        );
+        assert!(!tags.contains_key("my tag"));
+
+        let mut renamed = Annotated::from("value".to_string());
+        renamed
+            .meta_mut()
+            .errors_mut()
+            .push(invalid_key("invalid characters in tag key"));
+        renamed
+            .meta_mut()
+            .remarks_mut()
+            .push(remark(RemarkType::Substituted));
+        assert_eq_dbg!(tags["my_tag"], renamed);
+    }
+
+    #[test]
+    fn test_renamed_key_conflict() {
+        let mut event = event(r#"{"tags": {"my tag": "renamed", "my_tag": "value"}}"#);
+        normalize_tags(&mut event);
+
+        let tags = tags(&event);
+        assert_eq_dbg!(tags.len(), 1);
+        assert_eq_dbg!(tags["my_tag"], Annotated::from("value".to_string()));
+        let error = invalid_key("invalid characters in tag key");
+        let errors = event.tags.meta().errors().collect::<Vec<_>>();
+        assert_eq_dbg!(errors, vec![&error]);
+    }
+
+    #[test]
+    fn test_reserved_keys() {
+        let mut event = event(
+            r#"{
+                "environment": "production",
+                "tags": {
+                    "release": "1.0.0",
+                    "environment": "production",
+                    "level": "warning",
+                    "user": "email:jane@example.org"
+                }
+            }"#,
+        );
+        normalize_tags(&mut event);
+
+        assert!(tags(&event).is_empty());
+        assert_eq_str!(event.release.value().unwrap().as_ref().unwrap(), "1.0.0");
+        assert_eq_dbg!(event.level.value(), Some(&Some(Level::Warning)));
+        assert!(event.environment.meta().is_empty());
+
+        let user = event.user.value().unwrap().as_ref().unwrap();
+        assert_eq_dbg!(
+            user.email.value(),
+            Some(&Some("jane@example.org".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_user_tag_conflict() {
+        let mut event = event(r#"{"user": {"id": "1"}, "tags": {"user": "2"}}"#);
+        normalize_tags(&mut event);
+
+        assert_eq_dbg!(
+            tags(&event)["user"],
+            dropped(invalid_key("reserved tag key"))
+        );
+        let user = event.user.value().unwrap().as_ref().unwrap();
+        assert_eq_str!(user.id.value().unwrap().as_ref().unwrap(), "1");
+    }
+
+    #[test]
+    fn test_user_tag_creates_user() {
+        let mut event = event(r#"{"tags": {"user": "2"}}"#);
+        normalize_tags(&mut event);
+
+        assert!(tags(&event).is_empty());
+        let user = event.user.value().unwrap().as_ref().unwrap();
+        assert_eq_str!(user.id.value().unwrap().as_ref().unwrap(), "2");
+    }
+
+    #[test]
+    fn test_reserved_key_conflict() {
+        let mut event = event(r#"{"release": "1.0.0", "tags": {"release": "2.0.0"}}"#);
+        normalize_tags(&mut event);
+
+        assert_eq_dbg!(
+            tags(&event)["release"],
+            dropped(invalid_key("reserved tag key"))
+        );
+        assert_eq_str!(event.release.value().unwrap().as_ref().unwrap(), "1.0.0");
+    }
+}
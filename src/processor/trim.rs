@@ -0,0 +1,405 @@
+//! Trimming of events that exceed a size budget.
+use std::cmp::Reverse;
+
+use serde::Serialize;
+use serde_json;
+
+use protocol::{Annotated, Array, Event, Meta, Remark, RemarkType};
+
+use super::stacktrace;
+
+/// The rule id used for values removed to fit an event into its size budget.
+const TRIM_RULE_ID: &str = "@trimming";
+
+/// A part of an event that may be trimmed to reduce its size.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrimTarget {
+    /// Entries of the event's extra data.
+    Extra,
+    /// Breadcrumbs of the event.
+    Breadcrumbs,
+    /// Local variables of stack frames.
+    FrameVars,
+    /// The body, environment and cookies of the request.
+    RequestData,
+    /// Threads other than the crashed thread.
+    Threads,
+}
+
+impl TrimTarget {
+    /// Returns the default order in which parts of an event are trimmed.
+    pub fn default_order() -> Vec<TrimTarget> {
+        vec![
+            TrimTarget::Extra,
+            TrimTarget::Breadcrumbs,
+            TrimTarget::FrameVars,
+            TrimTarget::RequestData,
+            TrimTarget::Threads,
+        ]
+    }
+}
+
+/// The estimated size of an event compared to its budget.
+struct Budget {
+    size: usize,
+    max_size: usize,
+}
+
+impl Budget {
+    fn exceeded(&self) -> bool {
+        self.size > self.max_size
+    }
+}
+
+/// Returns the estimated size that meta data adds to an event when stored under the given key.
+///
+/// This covers the key, the meta data itself and the surrounding braces and separators in the
+/// `_meta` tree, but not the keys of parent values.
+fn meta_size(key: &str, meta: &Meta) -> usize {
+    if meta.is_empty() {
+        return 0;
+    }
+
+    let meta_len = serde_json::to_vec(meta).map(|json| json.len()).unwrap_or(0);
+    key.len() + meta_len + r#""":{"":},"#.len()
+}
+
+/// Returns the estimated size of a value, or zero if there is no value to remove.
+fn value_size<T: Serialize>(annotated: &Annotated<T>) -> usize {
+    match annotated.value() {
+        Some(_) => annotated.estimate_size(),
+        None => 0,
+    }
+}
+
+/// Selects the largest items for removal until the budget is met.
+///
+/// Returns a flag for every item indicating whether it should be removed.
+fn select_largest(sizes: &[usize], budget: &mut Budget) -> Vec<bool> {
+    let mut indices: Vec<_> = (0..sizes.len()).filter(|&i| sizes[i] > 0).collect();
+    indices.sort_by_key(|&i| Reverse(sizes[i]));
+
+    let mut selected = vec![false; sizes.len()];
+    for index in indices {
+        if !budget.exceeded() {
+            break;
+        }
+
+        selected[index] = true;
+        budget.size = budget.size.saturating_sub(sizes[index]);
+    }
+
+    selected
+}
+
+/// Removes a value and leaves a remark in its place.
+///
+/// The size of the value must already be deducted from the budget. The meta data that remains
+/// in place of the value is added to the budget again.
+fn remove_value<T>(annotated: &mut Annotated<T>, key: &str, budget: &mut Budget) {
+    if annotated.value().is_some() {
+        annotated.set_value(None);
+        annotated
+            .meta_mut()
+            .remarks_mut()
+            .push(Remark::new(RemarkType::Removed, TRIM_RULE_ID));
+        budget.size += meta_size(key, annotated.meta());
+    }
+}
+
+/// Removes the selected items from a list, and records a remark and its original length.
+///
+/// The sizes of the items must already be deducted from the budget. The meta data added to the
+/// list is added to the budget again.
+fn remove_items<T>(
+    annotated: &mut Annotated<Array<T>>,
+    key: &str,
+    selected: &[bool],
+    budget: &mut Budget,
+) {
+    if let Annotated(Some(ref mut items), ref mut meta) = *annotated {
+        let original_length = items.len();
+        let mut selected = selected.iter();
+        items.retain(|_| !selected.next().cloned().unwrap_or(false));
+
+        if items.len() != original_length {
+            let previous_size = meta_size(key, meta);
+            if meta.original_length().is_none() {
+                meta.set_original_length(Some(original_length as u32));
+            }
+            meta.remarks_mut()
+                .push(Remark::new(RemarkType::Removed, TRIM_RULE_ID));
+            budget.size = (budget.size + meta_size(key, meta)).saturating_sub(previous_size);
+        }
+    }
+}
+
+fn trim_extra(event: &mut Event, budget: &mut Budget) {
+    let extra = match event.extra.value_mut() {
+        Some(extra) => extra,
+        None => return,
+    };
+
+    let sizes: Vec<_> = extra.values().map(value_size).collect();
+    let selected = select_largest(&sizes, budget);
+    for ((key, value), selected) in extra.iter_mut().zip(selected) {
+        if selected {
+            remove_value(value, key, budget);
+        }
+    }
+}
+
+fn trim_breadcrumbs(event: &mut Event, budget: &mut Budget) {
+    let breadcrumbs = match event.breadcrumbs.value_mut() {
+        Some(breadcrumbs) => &mut breadcrumbs.values,
+        None => return,
+    };
+
+    let sizes: Vec<_> = match breadcrumbs.value() {
+        Some(items) => items.iter().map(value_size).collect(),
+        None => return,
+    };
+
+    let selected = select_largest(&sizes, budget);
+    remove_items(breadcrumbs, "values", &selected, budget);
+}
+
+fn trim_frame_vars(event: &mut Event, budget: &mut Budget) {
+    let mut sizes = Vec::new();
+    stacktrace::for_each_stacktrace(event, |stacktrace| {
+        let frames = stacktrace.frames.value().into_iter().flatten();
+        for frame in frames.filter_map(Annotated::value) {
+            sizes.push(value_size(&frame.vars));
+        }
+    });
+
+    let mut selected = select_largest(&sizes, budget).into_iter();
+    stacktrace::for_each_stacktrace(event, |stacktrace| {
+        let frames = stacktrace.frames.value_mut().into_iter().flatten();
+        for frame in frames.filter_map(Annotated::value_mut) {
+            if selected.next() == Some(true) {
+                remove_value(&mut frame.vars, "vars", budget);
+            }
+        }
+    });
+}
+
+fn trim_request_data(event: &mut Event, budget: &mut Budget) {
+    let request = match event.request.value_mut() {
+        Some(Some(request)) => request,
+        _ => return,
+    };
+
+    let sizes = [
+        value_size(&request.data),
+        value_size(&request.env),
+        value_size(&request.cookies),
+    ];
+
+    let selected = select_largest(&sizes, budget);
+    if selected[0] {
+        remove_value(&mut request.data, "data", budget);
+    }
+    if selected[1] {
+        remove_value(&mut request.env, "env", budget);
+    }
+    if selected[2] {
+        remove_value(&mut request.cookies, "cookies", budget);
+    }
+}
+
+fn trim_threads(event: &mut Event, budget: &mut Budget) {
+    let threads = match event.threads.value_mut() {
+        Some(threads) => &mut threads.values,
+        None => return,
+    };
+
+    let sizes: Vec<_> = match threads.value() {
+        Some(items) => items
+            .iter()
+            .map(|thread| match thread.value() {
+                Some(value) if value.crashed.value() == Some(&true) => 0,
+                _ => value_size(thread),
+            }).collect(),
+        None => return,
+    };
+
+    let selected = select_largest(&sizes, budget);
+    remove_items(threads, "values", &selected, budget);
+}
+
+/// Trims parts of an event in the given order until its estimated size fits into `max_size`.
+///
+/// Within every part, the largest values are removed first. Removed values leave a remark in
+/// their place, and lists that were shortened record a remark and their original length.
+///
+/// The event is only measured once. Afterwards, the sizes of removed values are deducted from
+/// the estimate and the sizes of the remarks left behind are added.
+pub(crate) fn trim_event(event: &mut Annotated<Event>, max_size: usize, order: &[TrimTarget]) {
+    let mut budget = Budget {
+        size: event.estimate_size(),
+        max_size,
+    };
+
+    for target in order {
+        if !budget.exceeded() {
+            break;
+        }
+
+        if let Some(event) = event.value_mut() {
+            match *target {
+                TrimTarget::Extra => trim_extra(event, &mut budget),
+                TrimTarget::Breadcrumbs => trim_breadcrumbs(event, &mut budget),
+                TrimTarget::FrameVars => trim_frame_vars(event, &mut budget),
+                TrimTarget::RequestData => trim_request_data(event, &mut budget),
+                TrimTarget::Threads => trim_threads(event, &mut budget),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::Value;
+    use testutils::event;
+
+    fn removed<T>() -> Annotated<T> {
+        let mut annotated = Annotated::empty();
+        annotated
+            .meta_mut()
+            .remarks_mut()
+            .push(Remark::new(RemarkType::Removed, TRIM_RULE_ID));
+        annotated
+    }
+
+    #[test]
+    fn test_within_budget() {
+        let mut event = Annotated::from(event(r#"{"extra": {"foo": "bar"}}"#));
+        let expected = event.clone();
+
+        let max_size = event.estimate_size();
+        trim_event(&mut event, max_size, &TrimTarget::default_order());
+        assert_eq_dbg!(event, expected);
+    }
+
+    #[test]
+    fn test_largest_extra_first() {
+        let mut event = Annotated::from(event(&format!(
+            r#"{{"extra": {{"small": "{}", "large": "{}"}}}}"#,
+            "x".repeat(100),
+            "x".repeat(200)
+        )));
+
+        let max_size = event.estimate_size() - 50;
+        trim_event(&mut event, max_size, &TrimTarget::default_order());
+
+        let extra = event.value().unwrap().extra.value().unwrap();
+        assert_eq_dbg!(extra["large"], removed());
+        assert_eq_dbg!(
+            extra["small"],
+            Annotated::from(Value::String("x".repeat(100)))
+        );
+    }
+
+    #[test]
+    fn test_estimate_after_trimming() {
+        let mut event = Annotated::from(event(&format!(
+            r#"{{
+                "extra": {{"a": "{0}", "b": "{0}", "c": "{0}"}},
+                "breadcrumbs": [
+                    {{"timestamp": 946684800, "message": "{0}"}},
+                    {{"timestamp": 946684800, "message": "{0}"}}
+                ]
+            }}"#,
+            "x".repeat(100)
+        )));
+
+        let max_size = event.estimate_size() - 250;
+        trim_event(&mut event, max_size, &TrimTarget::default_order());
+        assert!(event.estimate_size() <= max_size);
+    }
+
+    #[test]
+    fn test_order() {
+        let mut event = Annotated::from(event(&format!(
+            r#"{{
+                "breadcrumbs": [
+                    {{"timestamp": 946684800, "message": "{}"}},
+                    {{"timestamp": 946684800, "message": "short"}}
+                ],
+                "threads": [{{"id": 1, "name": "{}"}}]
+            }}"#,
+            "x".repeat(200),
+            "x".repeat(400)
+        )));
+
+        let max_size = event.estimate_size() - 50;
+        trim_event(&mut event, max_size, &TrimTarget::default_order());
+
+        let event = event.value().unwrap();
+        let breadcrumbs = &event.breadcrumbs.value().unwrap().values;
+        assert_eq_dbg!(breadcrumbs.value().unwrap().len(), 1);
+        assert_eq_dbg!(breadcrumbs.meta().original_length(), Some(2));
+        let remarks: Vec<_> = breadcrumbs.meta().remarks().cloned().collect();
+        assert_eq_dbg!(
+            remarks,
+            vec![Remark::new(RemarkType::Removed, TRIM_RULE_ID)]
+        );
+
+        let threads = &event.threads.value().unwrap().values;
+        assert_eq_dbg!(threads.value().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_frame_vars() {
+        let mut event = Annotated::from(event(&format!(
+            r#"{{
+                "stacktrace": {{
+                    "frames": [
+                        {{"function": "a", "vars": {{"foo": "{}"}}}},
+                        {{"function": "b", "vars": {{"foo": "bar"}}}}
+                    ]
+                }}
+            }}"#,
+            "x".repeat(200)
+        )));
+
+        let max_size = event.estimate_size() - 50;
+        trim_event(&mut event, max_size, &[TrimTarget::FrameVars]);
+
+        let stacktrace = event.value().unwrap().stacktrace.value().unwrap();
+        let frames = stacktrace.as_ref().unwrap().frames.value().unwrap();
+        assert_eq_dbg!(frames[0].value().unwrap().vars, removed());
+        assert!(frames[1].value().unwrap().vars.value().is_some());
+    }
+
+    #[test]
+    fn test_keeps_crashed_thread() {
+        let mut event = Annotated::from(event(&format!(
+            r#"{{
+                "threads": [
+                    {{"id": 1, "crashed": true, "name": "{}"}},
+                    {{"id": 2, "name": "short"}}
+                ]
+            }}"#,
+            "x".repeat(200)
+        )));
+
+        let max_size = event.estimate_size() - 10;
+        trim_event(&mut event, max_size, &[TrimTarget::Threads]);
+
+        let threads = &event.value().unwrap().threads.value().unwrap().values;
+        assert_eq_dbg!(threads.meta().original_length(), Some(2));
+        let remarks: Vec<_> = threads.meta().remarks().cloned().collect();
+        assert_eq_dbg!(
+            remarks,
+            vec![Remark::new(RemarkType::Removed, TRIM_RULE_ID)]
+        );
+
+        let threads = threads.value().unwrap();
+        assert_eq_dbg!(threads.len(), 1);
+        assert_eq_dbg!(threads[0].value().unwrap().crashed.value(), Some(&true));
+    }
+}
@@ -0,0 +1,480 @@
+//! Parsing of user agent headers into browser, OS and device contexts.
+//!
+//! User agents are matched against a bundled database of regular expressions in the format of
+//! [uap-core](https://github.com/ua-parser/uap-core). For every category, the first matching
+//! expression wins. Replacements may refer to capture groups as `$1` to `$9`, fields without
+//! replacement default to the respective capture group.
+//!
+//! The bundled `useragent.json` is not the complete uap-core database. It is a hand-picked subset
+//! of `regexes.yaml` that covers common browsers, operating systems and devices, so user agents of
+//! less common clients are not recognized. The entries keep the uap-core schema, so the file can be
+//! regenerated from any uap-core release by converting `regexes.yaml` to JSON:
+//!
+//! ```text
+//! python -c 'import json, sys, yaml; json.dump(yaml.safe_load(sys.stdin), sys.stdout, indent=2)' \
+//!     < uap-core/regexes.yaml > src/processor/useragent.json
+//! ```
+//!
+//! All expressions must compile with the `regex` crate, which does not support look-around or
+//! backreferences. Entries relying on these features have to be rewritten or removed, otherwise
+//! loading the database panics.
+use regex::{Captures, Regex};
+use serde_json;
+
+use protocol::{
+    Annotated, BrowserContext, Context, DeviceContext, Event, Map, OsContext, Remark, RemarkType,
+    Value,
+};
+
+use super::utils::fill_derived;
+
+/// The rule id used for context fields derived from the user agent.
+const USER_AGENT_RULE_ID: &str = "@user_agent";
+
+lazy_static! {
+    static ref DATABASE: Database = Database::load(include_str!("useragent.json"));
+}
+
+#[derive(Deserialize)]
+struct UserAgentParserSpec {
+    regex: String,
+    family_replacement: Option<String>,
+    v1_replacement: Option<String>,
+    v2_replacement: Option<String>,
+    v3_replacement: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OsParserSpec {
+    regex: String,
+    os_replacement: Option<String>,
+    os_v1_replacement: Option<String>,
+    os_v2_replacement: Option<String>,
+    os_v3_replacement: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeviceParserSpec {
+    regex: String,
+    regex_flag: Option<String>,
+    device_replacement: Option<String>,
+    brand_replacement: Option<String>,
+    model_replacement: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DatabaseSpec {
+    user_agent_parsers: Vec<UserAgentParserSpec>,
+    os_parsers: Vec<OsParserSpec>,
+    device_parsers: Vec<DeviceParserSpec>,
+}
+
+/// Substitutes capture group references (`$1`) in a replacement string.
+fn expand(replacement: &str, captures: &Captures) -> String {
+    let mut rv = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let group = match chars.peek().and_then(|d| d.to_digit(10)) {
+            Some(group) if c == '$' => group,
+            _ => {
+                rv.push(c);
+                continue;
+            }
+        };
+
+        chars.next();
+        if let Some(m) = captures.get(group as usize) {
+            rv.push_str(m.as_str());
+        }
+    }
+
+    rv
+}
+
+/// A compiled parser resolving a fixed set of fields.
+struct Parser {
+    regex: Regex,
+    replacements: Vec<Option<String>>,
+}
+
+impl Parser {
+    fn new(regex: &str, flag: Option<&str>, replacements: Vec<Option<String>>) -> Self {
+        let regex = match flag {
+            Some(flag) => format!("(?{}){}", flag, regex),
+            None => regex.to_string(),
+        };
+
+        Parser {
+            regex: Regex::new(&regex).unwrap(),
+            replacements,
+        }
+    }
+
+    /// Resolves all fields if the user agent matches.
+    ///
+    /// Fields without replacement fall back to the capture group given in `defaults`. Empty
+    /// fields are returned as `None`.
+    fn parse(&self, user_agent: &str, defaults: &[Option<usize>]) -> Option<Vec<Option<String>>> {
+        let captures = self.regex.captures(user_agent)?;
+
+        let fields = self
+            .replacements
+            .iter()
+            .zip(defaults)
+            .map(|(replacement, default)| {
+                let value = match *replacement {
+                    Some(ref replacement) => expand(replacement, &captures),
+                    None => default
+                        .and_then(|group| captures.get(group))
+                        .map_or(String::new(), |m| m.as_str().to_string()),
+                };
+
+                match value.trim() {
+                    "" => None,
+                    value => Some(value.to_string()),
+                }
+            })
+            .collect();
+
+        Some(fields)
+    }
+}
+
+/// Runs all parsers in order and returns the fields of the first match.
+fn parse_first(
+    parsers: &[Parser],
+    user_agent: &str,
+    defaults: &[Option<usize>],
+) -> Option<Vec<Option<String>>> {
+    parsers
+        .iter()
+        .filter_map(|parser| parser.parse(user_agent, defaults))
+        .next()
+}
+
+/// The bundled regex database.
+struct Database {
+    user_agent_parsers: Vec<Parser>,
+    os_parsers: Vec<Parser>,
+    device_parsers: Vec<Parser>,
+}
+
+impl Database {
+    fn load(json: &str) -> Self {
+        let spec: DatabaseSpec = serde_json::from_str(json).unwrap();
+
+        Database {
+            user_agent_parsers: spec
+                .user_agent_parsers
+                .into_iter()
+                .map(|p| {
+                    Parser::new(
+                        &p.regex,
+                        None,
+                        vec![
+                            p.family_replacement,
+                            p.v1_replacement,
+                            p.v2_replacement,
+                            p.v3_replacement,
+                        ],
+                    )
+                })
+                .collect(),
+            os_parsers: spec
+                .os_parsers
+                .into_iter()
+                .map(|p| {
+                    Parser::new(
+                        &p.regex,
+                        None,
+                        vec![
+                            p.os_replacement,
+                            p.os_v1_replacement,
+                            p.os_v2_replacement,
+                            p.os_v3_replacement,
+                        ],
+                    )
+                })
+                .collect(),
+            device_parsers: spec
+                .device_parsers
+                .into_iter()
+                .map(|p| {
+                    Parser::new(
+                        &p.regex,
+                        p.regex_flag.as_ref().map(String::as_str),
+                        vec![
+                            p.device_replacement,
+                            p.brand_replacement,
+                            p.model_replacement,
+                        ],
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A product name with an optional version, such as a browser or OS.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Product {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl Product {
+    /// Creates a product from a list of fields: the name, followed by version components.
+    fn from_fields(fields: Vec<Option<String>>) -> Option<Self> {
+        let mut fields = fields.into_iter();
+        let name = fields.next()??;
+
+        let components: Vec<_> = fields.take_while(Option::is_some).flatten().collect();
+        let version = if components.is_empty() {
+            None
+        } else {
+            Some(components.join("."))
+        };
+
+        Some(Product { name, version })
+    }
+}
+
+/// Device information derived from a user agent.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Device {
+    pub family: String,
+    pub brand: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Information parsed from a user agent string.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct UserAgent {
+    pub browser: Option<Product>,
+    pub os: Option<Product>,
+    pub device: Option<Device>,
+}
+
+/// Parses a user agent string using the bundled regex database.
+pub(crate) fn parse_user_agent(user_agent: &str) -> UserAgent {
+    let product_defaults = [Some(1), Some(2), Some(3), Some(4)];
+    let device_defaults = [Some(1), None, Some(1)];
+
+    let browser = parse_first(&DATABASE.user_agent_parsers, user_agent, &product_defaults)
+        .and_then(Product::from_fields);
+    let os = parse_first(&DATABASE.os_parsers, user_agent, &product_defaults)
+        .and_then(Product::from_fields);
+    let device = parse_first(&DATABASE.device_parsers, user_agent, &device_defaults).and_then(
+        |mut fields| {
+            let model = fields.pop()?;
+            let brand = fields.pop()?;
+            let family = fields.pop()??;
+            Some(Device {
+                family,
+                brand,
+                model,
+            })
+        },
+    );
+
+    UserAgent {
+        browser,
+        os,
+        device,
+    }
+}
+
+/// Returns the user agent header of the event's request.
+fn get_user_agent(event: &Event) -> Option<&str> {
+    let request = event.request.value()?.as_ref()?;
+    let headers = request.headers.value()?;
+    headers
+        .get("User-Agent")
+        .and_then(Annotated::value)
+        .map(String::as_str)
+}
+
+/// Inserts a context derived from the user agent if there is no context under the given key.
+///
+/// Contexts sent by the client are never modified.
+fn insert_context<F>(contexts: &mut Map<Context>, key: &str, derive: F)
+where
+    F: FnOnce() -> Context,
+{
+    if !contexts.contains_key(key) {
+        contexts.insert(key.to_string(), Annotated::from(derive()));
+    }
+}
+
+/// Populates browser, OS and device contexts from the request's user agent.
+///
+/// Contexts are only added if the event does not contain them yet. All derived fields are marked
+/// with a remark.
+pub(crate) fn normalize_user_agent(event: &mut Event) {
+    let user_agent = match get_user_agent(event) {
+        Some(user_agent) => parse_user_agent(user_agent),
+        None => return,
+    };
+
+    let contexts = match event.contexts.value_mut() {
+        Some(contexts) => contexts,
+        None => return,
+    };
+
+    if let Some(browser) = user_agent.browser {
+        insert_context(contexts, "browser", || {
+            let mut context = BrowserContext::default();
+            fill_derived(&mut context.name, Some(browser.name), USER_AGENT_RULE_ID);
+            fill_derived(&mut context.version, browser.version, USER_AGENT_RULE_ID);
+            Context::Browser(Box::new(context))
+        });
+    }
+
+    if let Some(os) = user_agent.os {
+        insert_context(contexts, "os", || {
+            let mut context = OsContext::default();
+            fill_derived(&mut context.name, Some(os.name), USER_AGENT_RULE_ID);
+            fill_derived(&mut context.version, os.version, USER_AGENT_RULE_ID);
+            Context::Os(Box::new(context))
+        });
+    }
+
+    if let Some(device) = user_agent.device {
+        insert_context(contexts, "device", || {
+            let mut context = DeviceContext::default();
+            fill_derived(&mut context.family, Some(device.family), USER_AGENT_RULE_ID);
+            fill_derived(&mut context.model, device.model, USER_AGENT_RULE_ID);
+
+            if let (Some(brand), Some(other)) = (device.brand, context.other.value_mut()) {
+                let mut value = Annotated::from(Value::String(brand));
+                value
+                    .meta_mut()
+                    .remarks_mut()
+                    .push(Remark::new(RemarkType::Substituted, USER_AGENT_RULE_ID));
+                other.insert("brand".to_string(), value);
+            }
+
+            Context::Device(Box::new(context))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutils::event;
+
+    fn product(name: &str, version: Option<&str>) -> Option<Product> {
+        Some(Product {
+            name: name.to_string(),
+            version: version.map(str::to_string),
+        })
+    }
+
+    fn derived(value: &str) -> Annotated<Option<String>> {
+        let mut annotated = Annotated::from(Some(value.to_string()));
+        annotated
+            .meta_mut()
+            .remarks_mut()
+            .push(Remark::new(RemarkType::Substituted, USER_AGENT_RULE_ID));
+        annotated
+    }
+
+    #[test]
+    fn test_chrome_windows() {
+        let ua = parse_user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/70.0.3538.102 Safari/537.36");
+        assert_eq_dbg!(ua.browser, product("Chrome", Some("70.0.3538")));
+        assert_eq_dbg!(ua.os, product("Windows", Some("10")));
+        assert_eq_dbg!(ua.device, None);
+    }
+
+    #[test]
+    fn test_safari_iphone() {
+        let ua = parse_user_agent("Mozilla/5.0 (iPhone; CPU iPhone OS 12_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/12.0 Mobile/15E148 Safari/604.1");
+        assert_eq_dbg!(ua.browser, product("Mobile Safari", Some("12.0")));
+        assert_eq_dbg!(ua.os, product("iOS", Some("12.1")));
+        assert_eq_dbg!(
+            ua.device,
+            Some(Device {
+                family: "iPhone".to_string(),
+                brand: Some("Apple".to_string()),
+                model: Some("iPhone".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_firefox_mac() {
+        let ua = parse_user_agent(
+            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.14; rv:63.0) Gecko/20100101 Firefox/63.0",
+        );
+        assert_eq_dbg!(ua.browser, product("Firefox", Some("63.0")));
+        assert_eq_dbg!(ua.os, product("Mac OS X", Some("10.14")));
+        assert_eq_dbg!(ua.device.map(|d| d.family), Some("Mac".to_string()));
+    }
+
+    #[test]
+    fn test_chrome_android() {
+        let ua = parse_user_agent("Mozilla/5.0 (Linux; Android 8.0.0; SM-G960F Build/R16NW) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/62.0.3202.84 Mobile Safari/537.36");
+        assert_eq_dbg!(ua.browser, product("Chrome Mobile", Some("62.0.3202")));
+        assert_eq_dbg!(ua.os, product("Android", Some("8.0.0")));
+        assert_eq_dbg!(
+            ua.device,
+            Some(Device {
+                family: "Samsung SM-G960F".to_string(),
+                brand: Some("Samsung".to_string()),
+                model: Some("SM-G960F".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq_dbg!(parse_user_agent("curl/7.54.0"), UserAgent::default());
+    }
+
+    #[test]
+    fn test_normalize_contexts() {
+        let mut event = event(
+            r#"{
+                "request": {"headers": {"User-Agent": "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.14; rv:63.0) Gecko/20100101 Firefox/63.0"}},
+                "contexts": {"os": {"type": "os", "name": "macOS"}}
+            }"#,
+        );
+
+        normalize_user_agent(&mut event);
+        let contexts = event.contexts.value().unwrap();
+
+        match contexts["browser"].value() {
+            Some(&Context::Browser(ref browser)) => {
+                assert_eq_dbg!(browser.name, derived("Firefox"));
+                assert_eq_dbg!(browser.version, derived("63.0"));
+            }
+            other => panic!("expected browser context, got {:?}", other),
+        }
+
+        match contexts["os"].value() {
+            Some(&Context::Os(ref os)) => {
+                assert_eq_dbg!(os.name, Annotated::from(Some("macOS".to_string())));
+                assert_eq_dbg!(os.version, Annotated::from(None));
+            }
+            other => panic!("expected os context, got {:?}", other),
+        }
+
+        match contexts["device"].value() {
+            Some(&Context::Device(ref device)) => {
+                assert_eq_dbg!(device.family, derived("Mac"));
+                assert_eq_dbg!(device.model, derived("Mac"));
+            }
+            other => panic!("expected device context, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_normalize_without_user_agent() {
+        let mut event = event(r#"{"request": {"url": "https://example.com"}}"#);
+        normalize_user_agent(&mut event);
+        assert!(event.contexts.value().unwrap().is_empty());
+    }
+}
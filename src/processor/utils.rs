@@ -0,0 +1,34 @@
+//! Helpers shared by the normalization steps.
+use protocol::{Annotated, Remark, RemarkType};
+
+/// Checks whether an optional value is missing and may be filled in.
+///
+/// Values that were rejected during deserialization are not considered missing, so that their
+/// errors are never hidden behind a substituted value.
+pub(crate) fn is_missing<T>(annotated: &Annotated<Option<T>>) -> bool {
+    annotated.value().map_or(true, Option::is_none) && !annotated.meta().has_errors()
+}
+
+/// Fills a missing value and marks it as derived by the given rule.
+///
+/// Values that are already set or contain errors are never overwritten.
+pub(crate) fn fill_derived(
+    annotated: &mut Annotated<Option<String>>,
+    value: Option<String>,
+    rule_id: &str,
+) {
+    let value = match value {
+        Some(value) => value,
+        None => return,
+    };
+
+    if !is_missing(annotated) {
+        return;
+    }
+
+    annotated.set_value(Some(Some(value)));
+    annotated
+        .meta_mut()
+        .remarks_mut()
+        .push(Remark::new(RemarkType::Substituted, rule_id));
+}
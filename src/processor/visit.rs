@@ -0,0 +1,726 @@
+//! Implements in-place visitors for the protocol.
+//!
+//! Unlike `Processor`, visitors do not consume and rebuild annotated values. A `VisitorMut`
+//! modifies values behind mutable references, and a `Visitor` inspects them without modifying
+//! anything. Both receive a `VisitState` that carries the path to the current value and its
+//! `ValueInfo`.
+use std::fmt;
+use std::mem;
+
+use chrono::{DateTime, Utc};
+use debugid::DebugId;
+use uuid::Uuid;
+
+use protocol::{
+    Addr, Annotated, Array, Level, Map, Meta, MetaError, RegVal, ThreadId, Value, Values,
+};
+
+use super::pii::ValueInfo;
+
+/// An item in the path of a visited value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathItem<'a> {
+    /// A field in a struct or a key in a map.
+    Key(&'a str),
+    /// An element in an array.
+    Index(usize),
+}
+
+impl<'a> fmt::Display for PathItem<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathItem::Key(key) => write!(f, "{}", key),
+            PathItem::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+/// The state of a visitor at the current value.
+///
+/// States form a chain from the current value up to the root, so entering a child value does not
+/// allocate.
+#[derive(Debug, Clone)]
+pub struct VisitState<'a> {
+    parent: Option<&'a VisitState<'a>>,
+    item: Option<PathItem<'a>>,
+    info: ValueInfo,
+}
+
+impl<'a> VisitState<'a> {
+    /// Creates the state for the root value.
+    pub fn root() -> VisitState<'static> {
+        VisitState {
+            parent: None,
+            item: None,
+            info: ValueInfo::default(),
+        }
+    }
+
+    /// Creates the state for a field in a struct or a key in a map.
+    pub fn enter_key<'b>(&'b self, key: &'b str, info: ValueInfo) -> VisitState<'b> {
+        VisitState {
+            parent: Some(self),
+            item: Some(PathItem::Key(key)),
+            info,
+        }
+    }
+
+    /// Creates the state for an element in an array.
+    pub fn enter_index<'b>(&'b self, index: usize, info: ValueInfo) -> VisitState<'b> {
+        VisitState {
+            parent: Some(self),
+            item: Some(PathItem::Index(index)),
+            info,
+        }
+    }
+
+    /// Creates the state for a value that does not add to the path, such as a flattened map.
+    pub fn enter_nothing<'b>(&'b self, info: ValueInfo) -> VisitState<'b> {
+        VisitState {
+            parent: Some(self),
+            item: None,
+            info,
+        }
+    }
+
+    /// Returns information on how to process the current value.
+    pub fn info(&self) -> &ValueInfo {
+        &self.info
+    }
+
+    /// Returns the path to the current value, starting at the root.
+    pub fn path(&self) -> Vec<PathItem<'a>> {
+        let mut path = Vec::new();
+        let mut state = Some(self);
+        while let Some(current) = state {
+            if let Some(item) = current.item {
+                path.push(item);
+            }
+            state = current.parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+impl<'a> fmt::Display for VisitState<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_path(&self.path()))
+    }
+}
+
+/// Formats a path as dot-separated string, or `"."` for the root.
+pub(crate) fn format_path(path: &[PathItem]) -> String {
+    if path.is_empty() {
+        return ".".to_string();
+    }
+
+    let items: Vec<_> = path.iter().map(ToString::to_string).collect();
+    items.join(".")
+}
+
+macro_rules! declare_primitive_visit {
+    ($ty:ident, $func:ident) => {
+        #[doc = "Visits an annotated value of type `"]
+        #[doc = stringify!($ty)]
+        #[doc = "`."]
+        fn $func(&mut self, value: Option<$ty>, meta: &Meta, state: &VisitState) {
+            let _ = (value, meta, state);
+        }
+    };
+}
+
+macro_rules! declare_primitive_visit_mut {
+    ($ty:ident, $func:ident) => {
+        #[doc = "Visits an annotated value of type `"]
+        #[doc = stringify!($ty)]
+        #[doc = "`."]
+        fn $func(&mut self, annotated: &mut Annotated<$ty>, state: &VisitState) {
+            let _ = (annotated, state);
+        }
+    };
+}
+
+/// A read-only visitor for annotated values.
+pub trait Visitor {
+    declare_primitive_visit!(bool, visit_bool);
+    declare_primitive_visit!(u64, visit_u64);
+    declare_primitive_visit!(i64, visit_i64);
+    declare_primitive_visit!(f64, visit_f64);
+
+    /// Visits an annotated value of type `String`.
+    fn visit_string(&mut self, value: Option<&str>, meta: &Meta, state: &VisitState) {
+        let _ = (value, meta, state);
+    }
+
+    /// Visits an annotated `Value`.
+    ///
+    /// By default, this descends into arrays and maps and visits primitive values.
+    fn visit_value(&mut self, value: Option<&Value>, meta: &Meta, state: &VisitState) {
+        walk_value(self, value, meta, state);
+    }
+}
+
+/// A visitor that modifies annotated values in place.
+pub trait VisitorMut {
+    declare_primitive_visit_mut!(bool, visit_bool);
+    declare_primitive_visit_mut!(u64, visit_u64);
+    declare_primitive_visit_mut!(i64, visit_i64);
+    declare_primitive_visit_mut!(f64, visit_f64);
+    declare_primitive_visit_mut!(String, visit_string);
+
+    /// Visits an annotated `Value`.
+    ///
+    /// By default, this descends into arrays and maps and visits primitive values.
+    fn visit_value(&mut self, annotated: &mut Annotated<Value>, state: &VisitState) {
+        walk_value_mut(self, annotated, state);
+    }
+}
+
+/// Visits the contents of a `Value` with a read-only visitor.
+pub fn walk_value<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    value: Option<&Value>,
+    meta: &Meta,
+    state: &VisitState,
+) {
+    match value {
+        Some(&Value::Bool(value)) => visitor.visit_bool(Some(value), meta, state),
+        Some(&Value::U64(value)) => visitor.visit_u64(Some(value), meta, state),
+        Some(&Value::I64(value)) => visitor.visit_i64(Some(value), meta, state),
+        Some(&Value::F64(value)) => visitor.visit_f64(Some(value), meta, state),
+        Some(&Value::String(ref value)) => visitor.visit_string(Some(value), meta, state),
+        Some(&Value::Array(ref items)) => {
+            for (index, item) in items.iter().enumerate() {
+                let state = state.enter_index(index, state.info().derive());
+                visitor.visit_value(item.value(), item.meta(), &state);
+            }
+        }
+        Some(&Value::Map(ref map)) => {
+            for (key, value) in map {
+                let state = state.enter_key(key, state.info().derive());
+                visitor.visit_value(value.value(), value.meta(), &state);
+            }
+        }
+        Some(&Value::Null) | None => (),
+    }
+}
+
+/// Visits the contents of a `Value` with a mutable visitor.
+///
+/// Arrays and maps are modified in place. Primitive values are moved out of the `Value` for the
+/// duration of the visit and moved back afterwards.
+pub fn walk_value_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    annotated: &mut Annotated<Value>,
+    state: &VisitState,
+) {
+    macro_rules! visit_primitive {
+        ($value:expr, $meta:expr, $variant:ident, $func:ident) => {{
+            let mut primitive = Annotated::new($value, $meta);
+            visitor.$func(&mut primitive, state);
+            primitive.map(Value::$variant)
+        }};
+    }
+
+    match annotated.0 {
+        Some(Value::Array(ref mut items)) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                visitor.visit_value(item, &state.enter_index(index, state.info().derive()));
+            }
+            return;
+        }
+        Some(Value::Map(ref mut map)) => {
+            for (key, value) in map.iter_mut() {
+                visitor.visit_value(value, &state.enter_key(key, state.info().derive()));
+            }
+            return;
+        }
+        Some(Value::Null) | None => return,
+        _ => (),
+    }
+
+    *annotated = match mem::replace(annotated, Annotated::empty()) {
+        Annotated(Some(Value::Bool(value)), meta) => {
+            visit_primitive!(value, meta, Bool, visit_bool)
+        }
+        Annotated(Some(Value::U64(value)), meta) => visit_primitive!(value, meta, U64, visit_u64),
+        Annotated(Some(Value::I64(value)), meta) => visit_primitive!(value, meta, I64, visit_i64),
+        Annotated(Some(Value::F64(value)), meta) => visit_primitive!(value, meta, F64, visit_f64),
+        Annotated(Some(Value::String(value)), meta) => {
+            visit_primitive!(value, meta, String, visit_string)
+        }
+        other => other,
+    };
+}
+
+/// A trait implemented for annotated types that support visiting.
+pub trait VisitAnnotatedValue {
+    /// Visits an annotated value with a read-only visitor.
+    ///
+    /// By default, this visits the children of the value.
+    fn visit_annotated_value(
+        value: Option<&Self>,
+        meta: &Meta,
+        visitor: &mut Visitor,
+        state: &VisitState,
+    ) where
+        Self: Sized,
+    {
+        let _meta = meta;
+        if let Some(value) = value {
+            value.visit_children(visitor, state);
+        }
+    }
+
+    /// Visits an annotated value with a mutable visitor.
+    ///
+    /// By default, this visits the children of the value.
+    fn visit_annotated_value_mut(
+        annotated: &mut Annotated<Self>,
+        visitor: &mut VisitorMut,
+        state: &VisitState,
+    ) where
+        Self: Sized,
+    {
+        if let Some(value) = annotated.value_mut() {
+            value.visit_children_mut(visitor, state);
+        }
+    }
+
+    /// Visits the children of this value with a read-only visitor.
+    fn visit_children(&self, visitor: &mut Visitor, state: &VisitState) {
+        let _ = (visitor, state);
+    }
+
+    /// Visits the children of this value with a mutable visitor.
+    fn visit_children_mut(&mut self, visitor: &mut VisitorMut, state: &VisitState) {
+        let _ = (visitor, state);
+    }
+}
+
+/// Visits an annotated value and all of its children with a read-only visitor.
+pub fn visit<T: VisitAnnotatedValue>(annotated: &Annotated<T>, visitor: &mut Visitor) {
+    VisitAnnotatedValue::visit_annotated_value(
+        annotated.value(),
+        annotated.meta(),
+        visitor,
+        &VisitState::root(),
+    );
+}
+
+/// Visits an annotated value and all of its children with a mutable visitor.
+pub fn visit_mut<T: VisitAnnotatedValue>(annotated: &mut Annotated<T>, visitor: &mut VisitorMut) {
+    VisitAnnotatedValue::visit_annotated_value_mut(annotated, visitor, &VisitState::root());
+}
+
+/// Checked conversion from the primitive passed to visitors back into the visited type.
+trait FromPrimitive<P>: Sized {
+    /// Converts the primitive, or returns `None` if it is out of range for this type.
+    fn from_primitive(primitive: P) -> Option<Self>;
+}
+
+macro_rules! impl_from_primitive {
+    ($ty:ident, $prim_ty:ident, |$x:ident| $in_range:expr) => {
+        impl FromPrimitive<$prim_ty> for $ty {
+            fn from_primitive($x: $prim_ty) -> Option<Self> {
+                if $in_range {
+                    Some($x as $ty)
+                } else {
+                    None
+                }
+            }
+        }
+    };
+}
+
+impl_from_primitive!(bool, bool, |x| true);
+impl_from_primitive!(u32, u64, |x| x <= u64::from(u32::max_value()));
+impl_from_primitive!(i32, i64, |x| x >= i64::from(i32::min_value())
+    && x <= i64::from(i32::max_value()));
+impl_from_primitive!(u64, u64, |x| true);
+impl_from_primitive!(i64, i64, |x| true);
+impl_from_primitive!(f32, f64, |x| !x.is_finite()
+    || x.abs() <= f64::from(f32::MAX));
+impl_from_primitive!(f64, f64, |x| true);
+
+/// Converts a visited primitive back into the visited type.
+///
+/// Values out of range for the visited type are removed and an error is added instead, so that
+/// visitors can never silently truncate values.
+fn from_primitive<T, P>(annotated: Annotated<P>, type_name: &str) -> Annotated<T>
+where
+    T: FromPrimitive<P>,
+{
+    match annotated {
+        Annotated(Some(primitive), mut meta) => match T::from_primitive(primitive) {
+            Some(value) => Annotated(Some(value), meta),
+            None => {
+                meta.errors_mut().push(MetaError::invalid(format!(
+                    "value out of range for {}",
+                    type_name
+                )));
+                Annotated(None, meta)
+            }
+        },
+        Annotated(None, meta) => Annotated(None, meta),
+    }
+}
+
+macro_rules! impl_primitive_visit {
+    ($ty:ident, $prim_ty:ident, $func:ident) => {
+        impl VisitAnnotatedValue for $ty {
+            fn visit_annotated_value(
+                value: Option<&Self>,
+                meta: &Meta,
+                visitor: &mut Visitor,
+                state: &VisitState,
+            ) {
+                visitor.$func(value.map(|x| *x as $prim_ty), meta, state);
+            }
+
+            fn visit_annotated_value_mut(
+                annotated: &mut Annotated<Self>,
+                visitor: &mut VisitorMut,
+                state: &VisitState,
+            ) {
+                let mut primitive = mem::replace(annotated, Annotated::empty()).map(From::from);
+                visitor.$func(&mut primitive, state);
+                *annotated = from_primitive(primitive, stringify!($ty));
+            }
+        }
+    };
+}
+
+impl_primitive_visit!(bool, bool, visit_bool);
+impl_primitive_visit!(u32, u64, visit_u64);
+impl_primitive_visit!(i32, i64, visit_i64);
+impl_primitive_visit!(u64, u64, visit_u64);
+impl_primitive_visit!(i64, i64, visit_i64);
+impl_primitive_visit!(f32, f64, visit_f64);
+impl_primitive_visit!(f64, f64, visit_f64);
+
+macro_rules! impl_opaque_visit {
+    ($($ty:ty),*) => {
+        $(
+            impl VisitAnnotatedValue for $ty {}
+        )*
+    };
+}
+
+// These values are visited as a whole and are not passed to the visitor.
+impl_opaque_visit!(Addr, RegVal, Level, ThreadId, Uuid, DebugId, DateTime<Utc>);
+impl_opaque_visit!((u64, u64), Vec<String>);
+
+impl VisitAnnotatedValue for String {
+    fn visit_annotated_value(
+        value: Option<&Self>,
+        meta: &Meta,
+        visitor: &mut Visitor,
+        state: &VisitState,
+    ) {
+        visitor.visit_string(value.map(String::as_str), meta, state);
+    }
+
+    fn visit_annotated_value_mut(
+        annotated: &mut Annotated<Self>,
+        visitor: &mut VisitorMut,
+        state: &VisitState,
+    ) {
+        visitor.visit_string(annotated, state);
+    }
+}
+
+impl VisitAnnotatedValue for Value {
+    fn visit_annotated_value(
+        value: Option<&Self>,
+        meta: &Meta,
+        visitor: &mut Visitor,
+        state: &VisitState,
+    ) {
+        visitor.visit_value(value, meta, state);
+    }
+
+    fn visit_annotated_value_mut(
+        annotated: &mut Annotated<Self>,
+        visitor: &mut VisitorMut,
+        state: &VisitState,
+    ) {
+        visitor.visit_value(annotated, state);
+    }
+}
+
+impl<T: VisitAnnotatedValue> VisitAnnotatedValue for Option<T> {
+    fn visit_annotated_value(
+        value: Option<&Self>,
+        meta: &Meta,
+        visitor: &mut Visitor,
+        state: &VisitState,
+    ) {
+        VisitAnnotatedValue::visit_annotated_value(
+            value.and_then(Option::as_ref),
+            meta,
+            visitor,
+            state,
+        );
+    }
+
+    fn visit_annotated_value_mut(
+        annotated: &mut Annotated<Self>,
+        visitor: &mut VisitorMut,
+        state: &VisitState,
+    ) {
+        let Annotated(value, meta) = mem::replace(annotated, Annotated::empty());
+        let absent = value.as_ref().map_or(false, Option::is_none);
+
+        let mut inner = Annotated(value.and_then(|x| x), meta);
+        VisitAnnotatedValue::visit_annotated_value_mut(&mut inner, visitor, state);
+
+        // Absent values stay absent unless the visitor sets a value. Values removed by the
+        // visitor are treated like values removed by a `Processor`.
+        let Annotated(value, meta) = inner;
+        *annotated = match value {
+            Some(value) => Annotated(Some(Some(value)), meta),
+            None if absent => Annotated(Some(None), meta),
+            None => Annotated(None, meta),
+        };
+    }
+}
+
+impl<T: VisitAnnotatedValue> VisitAnnotatedValue for Box<T> {
+    fn visit_annotated_value(
+        value: Option<&Self>,
+        meta: &Meta,
+        visitor: &mut Visitor,
+        state: &VisitState,
+    ) {
+        VisitAnnotatedValue::visit_annotated_value(value.map(|x| &**x), meta, visitor, state);
+    }
+
+    fn visit_annotated_value_mut(
+        annotated: &mut Annotated<Self>,
+        visitor: &mut VisitorMut,
+        state: &VisitState,
+    ) {
+        let mut inner = mem::replace(annotated, Annotated::empty()).map(|x| *x);
+        VisitAnnotatedValue::visit_annotated_value_mut(&mut inner, visitor, state);
+        *annotated = inner.map(Box::new);
+    }
+}
+
+impl<T: VisitAnnotatedValue> VisitAnnotatedValue for Values<T> {
+    fn visit_children(&self, visitor: &mut Visitor, state: &VisitState) {
+        let values = &self.values;
+        VisitAnnotatedValue::visit_annotated_value(
+            values.value(),
+            values.meta(),
+            visitor,
+            &state.enter_key("values", state.info().derive()),
+        );
+
+        let other = &self.other;
+        VisitAnnotatedValue::visit_annotated_value(
+            other.value(),
+            other.meta(),
+            visitor,
+            &state.enter_nothing(state.info().derive()),
+        );
+    }
+
+    fn visit_children_mut(&mut self, visitor: &mut VisitorMut, state: &VisitState) {
+        VisitAnnotatedValue::visit_annotated_value_mut(
+            &mut self.values,
+            visitor,
+            &state.enter_key("values", state.info().derive()),
+        );
+        VisitAnnotatedValue::visit_annotated_value_mut(
+            &mut self.other,
+            visitor,
+            &state.enter_nothing(state.info().derive()),
+        );
+    }
+}
+
+impl<T: VisitAnnotatedValue> VisitAnnotatedValue for Array<T> {
+    fn visit_children(&self, visitor: &mut Visitor, state: &VisitState) {
+        for (index, item) in self.iter().enumerate() {
+            VisitAnnotatedValue::visit_annotated_value(
+                item.value(),
+                item.meta(),
+                visitor,
+                &state.enter_index(index, state.info().derive()),
+            );
+        }
+    }
+
+    fn visit_children_mut(&mut self, visitor: &mut VisitorMut, state: &VisitState) {
+        for (index, item) in self.iter_mut().enumerate() {
+            VisitAnnotatedValue::visit_annotated_value_mut(
+                item,
+                visitor,
+                &state.enter_index(index, state.info().derive()),
+            );
+        }
+    }
+}
+
+impl<T: VisitAnnotatedValue> VisitAnnotatedValue for Map<T> {
+    fn visit_children(&self, visitor: &mut Visitor, state: &VisitState) {
+        for (key, value) in self {
+            VisitAnnotatedValue::visit_annotated_value(
+                value.value(),
+                value.meta(),
+                visitor,
+                &state.enter_key(key, state.info().derive()),
+            );
+        }
+    }
+
+    fn visit_children_mut(&mut self, visitor: &mut VisitorMut, state: &VisitState) {
+        for (key, value) in self.iter_mut() {
+            VisitAnnotatedValue::visit_annotated_value_mut(
+                value,
+                visitor,
+                &state.enter_key(key, state.info().derive()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::PiiKind;
+    use protocol::Event;
+
+    struct UppercaseVisitor;
+
+    impl VisitorMut for UppercaseVisitor {
+        fn visit_string(&mut self, annotated: &mut Annotated<String>, state: &VisitState) {
+            if state.info().pii_kind == Some(PiiKind::Freeform) {
+                if let Some(value) = annotated.value_mut() {
+                    *value = value.to_uppercase();
+                }
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct PathCollector {
+        paths: Vec<String>,
+    }
+
+    impl Visitor for PathCollector {
+        fn visit_string(&mut self, value: Option<&str>, meta: &Meta, state: &VisitState) {
+            let _meta = meta;
+            if let Some(value) = value {
+                self.paths.push(format!("{}={}", state, value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_visit_mut() {
+        let mut event = Annotated::<Event>::from_json(
+            r#"{
+                "message": "hello world",
+                "logger": "my.logger"
+            }"#,
+        )
+        .unwrap();
+        visit_mut(&mut event, &mut UppercaseVisitor);
+
+        let event = event.value().unwrap();
+        assert_eq_str!(
+            event.message.value().unwrap().as_ref().unwrap(),
+            "HELLO WORLD"
+        );
+        assert_eq_str!(event.logger.value().unwrap().as_ref().unwrap(), "my.logger");
+    }
+
+    #[test]
+    fn test_visit_paths() {
+        let event = Annotated::<Event>::from_json(
+            r#"{
+                "exception": {"values": [{"type": "ZeroDivisionError", "value": "division by zero"}]},
+                "extra": {"foo": ["bar"]},
+                "release": "1.0",
+                "custom": "value"
+            }"#,
+        ).unwrap();
+
+        let mut visitor = PathCollector::default();
+        visit(&event, &mut visitor);
+
+        assert!(visitor
+            .paths
+            .contains(&"exception.values.0.value=division by zero".to_string()));
+        assert!(visitor
+            .paths
+            .contains(&"exception.values.0.type=ZeroDivisionError".to_string()));
+        assert!(visitor.paths.contains(&"extra.foo.0=bar".to_string()));
+        assert!(visitor.paths.contains(&"release=1.0".to_string()));
+        assert!(visitor.paths.contains(&"custom=value".to_string()));
+    }
+
+    #[test]
+    fn test_walk_value_mut() {
+        struct Counter(u64);
+
+        impl VisitorMut for Counter {
+            fn visit_u64(&mut self, annotated: &mut Annotated<u64>, state: &VisitState) {
+                assert_eq_str!(state.to_string(), format!("a.{}", self.0));
+                self.0 += 1;
+                annotated.set_value(Some(self.0));
+            }
+        }
+
+        let mut value = Annotated::<Value>::from_json(r#"{"a": [0, 0, 0]}"#).unwrap();
+        visit_mut(&mut value, &mut Counter(0));
+        assert_eq_str!(value.to_json().unwrap(), r#"{"a":[1,2,3]}"#);
+    }
+
+    #[test]
+    fn test_visit_mut_out_of_range() {
+        struct Overflow;
+
+        impl VisitorMut for Overflow {
+            fn visit_u64(&mut self, annotated: &mut Annotated<u64>, _state: &VisitState) {
+                annotated.set_value(Some(u64::max_value()));
+            }
+
+            fn visit_i64(&mut self, annotated: &mut Annotated<i64>, _state: &VisitState) {
+                annotated.set_value(Some(i64::min_value()));
+            }
+
+            fn visit_f64(&mut self, annotated: &mut Annotated<f64>, _state: &VisitState) {
+                annotated.set_value(Some(1e300));
+            }
+        }
+
+        let mut value = Annotated::from(42u32);
+        visit_mut(&mut value, &mut Overflow);
+        assert_eq_dbg!(
+            value,
+            Annotated::from_error(MetaError::invalid("value out of range for u32"))
+        );
+
+        let mut value = Annotated::from(42i32);
+        visit_mut(&mut value, &mut Overflow);
+        assert_eq_dbg!(
+            value,
+            Annotated::from_error(MetaError::invalid("value out of range for i32"))
+        );
+
+        let mut value = Annotated::from(1.5f32);
+        visit_mut(&mut value, &mut Overflow);
+        assert_eq_dbg!(
+            value,
+            Annotated::from_error(MetaError::invalid("value out of range for f32"))
+        );
+
+        let mut value = Annotated::from(42u64);
+        visit_mut(&mut value, &mut Overflow);
+        assert_eq_dbg!(value, Annotated::from(u64::max_value()));
+    }
+}
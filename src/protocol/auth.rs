@@ -0,0 +1,281 @@
+//! Parsing of Sentry authentication from the `X-Sentry-Auth` header or query string.
+
+use std::{fmt, str};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use url::form_urlencoded;
+
+use super::serde_chrono::timestamp_to_datetime;
+
+/// The oldest protocol version that can be assumed if no version is sent.
+const DEFAULT_VERSION: u16 = 2;
+
+/// An error used when parsing `Auth`.
+#[derive(Debug, Fail, Clone, PartialEq, Eq)]
+pub enum ParseAuthError {
+    /// The header does not start with the `Sentry` prefix.
+    #[fail(display = "non sentry auth")]
+    NonSentryAuth,
+    /// The `sentry_version` value is not a valid integer.
+    #[fail(display = "invalid value for version")]
+    InvalidVersion,
+    /// The `sentry_timestamp` value is neither a unix timestamp nor a date.
+    #[fail(display = "invalid value for timestamp")]
+    InvalidTimestamp,
+    /// The `sentry_key` value is missing.
+    #[fail(display = "missing public key in auth")]
+    MissingPublicKey,
+}
+
+/// Authentication information sent by a client.
+///
+/// This can be parsed from the `X-Sentry-Auth` header with `FromStr` or from a query string with
+/// `Auth::from_querystring`. Older clients may also send the header as `Authorization`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Auth {
+    version: u16,
+    client: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+    key: String,
+    secret: Option<String>,
+}
+
+impl Auth {
+    /// Parses authentication from the pairs of an URL-encoded query string.
+    ///
+    /// Only keys prefixed with `sentry_` are considered, everything else is ignored.
+    pub fn from_querystring(qs: &[u8]) -> Result<Self, ParseAuthError> {
+        Self::from_pairs(
+            form_urlencoded::parse(qs)
+                .map(|(key, value)| (key.into_owned(), value.into_owned())),
+        )
+    }
+
+    /// Parses authentication from key value pairs.
+    ///
+    /// Only keys prefixed with `sentry_` are considered, everything else is ignored.
+    pub fn from_pairs<I, K, V>(pairs: I) -> Result<Self, ParseAuthError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let mut version = None;
+        let mut client = None;
+        let mut timestamp = None;
+        let mut key = None;
+        let mut secret = None;
+
+        for (name, value) in pairs {
+            let name = name.as_ref();
+            if !name.starts_with("sentry_") {
+                continue;
+            }
+
+            let value = value.into();
+            match &name["sentry_".len()..] {
+                "version" => {
+                    version = Some(
+                        value
+                            .trim()
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|v| v.is_finite() && *v >= 0.0 && *v < 65536.0)
+                            .map(|v| v as u16)
+                            .ok_or(ParseAuthError::InvalidVersion)?,
+                    )
+                }
+                "client" => client = Some(value),
+                "timestamp" => timestamp = Some(parse_timestamp(value.trim())?),
+                "key" => key = Some(value),
+                "secret" => secret = Some(value),
+                _ => {}
+            }
+        }
+
+        Ok(Auth {
+            version: version.unwrap_or(DEFAULT_VERSION),
+            client,
+            timestamp,
+            key: key
+                .filter(|k| !k.is_empty())
+                .ok_or(ParseAuthError::MissingPublicKey)?,
+            secret,
+        })
+    }
+
+    /// Returns the protocol version the client speaks.
+    ///
+    /// Defaults to version `2` if the client did not send a version.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Returns the client name and version, e.g. `raven-python/6.0.0`.
+    pub fn client_agent(&self) -> Option<&str> {
+        self.client.as_ref().map(String::as_str)
+    }
+
+    /// Returns the time at which the client sent the request.
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+    }
+
+    /// Returns the public key.
+    pub fn public_key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the secret key, if sent.
+    pub fn secret_key(&self) -> Option<&str> {
+        self.secret.as_ref().map(String::as_str)
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, ParseAuthError> {
+    if let Ok(ts) = value.parse::<f64>() {
+        return timestamp_to_datetime(ts).ok_or(ParseAuthError::InvalidTimestamp);
+    }
+
+    match value.parse::<NaiveDateTime>() {
+        Ok(dt) => Ok(DateTime::from_utc(dt, Utc)),
+        Err(_) => value.parse(),
+    }.map_err(|_| ParseAuthError::InvalidTimestamp)
+}
+
+impl str::FromStr for Auth {
+    type Err = ParseAuthError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut tokens = string.trim().splitn(2, char::is_whitespace);
+        match tokens.next() {
+            Some(prefix) if prefix.eq_ignore_ascii_case("sentry") => {}
+            _ => return Err(ParseAuthError::NonSentryAuth),
+        }
+
+        let pairs = tokens.next().unwrap_or("").split(',').filter_map(|item| {
+            let mut kv = item.splitn(2, '=');
+            let key = kv.next()?.trim();
+            let value = kv.next().unwrap_or("").trim();
+            Some((key, value))
+        });
+
+        Auth::from_pairs(pairs)
+    }
+}
+
+impl fmt::Display for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sentry sentry_key={}, sentry_version={}", self.key, self.version)?;
+        if let Some(ref secret) = self.secret {
+            write!(f, ", sentry_secret={}", secret)?;
+        }
+        if let Some(timestamp) = self.timestamp {
+            write!(f, ", sentry_timestamp={}", timestamp.timestamp())?;
+        }
+        if let Some(ref client) = self.client {
+            write!(f, ", sentry_client={}", client)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_auth {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_header() {
+        let auth: Auth = "Sentry sentry_timestamp=1328055286.5, sentry_client=raven-python/42, \
+                          sentry_version=6, sentry_key=public, sentry_secret=secret"
+            .parse()
+            .unwrap();
+
+        assert_eq_dbg!(auth.version(), 6);
+        assert_eq_dbg!(auth.client_agent(), Some("raven-python/42"));
+        assert_eq_dbg!(
+            auth.timestamp(),
+            Some(Utc.ymd(2012, 2, 1).and_hms_milli(0, 14, 46, 500))
+        );
+        assert_eq_str!(auth.public_key(), "public");
+        assert_eq_dbg!(auth.secret_key(), Some("secret"));
+    }
+
+    #[test]
+    fn test_parse_header_minimal() {
+        let auth: Auth = "sentry sentry_key=public".parse().unwrap();
+        assert_eq_dbg!(auth.version(), 2);
+        assert_eq_dbg!(auth.client_agent(), None);
+        assert_eq_dbg!(auth.timestamp(), None);
+        assert_eq_dbg!(auth.secret_key(), None);
+    }
+
+    #[test]
+    fn test_parse_header_iso_timestamp() {
+        let auth: Auth = "Sentry sentry_key=public, sentry_timestamp=2012-02-01T00:14:46"
+            .parse()
+            .unwrap();
+        assert_eq_dbg!(auth.timestamp(), Some(Utc.ymd(2012, 2, 1).and_hms(0, 14, 46)));
+    }
+
+    #[test]
+    fn test_parse_header_errors() {
+        assert_eq_dbg!(
+            "Basic dXNlcjpwYXNz".parse::<Auth>(),
+            Err(ParseAuthError::NonSentryAuth)
+        );
+        assert_eq_dbg!(
+            "Sentry sentry_version=7".parse::<Auth>(),
+            Err(ParseAuthError::MissingPublicKey)
+        );
+        assert_eq_dbg!(
+            "Sentry sentry_key=public, sentry_version=seven".parse::<Auth>(),
+            Err(ParseAuthError::InvalidVersion)
+        );
+        assert_eq_dbg!(
+            "Sentry sentry_key=public, sentry_timestamp=yesterday".parse::<Auth>(),
+            Err(ParseAuthError::InvalidTimestamp)
+        );
+        assert_eq_dbg!(
+            "Sentry sentry_key=public, sentry_timestamp=1e20".parse::<Auth>(),
+            Err(ParseAuthError::InvalidTimestamp)
+        );
+        assert_eq_dbg!(
+            "Sentry sentry_key=public, sentry_timestamp=-1e20".parse::<Auth>(),
+            Err(ParseAuthError::InvalidTimestamp)
+        );
+    }
+
+    #[test]
+    fn test_parse_unprefixed_keys() {
+        assert_eq_dbg!(
+            "Sentry key=public".parse::<Auth>(),
+            Err(ParseAuthError::MissingPublicKey)
+        );
+
+        let auth: Auth = "Sentry sentry_key=public, version=7, sentry_sentry_version=6"
+            .parse()
+            .unwrap();
+        assert_eq_dbg!(auth.version(), 2);
+    }
+
+    #[test]
+    fn test_parse_querystring() {
+        let auth = Auth::from_querystring(
+            b"sentry_version=7&sentry_key=public&sentry_client=raven-js%2F3.23.3&foo=bar&key=other",
+        ).unwrap();
+
+        assert_eq_dbg!(auth.version(), 7);
+        assert_eq_str!(auth.public_key(), "public");
+        assert_eq_dbg!(auth.client_agent(), Some("raven-js/3.23.3"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let header = "Sentry sentry_key=public, sentry_version=7, sentry_secret=secret, \
+                      sentry_timestamp=1328055286, sentry_client=raven-python/42";
+        let auth: Auth = header.parse().unwrap();
+        assert_eq_str!(auth.to_string(), header);
+    }
+}
@@ -1,3 +1,446 @@
 //! Serde buffers.
+//!
+//! `Content` buffers any self-describing input, so that it can be inspected before it is
+//! deserialized into its target type. Borrowed strings and bytes are retained. This is a minimal
+//! version of Serde's private buffer, which is not part of its public API.
 
-pub use serde::private::de::{Content, ContentDeserializer, ContentRefDeserializer, ContentRepr};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, Unexpected, VariantAccess, Visitor,
+};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A buffered value of any self-describing format.
+#[derive(Debug, Clone)]
+pub enum Content<'de> {
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Char(char),
+    String(String),
+    Str(&'de str),
+    ByteBuf(Vec<u8>),
+    Bytes(&'de [u8]),
+    None,
+    Some(Box<Content<'de>>),
+    Unit,
+    Newtype(Box<Content<'de>>),
+    Seq(Vec<Content<'de>>),
+    Map(Vec<(Content<'de>, Content<'de>)>),
+}
+
+impl<'de> Content<'de> {
+    fn unexpected(&self) -> Unexpected {
+        match *self {
+            Content::Bool(b) => Unexpected::Bool(b),
+            Content::U64(n) => Unexpected::Unsigned(n),
+            Content::I64(n) => Unexpected::Signed(n),
+            Content::F64(f) => Unexpected::Float(f),
+            Content::Char(c) => Unexpected::Char(c),
+            Content::String(ref s) => Unexpected::Str(s),
+            Content::Str(s) => Unexpected::Str(s),
+            Content::ByteBuf(ref b) => Unexpected::Bytes(b),
+            Content::Bytes(b) => Unexpected::Bytes(b),
+            Content::None | Content::Some(_) => Unexpected::Option,
+            Content::Unit => Unexpected::Unit,
+            Content::Newtype(_) => Unexpected::NewtypeStruct,
+            Content::Seq(_) => Unexpected::Seq,
+            Content::Map(_) => Unexpected::Map,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Content<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ContentVisitor)
+    }
+}
+
+struct ContentVisitor;
+
+impl<'de> Visitor<'de> for ContentVisitor {
+    type Value = Content<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E> {
+        Ok(Content::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(Content::I64(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(Content::U64(value))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E> {
+        Ok(Content::F64(value))
+    }
+
+    fn visit_char<E>(self, value: char) -> Result<Self::Value, E> {
+        Ok(Content::Char(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(Content::String(value.to_owned()))
+    }
+
+    fn visit_borrowed_str<E>(self, value: &'de str) -> Result<Self::Value, E> {
+        Ok(Content::Str(value))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Self::Value, E> {
+        Ok(Content::String(value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E> {
+        Ok(Content::ByteBuf(value.to_owned()))
+    }
+
+    fn visit_borrowed_bytes<E>(self, value: &'de [u8]) -> Result<Self::Value, E> {
+        Ok(Content::Bytes(value))
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Content::ByteBuf(value))
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(Content::Unit)
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(Content::None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        Deserialize::deserialize(deserializer).map(|v| Content::Some(Box::new(v)))
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        Deserialize::deserialize(deserializer).map(|v| Content::Newtype(Box::new(v)))
+    }
+
+    fn visit_seq<V: SeqAccess<'de>>(self, mut visitor: V) -> Result<Self::Value, V::Error> {
+        let mut vec = Vec::with_capacity(visitor.size_hint().unwrap_or(0).min(4096));
+        while let Some(element) = visitor.next_element()? {
+            vec.push(element);
+        }
+        Ok(Content::Seq(vec))
+    }
+
+    fn visit_map<V: MapAccess<'de>>(self, mut visitor: V) -> Result<Self::Value, V::Error> {
+        let mut vec = Vec::with_capacity(visitor.size_hint().unwrap_or(0).min(4096));
+        while let Some(entry) = visitor.next_entry()? {
+            vec.push(entry);
+        }
+        Ok(Content::Map(vec))
+    }
+}
+
+/// A deserializer that consumes buffered content.
+pub struct ContentDeserializer<'de, E> {
+    content: Content<'de>,
+    err: PhantomData<E>,
+}
+
+impl<'de, E: de::Error> ContentDeserializer<'de, E> {
+    /// Creates a deserializer for the given content.
+    pub fn new(content: Content<'de>) -> Self {
+        ContentDeserializer {
+            content,
+            err: PhantomData,
+        }
+    }
+}
+
+impl<'de, E: de::Error> Deserializer<'de> for ContentDeserializer<'de, E> {
+    type Error = E;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        match self.content {
+            Content::Bool(b) => visitor.visit_bool(b),
+            Content::U64(n) => visitor.visit_u64(n),
+            Content::I64(n) => visitor.visit_i64(n),
+            Content::F64(f) => visitor.visit_f64(f),
+            Content::Char(c) => visitor.visit_char(c),
+            Content::String(s) => visitor.visit_string(s),
+            Content::Str(s) => visitor.visit_borrowed_str(s),
+            Content::ByteBuf(b) => visitor.visit_byte_buf(b),
+            Content::Bytes(b) => visitor.visit_borrowed_bytes(b),
+            Content::None => visitor.visit_none(),
+            Content::Some(v) => visitor.visit_some(ContentDeserializer::new(*v)),
+            Content::Unit => visitor.visit_unit(),
+            Content::Newtype(v) => visitor.visit_newtype_struct(ContentDeserializer::new(*v)),
+            Content::Seq(v) => {
+                let mut seq = SeqDeserializer::new(v.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Content::Map(v) => {
+                let mut map = MapDeserializer::new(v.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        match self.content {
+            Content::None | Content::Unit => visitor.visit_none(),
+            Content::Some(v) => visitor.visit_some(ContentDeserializer::new(*v)),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        match self.content {
+            Content::Newtype(v) => visitor.visit_newtype_struct(ContentDeserializer::new(*v)),
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        let (variant, value) = match self.content {
+            Content::Map(mut entries) => {
+                if entries.len() != 1 {
+                    return Err(de::Error::invalid_value(
+                        Unexpected::Map,
+                        &"map with a single key",
+                    ));
+                }
+                let (variant, value) = entries.pop().unwrap();
+                (variant, Some(value))
+            }
+            content @ Content::String(_) | content @ Content::Str(_) => (content, None),
+            other => {
+                return Err(de::Error::invalid_type(
+                    other.unexpected(),
+                    &"string or map",
+                ))
+            }
+        };
+
+        visitor.visit_enum(EnumDeserializer {
+            variant,
+            value,
+            err: PhantomData,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de, E: de::Error> IntoDeserializer<'de, E> for Content<'de> {
+    type Deserializer = ContentDeserializer<'de, E>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        ContentDeserializer::new(self)
+    }
+}
+
+/// Provides the variant and value of an externally tagged enum.
+struct EnumDeserializer<'de, E> {
+    variant: Content<'de>,
+    value: Option<Content<'de>>,
+    err: PhantomData<E>,
+}
+
+impl<'de, E: de::Error> EnumAccess<'de> for EnumDeserializer<'de, E> {
+    type Error = E;
+    type Variant = VariantDeserializer<'de, E>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), E> {
+        let variant = seed.deserialize(ContentDeserializer::<E>::new(self.variant))?;
+        let visitor = VariantDeserializer {
+            value: self.value,
+            err: PhantomData,
+        };
+        Ok((variant, visitor))
+    }
+}
+
+/// Provides the value of an enum variant.
+struct VariantDeserializer<'de, E> {
+    value: Option<Content<'de>>,
+    err: PhantomData<E>,
+}
+
+impl<'de, E: de::Error> VariantAccess<'de> for VariantDeserializer<'de, E> {
+    type Error = E;
+
+    fn unit_variant(self) -> Result<(), E> {
+        match self.value {
+            Some(value) => Deserialize::deserialize(ContentDeserializer::new(value)),
+            None => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, E> {
+        match self.value {
+            Some(value) => seed.deserialize(ContentDeserializer::new(value)),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, E> {
+        match self.value {
+            Some(value @ Content::Seq(_)) => {
+                ContentDeserializer::new(value).deserialize_any(visitor)
+            }
+            Some(other) => Err(de::Error::invalid_type(
+                other.unexpected(),
+                &"tuple variant",
+            )),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        match self.value {
+            Some(value @ Content::Map(_)) => {
+                ContentDeserializer::new(value).deserialize_any(visitor)
+            }
+            Some(value @ Content::Seq(_)) => {
+                ContentDeserializer::new(value).deserialize_any(visitor)
+            }
+            Some(other) => Err(de::Error::invalid_type(
+                other.unexpected(),
+                &"struct variant",
+            )),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
+
+/// A deserializer that borrows buffered content, so that it can be deserialized again.
+pub struct ContentRefDeserializer<'a, 'de: 'a, E> {
+    content: &'a Content<'de>,
+    err: PhantomData<E>,
+}
+
+impl<'a, 'de, E: de::Error> ContentRefDeserializer<'a, 'de, E> {
+    /// Creates a deserializer for the given content.
+    pub fn new(content: &'a Content<'de>) -> Self {
+        ContentRefDeserializer {
+            content,
+            err: PhantomData,
+        }
+    }
+}
+
+impl<'a, 'de, E: de::Error> Deserializer<'de> for ContentRefDeserializer<'a, 'de, E> {
+    type Error = E;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        match *self.content {
+            Content::Bool(b) => visitor.visit_bool(b),
+            Content::U64(n) => visitor.visit_u64(n),
+            Content::I64(n) => visitor.visit_i64(n),
+            Content::F64(f) => visitor.visit_f64(f),
+            Content::Char(c) => visitor.visit_char(c),
+            Content::String(ref s) => visitor.visit_str(s),
+            Content::Str(s) => visitor.visit_borrowed_str(s),
+            Content::ByteBuf(ref b) => visitor.visit_bytes(b),
+            Content::Bytes(b) => visitor.visit_borrowed_bytes(b),
+            Content::None => visitor.visit_none(),
+            Content::Some(ref v) => visitor.visit_some(ContentRefDeserializer::new(v)),
+            Content::Unit => visitor.visit_unit(),
+            Content::Newtype(ref v) => visitor.visit_newtype_struct(ContentRefDeserializer::new(v)),
+            Content::Seq(ref v) => {
+                let mut seq = SeqDeserializer::new(v.iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Content::Map(ref v) => {
+                let mut map = MapDeserializer::new(v.iter().map(|&(ref k, ref v)| (k, v)));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+        match *self.content {
+            Content::None | Content::Unit => visitor.visit_none(),
+            Content::Some(ref v) => visitor.visit_some(ContentRefDeserializer::new(v)),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        match *self.content {
+            Content::Newtype(ref v) => visitor.visit_newtype_struct(ContentRefDeserializer::new(v)),
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E> {
+        // Enums are rare in buffered content, so they are deserialized from a copy.
+        ContentDeserializer::new(self.content.clone()).deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'a, 'de, E: de::Error> IntoDeserializer<'de, E> for &'a Content<'de> {
+    type Deserializer = ContentRefDeserializer<'a, 'de, E>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        ContentRefDeserializer::new(self)
+    }
+}
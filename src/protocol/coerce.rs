@@ -0,0 +1,306 @@
+//! Lenient coercion of primitive values that fail to deserialize.
+
+use std::cell::Cell;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde_json::{self, Number, Value};
+
+use super::serde::CustomDeserialize;
+
+/// The rule id used for remarks on coerced values.
+pub const COERCION_RULE_ID: &str = "@coercion";
+
+/// The kind of value requested by a deserialize implementation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Expected {
+    Any,
+    Bool,
+    Int,
+    Float,
+    String,
+    Other,
+}
+
+/// A deserializer that records which kind of value a deserialize implementation requests.
+#[derive(Clone, Copy)]
+struct Probe<'a>(&'a Cell<Expected>);
+
+impl<'a> Probe<'a> {
+    fn expect<T>(self, expected: Expected) -> Result<T, serde_json::Error> {
+        self.0.set(expected);
+        Err(de::Error::custom("probed"))
+    }
+}
+
+macro_rules! probe {
+    ($($method:ident => $expected:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+                self.expect(Expected::$expected)
+            }
+        )*
+    };
+}
+
+impl<'a, 'de> Deserializer<'de> for Probe<'a> {
+    type Error = serde_json::Error;
+
+    probe! {
+        deserialize_any => Any,
+        deserialize_bool => Bool,
+        deserialize_i8 => Int,
+        deserialize_i16 => Int,
+        deserialize_i32 => Int,
+        deserialize_i64 => Int,
+        deserialize_u8 => Int,
+        deserialize_u16 => Int,
+        deserialize_u32 => Int,
+        deserialize_u64 => Int,
+        deserialize_f32 => Float,
+        deserialize_f64 => Float,
+        deserialize_char => String,
+        deserialize_str => String,
+        deserialize_string => String,
+        deserialize_bytes => Other,
+        deserialize_byte_buf => Other,
+        deserialize_unit => Other,
+        deserialize_seq => Other,
+        deserialize_map => Other,
+        deserialize_identifier => Other,
+        deserialize_ignored_any => Other,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.expect(Expected::Other)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.expect(Expected::Other)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.expect(Expected::Other)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.expect(Expected::Other)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.expect(Expected::Other)
+    }
+}
+
+/// Determines the kind of value requested by a deserialize implementation.
+fn expected<'de, T, C>() -> Expected
+where
+    C: CustomDeserialize<'de, T>,
+{
+    let expected = Cell::new(Expected::Other);
+    C::deserialize(Probe(&expected)).ok();
+    expected.get()
+}
+
+/// Converts an integral float into an integer value without losing precision.
+fn lossless_int(float: f64) -> Option<Value> {
+    if float.fract() != 0.0 {
+        None
+    } else if float >= 0.0 && float < u64::max_value() as f64 {
+        Some(Value::from(float as u64))
+    } else if float < 0.0 && float >= i64::min_value() as f64 {
+        Some(Value::from(float as i64))
+    } else {
+        None
+    }
+}
+
+/// Parses a numeric string into an integer or float value.
+fn parse_number(string: &str) -> Option<Value> {
+    let string = string.trim();
+    if let Ok(int) = string.parse::<u64>() {
+        Some(Value::from(int))
+    } else if let Ok(int) = string.parse::<i64>() {
+        Some(Value::from(int))
+    } else {
+        string
+            .parse()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+    }
+}
+
+/// Parses a bool-like string.
+fn parse_bool(string: &str) -> Option<Value> {
+    match string.trim().to_lowercase().as_str() {
+        "true" | "1" => Some(Value::Bool(true)),
+        "false" | "0" => Some(Value::Bool(false)),
+        _ => None,
+    }
+}
+
+/// Converts a primitive value into the expected kind of value, if possible.
+fn coerce_value(value: &Value, expected: Expected) -> Option<Value> {
+    match *value {
+        Value::String(ref string) => match expected {
+            Expected::Bool => parse_bool(string),
+            Expected::Int => match parse_number(string)? {
+                Value::Number(ref number) if number.is_f64() => lossless_int(number.as_f64()?),
+                number => Some(number),
+            },
+            Expected::Float | Expected::Any => parse_number(string),
+            _ => None,
+        },
+        Value::Number(ref number) => match expected {
+            Expected::Int if number.is_f64() => lossless_int(number.as_f64()?),
+            Expected::String => Some(Value::String(number.to_string())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the text of a primitive, which is the contents of strings and the JSON of other values.
+fn to_text(value: &Value) -> String {
+    match *value {
+        Value::String(ref string) => string.clone(),
+        ref other => other.to_string(),
+    }
+}
+
+/// Deserializes a primitive value after coercing it into the type requested by `C`.
+///
+/// This is used for values that failed to deserialize in their original type. Numeric strings
+/// are coerced into integers and floats, bool-like strings into booleans, numbers into strings
+/// and integral floats into integers. Returns `None` if no coercion applies or the coerced value
+/// is still invalid.
+///
+/// Along with the coerced value, this returns whether the coercion is lossless. This is the case
+/// if the coerced value has the same text as the original, like `"42"` and `42`, but not for
+/// `" 42 "` or `"1"` coerced into `true`.
+pub fn coerce<'de, T, C>(value: &Value) -> Option<(T, bool)>
+where
+    C: CustomDeserialize<'de, T>,
+{
+    let coerced = coerce_value(value, expected::<T, C>())?;
+    let lossless = to_text(&coerced) == to_text(value);
+    C::deserialize(coerced)
+        .ok()
+        .map(|coerced| (coerced, lossless))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::serde::DefaultDeserialize;
+
+    fn coerce_json<T>(json: &str) -> Option<T>
+    where
+        for<'de> DefaultDeserialize<T>: CustomDeserialize<'de, T>,
+    {
+        coerce::<T, DefaultDeserialize<T>>(&serde_json::from_str(json).unwrap())
+            .map(|(coerced, _)| coerced)
+    }
+
+    fn is_lossless<T>(json: &str) -> bool
+    where
+        for<'de> DefaultDeserialize<T>: CustomDeserialize<'de, T>,
+    {
+        coerce::<T, DefaultDeserialize<T>>(&serde_json::from_str(json).unwrap())
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn test_string_to_int() {
+        assert_eq_dbg!(coerce_json::<u32>(r#""42""#), Some(42));
+        assert_eq_dbg!(coerce_json::<i64>(r#"" -42 ""#), Some(-42));
+        assert_eq_dbg!(coerce_json::<u64>(r#""42.0""#), Some(42));
+        assert_eq_dbg!(coerce_json::<Option<u64>>(r#""42""#), Some(Some(42)));
+        assert_eq_dbg!(coerce_json::<u64>(r#""42.5""#), None);
+        assert_eq_dbg!(coerce_json::<u64>(r#""foo""#), None);
+    }
+
+    #[test]
+    fn test_string_to_float() {
+        assert_eq_dbg!(coerce_json::<f64>(r#""42.5""#), Some(42.5));
+        assert_eq_dbg!(coerce_json::<f64>(r#""42""#), Some(42.0));
+        assert_eq_dbg!(coerce_json::<f64>(r#""NaN""#), None);
+    }
+
+    #[test]
+    fn test_string_to_bool() {
+        assert_eq_dbg!(coerce_json::<bool>(r#""true""#), Some(true));
+        assert_eq_dbg!(coerce_json::<bool>(r#""False""#), Some(false));
+        assert_eq_dbg!(coerce_json::<bool>(r#""1""#), Some(true));
+        assert_eq_dbg!(coerce_json::<bool>(r#""yes""#), None);
+    }
+
+    #[test]
+    fn test_number_to_string() {
+        assert_eq_dbg!(coerce_json::<String>("42"), Some("42".to_string()));
+        assert_eq_dbg!(coerce_json::<String>("-1.5"), Some("-1.5".to_string()));
+    }
+
+    #[test]
+    fn test_float_to_int() {
+        assert_eq_dbg!(coerce_json::<u64>("42.0"), Some(42));
+        assert_eq_dbg!(coerce_json::<i32>("-42.0"), Some(-42));
+        assert_eq_dbg!(coerce_json::<u64>("42.5"), None);
+        assert_eq_dbg!(coerce_json::<u8>("1e10"), None);
+    }
+
+    #[test]
+    fn test_lossless() {
+        assert!(is_lossless::<u64>(r#""42""#));
+        assert!(is_lossless::<bool>(r#""true""#));
+        assert!(is_lossless::<String>("-1.5"));
+        assert!(!is_lossless::<u64>(r#"" 42 ""#));
+        assert!(!is_lossless::<u64>(r#""42.0""#));
+        assert!(!is_lossless::<u64>("42.0"));
+        assert!(!is_lossless::<bool>(r#""1""#));
+        assert!(!is_lossless::<bool>(r#""False""#));
+    }
+
+    #[test]
+    fn test_no_coercion() {
+        assert_eq_dbg!(coerce_json::<String>("true"), None);
+        assert_eq_dbg!(coerce_json::<u64>("[42]"), None);
+        assert_eq_dbg!(coerce_json::<Vec<String>>("42"), None);
+    }
+}
@@ -5,7 +5,9 @@ use std::fmt;
 
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
+use super::buffer::{Content, ContentDeserializer};
 use super::meta::Annotated;
+use super::tracked::{take_context, TrackedContext, TrackedDeserializer};
 
 /// A list of annotated values.
 pub type Array<V> = Vec<Annotated<V>>;
@@ -367,23 +369,45 @@ where
 {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         #[derive(Deserialize)]
-        #[serde(untagged)]
-        #[cfg_attr(feature = "cargo-clippy", allow(large_enum_variant))]
-        enum Repr<T> {
-            Qualified {
-                values: Annotated<Array<T>>,
-                #[serde(flatten)]
-                other: Annotated<Map<Value>>,
-            },
-            Unqualified(Array<T>),
-            Single(Annotated<T>),
+        struct Qualified<T> {
+            values: Annotated<Array<T>>,
+            #[serde(flatten)]
+            other: Annotated<Map<Value>>,
+        }
+
+        // The representation is chosen from the buffered content instead of an untagged enum,
+        // which would try every variant without tracking and lose paths and meta data.
+        let context = take_context().unwrap_or_else(TrackedContext::untracked);
+        let content = Content::deserialize(deserializer)?;
+
+        match content {
+            Content::Map(ref entries) if entries.iter().any(|(key, _)| is_values_key(key)) => {
+                let deserializer =
+                    TrackedDeserializer::new(ContentDeserializer::new(content), context);
+                let Qualified { values, other } = Qualified::deserialize(deserializer)?;
+                Ok(Values { values, other })
+            }
+            Content::Seq(_) => {
+                let context = context.enter_key("values");
+                let deserializer =
+                    TrackedDeserializer::new(ContentDeserializer::new(content), context);
+                Array::deserialize(deserializer).map(Values::from)
+            }
+            _ => {
+                let context = context.enter_key("values").enter_index(0);
+                let deserializer =
+                    TrackedDeserializer::new(ContentDeserializer::new(content), context);
+                Annotated::deserialize(deserializer).map(|value| Values::from(vec![value]))
+            }
         }
+    }
+}
 
-        Deserialize::deserialize(deserializer).map(|x| match x {
-            Repr::Qualified { values, other } => Values { values, other },
-            Repr::Unqualified(values) => values.into(),
-            Repr::Single(value) => vec![value].into(),
-        })
+fn is_values_key(key: &Content) -> bool {
+    match *key {
+        Content::String(ref key) => key == "values",
+        Content::Str(key) => key == "values",
+        _ => false,
     }
 }
 
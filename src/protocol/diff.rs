@@ -0,0 +1,420 @@
+//! Structural diffs between annotated values.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::ser::Serialize;
+use serde_json::{self, Value};
+
+use super::meta::{serialize_meta, Annotated, Error};
+
+/// The kind of a change between two annotated values.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChangeKind {
+    /// A value was set where there was none before.
+    Added,
+    /// A value was removed.
+    Removed,
+    /// A value was replaced with a different value.
+    Changed,
+    /// The meta data of a value changed, regardless of the value itself.
+    Meta,
+}
+
+/// A single change between two annotated values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The kind of this change.
+    pub kind: ChangeKind,
+    /// Path to the changed value, like `exception.values.0.type`.
+    pub path: String,
+    /// The old value, or the old meta data for meta changes.
+    pub old: Option<Value>,
+    /// The new value, or the new meta data for meta changes.
+    pub new: Option<Value>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Json<'a>(&'a Option<Value>);
+
+        impl<'a> fmt::Display for Json<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match *self.0 {
+                    Some(ref value) => write!(f, "{}", value),
+                    None => f.write_str("(none)"),
+                }
+            }
+        }
+
+        match self.kind {
+            ChangeKind::Added => write!(f, "+ {}: {}", self.path, Json(&self.new)),
+            ChangeKind::Removed => write!(f, "- {}: {}", self.path, Json(&self.old)),
+            ChangeKind::Changed => write!(
+                f,
+                "~ {}: {} -> {}",
+                self.path,
+                Json(&self.old),
+                Json(&self.new)
+            ),
+            ChangeKind::Meta => write!(
+                f,
+                "! {} (meta): {} -> {}",
+                self.path,
+                Json(&self.old),
+                Json(&self.new)
+            ),
+        }
+    }
+}
+
+/// A list of changes between two annotated values, ordered depth-first by their paths.
+///
+/// The `Display` implementation renders one change per line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff(Vec<Change>);
+
+impl Diff {
+    /// Returns `true` if there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The list of changes.
+    pub fn changes(&self) -> &[Change] {
+        &self.0
+    }
+}
+
+impl IntoIterator for Diff {
+    type Item = Change;
+    type IntoIter = ::std::vec::IntoIter<Change>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("(no differences)");
+        }
+
+        for (index, change) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}", change)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A value in the serialized value and meta data trees of an annotated value.
+#[derive(Clone, Copy, Default)]
+struct Node<'a> {
+    value: Option<&'a Value>,
+    meta: Option<&'a Value>,
+}
+
+impl<'a> Node<'a> {
+    /// The meta data of this value, excluding meta data of nested values.
+    fn own_meta(&self) -> Option<&'a Value> {
+        self.meta.and_then(|meta| meta.get(""))
+    }
+
+    /// The value, treating `null` as missing.
+    fn present_value(&self) -> Option<&'a Value> {
+        self.value.filter(|value| !value.is_null())
+    }
+
+    /// Returns the child at the given key, optionally without its value.
+    fn child(&self, key: &str, with_value: bool) -> Node<'a> {
+        let value = self
+            .value
+            .filter(|_| with_value)
+            .and_then(|value| match *value {
+                Value::Object(ref map) => map.get(key),
+                Value::Array(ref array) => {
+                    key.parse::<usize>().ok().and_then(|index| array.get(index))
+                }
+                _ => None,
+            });
+
+        Node {
+            value,
+            meta: self.meta.and_then(|meta| meta.get(key)),
+        }
+    }
+}
+
+/// Collects the keys of all children of two nodes in a stable order.
+///
+/// Object keys are sorted, array indexes are numeric. Keys that only occur in the meta data are
+/// appended in sorted order.
+fn child_keys(old: Node, new: Node, with_values: bool) -> Vec<String> {
+    let objects = (
+        old.value.and_then(Value::as_object),
+        new.value.and_then(Value::as_object),
+    );
+    let arrays = (
+        old.value.and_then(Value::as_array),
+        new.value.and_then(Value::as_array),
+    );
+
+    let mut keys: Vec<String> = match (objects, arrays) {
+        ((Some(old), Some(new)), _) if with_values => old
+            .keys()
+            .chain(new.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+        (_, (Some(old), Some(new))) if with_values => (0..old.len().max(new.len()))
+            .map(|i| i.to_string())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let meta_keys: BTreeSet<&String> = old
+        .meta
+        .into_iter()
+        .chain(new.meta)
+        .filter_map(Value::as_object)
+        .flat_map(|map| map.keys())
+        .filter(|key| !key.is_empty())
+        .collect();
+
+    for key in meta_keys {
+        if !keys.contains(key) {
+            keys.push(key.clone());
+        }
+    }
+
+    keys
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path == "." {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn push_change(
+    changes: &mut Vec<Change>,
+    kind: ChangeKind,
+    path: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+) {
+    changes.push(Change {
+        kind,
+        path: path.to_string(),
+        old: old.cloned(),
+        new: new.cloned(),
+    });
+}
+
+/// Compares two nodes and all of their children.
+fn diff_node(changes: &mut Vec<Change>, path: &str, old: Node, new: Node) {
+    // Objects and arrays are compared by their children. All other values, including containers
+    // replaced by a different kind of value, are compared as a whole.
+    let with_values = match (old.value, new.value) {
+        (Some(&Value::Object(_)), Some(&Value::Object(_)))
+        | (Some(&Value::Array(_)), Some(&Value::Array(_))) => true,
+        _ => {
+            match (old.present_value(), new.present_value()) {
+                (None, Some(new)) => push_change(changes, ChangeKind::Added, path, None, Some(new)),
+                (Some(old), None) => {
+                    push_change(changes, ChangeKind::Removed, path, Some(old), None)
+                }
+                (Some(old), Some(new)) if old != new => {
+                    push_change(changes, ChangeKind::Changed, path, Some(old), Some(new))
+                }
+                _ => (),
+            }
+            false
+        }
+    };
+
+    if old.own_meta() != new.own_meta() {
+        push_change(
+            changes,
+            ChangeKind::Meta,
+            path,
+            old.own_meta(),
+            new.own_meta(),
+        );
+    }
+
+    for key in child_keys(old, new, with_values) {
+        let path = child_path(path, &key);
+        diff_node(
+            changes,
+            &path,
+            old.child(&key, with_values),
+            new.child(&key, with_values),
+        );
+    }
+}
+
+impl<T: Serialize> Annotated<T> {
+    /// Computes the structural differences from this annotated value to another one.
+    ///
+    /// Both values are compared along with their meta data. Changes of meta data are reported
+    /// separately from changes of the values they belong to.
+    pub fn diff(&self, other: &Self) -> Result<Diff, Error> {
+        use serde::ser::Error;
+
+        let old_value = serde_json::to_value(self)?;
+        let new_value = serde_json::to_value(other)?;
+        let old_meta = serialize_meta(self).map_err(serde_json::Error::custom)?;
+        let old_meta = serde_json::to_value(old_meta)?;
+        let new_meta = serialize_meta(other).map_err(serde_json::Error::custom)?;
+        let new_meta = serde_json::to_value(new_meta)?;
+
+        let old = Node {
+            value: Some(&old_value),
+            meta: Some(&old_meta),
+        };
+
+        let new = Node {
+            value: Some(&new_value),
+            meta: Some(&new_meta),
+        };
+
+        let mut changes = Vec::new();
+        diff_node(&mut changes, ".", old, new);
+        Ok(Diff(changes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{Map, Meta, Remark, RemarkType};
+
+    #[derive(Clone, Debug, Default, Serialize)]
+    struct Test {
+        name: Annotated<Option<String>>,
+        values: Annotated<Vec<Annotated<i32>>>,
+        other: Annotated<Map<String>>,
+    }
+
+    fn test() -> Annotated<Test> {
+        let mut other = Map::new();
+        other.insert("foo".to_string(), Annotated::from("bar".to_string()));
+
+        Annotated::from(Test {
+            name: Annotated::from(Some("test".to_string())),
+            values: Annotated::from(vec![Annotated::from(1), Annotated::from(2)]),
+            other: Annotated::from(other),
+        })
+    }
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    fn change(kind: ChangeKind, path: &str, old: Option<Value>, new: Option<Value>) -> Change {
+        Change {
+            kind,
+            path: path.to_string(),
+            old,
+            new,
+        }
+    }
+
+    #[test]
+    fn test_equal() {
+        let diff = test().diff(&test()).unwrap();
+        assert!(diff.is_empty());
+        assert_eq_str!(diff.to_string(), "(no differences)");
+    }
+
+    #[test]
+    fn test_values() {
+        let old = test();
+        let mut new = test();
+        {
+            let test = new.value_mut().unwrap();
+            test.values.value_mut().unwrap()[0] = Annotated::from(3);
+            test.values.value_mut().unwrap().push(Annotated::from(4));
+            test.other.value_mut().unwrap().clear();
+            test.other
+                .value_mut()
+                .unwrap()
+                .insert("baz".to_string(), Annotated::from("qux".to_string()));
+        }
+
+        let diff = old.diff(&new).unwrap();
+        assert_eq_dbg!(
+            diff.changes(),
+            &[
+                change(ChangeKind::Added, "other.baz", None, Some("qux".into())),
+                change(ChangeKind::Removed, "other.foo", Some("bar".into()), None),
+                change(
+                    ChangeKind::Changed,
+                    "values.0",
+                    Some(1.into()),
+                    Some(3.into())
+                ),
+                change(ChangeKind::Added, "values.2", None, Some(4.into())),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn test_meta() {
+        let old = test();
+        let mut new = test();
+        {
+            let test = new.value_mut().unwrap();
+            test.name =
+                Annotated::from(None).with_removed_value(Remark::new(RemarkType::Removed, "@test"));
+            test.name.meta_mut().set_original_length(Some(4));
+            *test.values.value_mut().unwrap()[1].meta_mut() = Meta::from_error("some error");
+        }
+
+        let diff = old.diff(&new).unwrap();
+        assert_eq_dbg!(
+            diff.changes(),
+            &[
+                change(ChangeKind::Removed, "name", Some("test".into()), None),
+                change(
+                    ChangeKind::Meta,
+                    "name",
+                    None,
+                    Some(json(r#"{"rem":[["@test","x"]],"len":4}"#)),
+                ),
+                change(
+                    ChangeKind::Meta,
+                    "values.1",
+                    None,
+                    Some(json(r#"{"err":["some error"]}"#)),
+                ),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let old = test();
+        let mut new = test();
+        {
+            let test = new.value_mut().unwrap();
+            test.name = Annotated::from(Some("other".to_string()));
+            *test.values.meta_mut() = Meta::from_error("some error");
+        }
+
+        assert_eq_str!(
+            old.diff(&new).unwrap().to_string(),
+            r#"~ name: "test" -> "other"
+! values (meta): (none) -> {"err":["some error"]}"#
+        );
+    }
+}
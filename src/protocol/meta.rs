@@ -4,24 +4,35 @@ use std::borrow;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::io;
 use std::iter::FromIterator;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use serde::de::{self, Deserialize, Deserializer, IgnoredAny};
 use serde::ser::{Serialize, SerializeSeq, Serializer};
+use serde_cbor;
 use serde_json;
 
-use super::buffer::{Content, ContentDeserializer, ContentRefDeserializer, ContentRepr};
-use super::meta_ser::{serialize_annotated_meta, MetaError, MetaSerializer, MetaTree};
+use super::buffer::{Content, ContentDeserializer, ContentRefDeserializer};
+use super::coerce::{coerce, COERCION_RULE_ID};
+use super::meta_ser::{
+    serialize_annotated_meta, MetaError as MetaSerializeError, MetaSerializer, MetaTree,
+};
 use super::serde::{CustomDeserialize, CustomSerialize, DefaultDeserialize, DefaultSerialize};
-use super::tracked::{Path, TrackedDeserializer};
+use super::tracked::{
+    take_context, ContextScope, DeserializeLimits, LimitViolation, Limiter, Path, TrackedContext,
+    TrackedDeserializer,
+};
 
 pub use serde_json::Error;
 
 /// Internal synchronization for meta data serialization.
 thread_local!(static SERIALIZE_META: AtomicBool = AtomicBool::new(false));
 
+/// The maximum size of an original value retained for invalid data, in bytes of JSON.
+const MAX_ORIGINAL_VALUE_SIZE: usize = 500;
+
 /// The start (inclusive) and end (exclusive) indices of a `Remark`.
 pub type Range = (usize, usize);
 
@@ -151,6 +162,242 @@ impl Serialize for Remark {
     }
 }
 
+/// The kind of an error in the meta data of a field.
+///
+/// Every kind has a stable code, which is used when serializing meta data.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The data could not be parsed or has an invalid type.
+    InvalidData,
+    /// A required attribute is missing.
+    MissingAttribute,
+    /// An attribute or key is not valid.
+    InvalidAttribute,
+    /// The value exceeds the maximum length.
+    ValueTooLong,
+    /// A collection has too many entries, or the input has too many values in total.
+    TooManyValues,
+    /// The value is nested too deeply.
+    NestingTooDeep,
+    /// A timestamp is too far in the future.
+    FutureTimestamp,
+    /// A timestamp is too far in the past.
+    PastTimestamp,
+    /// An error code or legacy error message that is not known to this library.
+    Unknown(String),
+}
+
+impl ErrorKind {
+    /// Returns the stable code of this error kind.
+    pub fn code(&self) -> &str {
+        match *self {
+            ErrorKind::InvalidData => "invalid_data",
+            ErrorKind::MissingAttribute => "missing_attribute",
+            ErrorKind::InvalidAttribute => "invalid_attribute",
+            ErrorKind::ValueTooLong => "value_too_long",
+            ErrorKind::TooManyValues => "too_many_values",
+            ErrorKind::NestingTooDeep => "nesting_too_deep",
+            ErrorKind::FutureTimestamp => "future_timestamp",
+            ErrorKind::PastTimestamp => "past_timestamp",
+            ErrorKind::Unknown(ref code) => code,
+        }
+    }
+
+    /// Returns a human readable description of this error kind.
+    fn description(&self) -> &str {
+        match *self {
+            ErrorKind::InvalidData => "invalid data",
+            ErrorKind::MissingAttribute => "missing attribute",
+            ErrorKind::InvalidAttribute => "invalid attribute",
+            ErrorKind::ValueTooLong => "value too long",
+            ErrorKind::TooManyValues => "too many values",
+            ErrorKind::NestingTooDeep => "value is nested too deeply",
+            ErrorKind::FutureTimestamp => "timestamp is too far in the future",
+            ErrorKind::PastTimestamp => "timestamp is too far in the past",
+            ErrorKind::Unknown(ref code) => code,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for ErrorKind {
+    fn from(code: &'a str) -> Self {
+        match code {
+            "invalid_data" => ErrorKind::InvalidData,
+            "missing_attribute" => ErrorKind::MissingAttribute,
+            "invalid_attribute" => ErrorKind::InvalidAttribute,
+            "value_too_long" => ErrorKind::ValueTooLong,
+            "too_many_values" => ErrorKind::TooManyValues,
+            "nesting_too_deep" => ErrorKind::NestingTooDeep,
+            "future_timestamp" => ErrorKind::FutureTimestamp,
+            "past_timestamp" => ErrorKind::PastTimestamp,
+            other => ErrorKind::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// An error in the meta data of a field.
+///
+/// Errors consist of a kind and optional structured data, such as the `expected` type of a value.
+/// Errors without data are serialized as their code, for instance `"invalid_data"`. Errors with
+/// data are serialized as a tuple of code and data, for instance
+/// `["invalid_data", {"expected": "a string"}]`. Legacy error messages are read as unknown kinds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetaError {
+    kind: ErrorKind,
+    data: BTreeMap<String, serde_json::Value>,
+}
+
+impl MetaError {
+    /// Creates a new error without data.
+    pub fn new(kind: ErrorKind) -> Self {
+        MetaError {
+            kind,
+            data: BTreeMap::new(),
+        }
+    }
+
+    /// Creates an invalid data error with a human readable reason.
+    pub fn invalid<S: fmt::Display>(reason: S) -> Self {
+        MetaError::new(ErrorKind::InvalidData).with_data("reason", reason.to_string())
+    }
+
+    /// Creates an invalid data error that describes the expected value.
+    pub fn expected<S: fmt::Display>(expected: S) -> Self {
+        MetaError::new(ErrorKind::InvalidData).with_data("expected", expected.to_string())
+    }
+
+    /// Creates an error for a missing required attribute.
+    pub fn missing_attribute<S: fmt::Display>(attribute: S) -> Self {
+        MetaError::new(ErrorKind::MissingAttribute).with_data("attribute", attribute.to_string())
+    }
+
+    /// Converts the message of a deserialization error into a structured error.
+    pub(crate) fn from_deserialize_error(message: &str) -> Self {
+        if message.starts_with("missing field `") && message.ends_with('`') {
+            let attribute = &message["missing field `".len()..message.len() - 1];
+            return MetaError::missing_attribute(attribute);
+        }
+
+        match message.find(", expected ") {
+            Some(index) => MetaError::expected(&message[index + ", expected ".len()..]),
+            None => MetaError::invalid(message),
+        }
+    }
+
+    /// Adds structured data to this error.
+    pub fn with_data<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<serde_json::Value>,
+    {
+        self.data.insert(key.into(), value.into());
+        self
+    }
+
+    /// The kind of this error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Returns the structured data with the given key.
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.data.get(key)
+    }
+
+    /// Iterates all structured data of this error.
+    pub fn data(&self) -> impl Iterator<Item = (&str, &serde_json::Value)> {
+        self.data.iter().map(|(key, value)| (key.as_str(), value))
+    }
+}
+
+impl From<ErrorKind> for MetaError {
+    fn from(kind: ErrorKind) -> Self {
+        MetaError::new(kind)
+    }
+}
+
+impl<'a> From<&'a str> for MetaError {
+    fn from(code: &'a str) -> Self {
+        MetaError::new(code.into())
+    }
+}
+
+impl From<String> for MetaError {
+    fn from(code: String) -> Self {
+        MetaError::new(code.as_str().into())
+    }
+}
+
+impl fmt::Display for MetaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let string = |key| self.get(key).and_then(serde_json::Value::as_str);
+
+        if let Some(reason) = string("reason") {
+            write!(f, "{}", reason)
+        } else if let Some(expected) = string("expected") {
+            write!(f, "{}, expected {}", self.kind.description(), expected)
+        } else if let Some(attribute) = string("attribute") {
+            write!(f, "{} `{}`", self.kind.description(), attribute)
+        } else {
+            write!(f, "{}", self.kind.description())
+        }
+    }
+}
+
+struct MetaErrorVisitor;
+
+impl<'de> de::Visitor<'de> for MetaErrorVisitor {
+    type Value = MetaError;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a meta error")
+    }
+
+    fn visit_str<E: de::Error>(self, code: &str) -> Result<Self::Value, E> {
+        Ok(code.into())
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let code: String = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::custom("missing required error code"))?;
+        let data = seq.next_element()?.unwrap_or_default();
+
+        // Drain the sequence
+        while let Some(IgnoredAny) = seq.next_element()? {}
+
+        Ok(MetaError {
+            kind: code.as_str().into(),
+            data,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for MetaError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MetaErrorVisitor)
+    }
+}
+
+impl Serialize for MetaError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.data.is_empty() {
+            return serializer.serialize_str(self.kind.code());
+        }
+
+        let mut seq = serializer.serialize_seq(Some(2))?;
+        seq.serialize_element(self.kind.code())?;
+        seq.serialize_element(&self.data)?;
+        seq.end()
+    }
+}
+
 /// Meta information for a data field in the event payload.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Meta {
@@ -168,7 +415,7 @@ pub struct Meta {
         skip_serializing_if = "Vec::is_empty",
         rename = "err"
     )]
-    pub errors: Vec<String>,
+    pub errors: Vec<MetaError>,
 
     /// The original length of modified text fields or collections.
     #[serde(
@@ -178,6 +425,14 @@ pub struct Meta {
     )]
     pub original_length: Option<u32>,
 
+    /// The original value of modified or invalid fields.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "val"
+    )]
+    pub original_value: Option<serde_json::Value>,
+
     /// Path at which the annotated value was deserialized.
     #[serde(skip)]
     pub path: Option<String>,
@@ -188,16 +443,18 @@ impl PartialEq for Meta {
         self.remarks == other.remarks
             && self.errors == other.errors
             && self.original_length == other.original_length
+            && self.original_value == other.original_value
     }
 }
 
 impl Meta {
-    /// Creates a new meta data object from an error message.
-    pub fn from_error<S: Into<String>>(message: S) -> Self {
+    /// Creates a new meta data object from an error.
+    pub fn from_error<E: Into<MetaError>>(error: E) -> Self {
         Meta {
             remarks: Vec::new(),
-            errors: vec![message.into()],
+            errors: vec![error.into()],
             original_length: None,
+            original_value: None,
             path: None,
         }
     }
@@ -212,6 +469,16 @@ impl Meta {
         self.original_length = original_length;
     }
 
+    /// The original value of this field, if it was modified.
+    pub fn original_value(&self) -> Option<&serde_json::Value> {
+        self.original_value.as_ref()
+    }
+
+    /// Updates the original value of this annotation.
+    pub fn set_original_value(&mut self, original_value: Option<serde_json::Value>) {
+        self.original_value = original_value;
+    }
+
     /// Iterates all remarks on this field.
     pub fn remarks(&self) -> impl Iterator<Item = &Remark> {
         self.remarks.iter()
@@ -228,12 +495,12 @@ impl Meta {
     }
 
     /// Iterates errors on this field.
-    pub fn errors(&self) -> impl Iterator<Item = &str> {
-        self.errors.iter().map(|x| x.as_str())
+    pub fn errors(&self) -> impl Iterator<Item = &MetaError> {
+        self.errors.iter()
     }
 
     /// Mutable reference to errors of this field.
-    pub fn errors_mut(&mut self) -> &mut Vec<String> {
+    pub fn errors_mut(&mut self) -> &mut Vec<MetaError> {
         &mut self.errors
     }
 
@@ -249,7 +516,10 @@ impl Meta {
 
     /// Indicates whether this field has meta data attached.
     pub fn is_empty(&self) -> bool {
-        self.original_length.is_none() && self.remarks.is_empty() && self.errors.is_empty()
+        self.original_length.is_none()
+            && self.original_value.is_none()
+            && self.remarks.is_empty()
+            && self.errors.is_empty()
     }
 
     /// The path at which the annotated value was deserialized.
@@ -269,6 +539,7 @@ impl Default for Meta {
             remarks: Vec::new(),
             errors: Vec::new(),
             original_length: None,
+            original_value: None,
             path: None,
         }
     }
@@ -282,6 +553,17 @@ impl<'de, T: Deserialize<'de>> Annotated<T> {
     /// Deserializes an annotated from a deserializer
     pub fn deserialize_with_meta<D: Deserializer<'de>>(
         deserializer: D,
+    ) -> Result<Annotated<T>, D::Error> {
+        Self::deserialize_with_limits(deserializer, DeserializeLimits::default())
+    }
+
+    /// Deserializes an annotated from a deserializer, enforcing limits on the input.
+    ///
+    /// Values exceeding the limits are truncated or dropped and receive an error in their meta
+    /// data. See `DeserializeLimits` for more information.
+    pub fn deserialize_with_limits<D: Deserializer<'de>>(
+        deserializer: D,
+        limits: DeserializeLimits,
     ) -> Result<Annotated<T>, D::Error> {
         #[derive(Debug, Deserialize)]
         struct MetaDeserializeHelper {
@@ -289,9 +571,26 @@ impl<'de, T: Deserialize<'de>> Annotated<T> {
             meta: Option<MetaMap>,
         }
 
-        let content = Content::deserialize(deserializer)?;
-        let helper = MetaDeserializeHelper::deserialize(ContentRefDeserializer::new(&content))?;
-        let meta_map = helper.meta.unwrap_or_default();
+        let _scope = ContextScope::enter();
+
+        // Limits are enforced while buffering, so that the buffer never holds excessive data.
+        let limiter = Rc::new(Limiter::new(limits));
+        let context = TrackedContext {
+            limiter: Some(limiter.clone()),
+            ..Default::default()
+        };
+
+        let content = Content::deserialize(TrackedDeserializer::new(deserializer, context))?;
+        // Only objects can carry meta data. Other values would be read as a sequence of fields.
+        let meta_map = match content {
+            Content::Map(_) => {
+                MetaDeserializeHelper::deserialize(ContentRefDeserializer::new(&content))?
+                    .meta
+                    .unwrap_or_default()
+            }
+            _ => MetaMap::default(),
+        };
+        meta_map.add_violations(limiter.take_violations());
         deserialize_meta(ContentDeserializer::new(content), meta_map)
     }
 
@@ -300,10 +599,23 @@ impl<'de, T: Deserialize<'de>> Annotated<T> {
         Self::deserialize_with_meta(&mut serde_json::Deserializer::from_str(s))
     }
 
+    /// Deserializes an annotated from a JSON string, enforcing limits on the input.
+    pub fn from_json_with_limits(
+        s: &'de str,
+        limits: DeserializeLimits,
+    ) -> Result<Annotated<T>, Error> {
+        Self::deserialize_with_limits(&mut serde_json::Deserializer::from_str(s), limits)
+    }
+
     /// Deserializes an annotated from JSON bytes.
     pub fn from_json_bytes(b: &'de [u8]) -> Result<Annotated<T>, Error> {
         Self::deserialize_with_meta(&mut serde_json::Deserializer::from_slice(b))
     }
+
+    /// Deserializes an annotated from CBOR bytes.
+    pub fn from_cbor(b: &'de [u8]) -> Result<Annotated<T>, serde_cbor::Error> {
+        Self::deserialize_with_meta(&mut serde_cbor::Deserializer::from_slice(b))
+    }
 }
 
 impl<T: Serialize> Annotated<T> {
@@ -338,6 +650,34 @@ impl<T: Serialize> Annotated<T> {
         self.serialize_with_meta(&mut ser)?;
         Ok(unsafe { String::from_utf8_unchecked(ser.into_inner()) })
     }
+
+    /// Serializes an annotated value into CBOR bytes.
+    ///
+    /// Like in JSON, meta data is emitted into a `_meta` key, so that `from_cbor` restores the
+    /// value along with all of its meta data.
+    pub fn to_cbor(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        let mut ser = serde_cbor::Serializer::new(Vec::with_capacity(128));
+        self.serialize_with_meta(&mut ser)?;
+        Ok(ser.into_inner())
+    }
+
+    /// Estimates the size of this value in bytes when serialized to compact JSON.
+    ///
+    /// Meta data is accounted for as if it was serialized into a `_meta` key next to the value.
+    /// The JSON output is only measured and never buffered.
+    pub fn estimate_size(&self) -> usize {
+        let mut counter = SizeCounter(0);
+        serde_json::to_writer(&mut counter, self).ok();
+
+        if let Ok(tree) = serialize_meta(self) {
+            if !tree.is_empty() {
+                counter.0 += r#","_meta":"#.len();
+                serde_json::to_writer(&mut counter, &tree).ok();
+            }
+        }
+
+        counter.0
+    }
 }
 
 impl<T> Annotated<T> {
@@ -351,9 +691,9 @@ impl<T> Annotated<T> {
         Annotated(Some(value), meta)
     }
 
-    /// Creates an annotated wrapper for invalid data with an error message.
-    pub fn from_error<S: Into<String>>(message: S) -> Self {
-        Annotated(None, Meta::from_error(message))
+    /// Creates an annotated wrapper for invalid data with an error.
+    pub fn from_error<E: Into<MetaError>>(error: E) -> Self {
+        Annotated(None, Meta::from_error(error))
     }
 
     /// Custom deserialize implementation that merges meta data from the deserializer.
@@ -369,40 +709,78 @@ impl<T> Annotated<T> {
         //
         // THIS IS A BUG AND NEEDS TO BE FIXED WITH CUSTOM DESERIALIZATION!
 
+        // The context must be taken before the deserializer is used. It is only available if the
+        // deserializer is tracked.
+        let context = take_context();
+
         let mut annotated = {
             let mut annotated = Annotated::<T>::empty();
 
-            let path: Option<&Rc<Path>> = deserializer.state().get();
-            let meta_map: Option<&Rc<MetaMap>> = deserializer.state().get();
-            if let (Some(path), Some(meta_map)) = (path, meta_map) {
+            let path = context.as_ref().and_then(|context| context.path.clone());
+            let meta_map = context
+                .as_ref()
+                .and_then(|context| context.meta_map.as_ref());
+            if let (Some(path), Some(meta_map)) = (path.as_ref(), meta_map) {
                 if let Some(meta) = meta_map.remove(&path.to_string()) {
                     *annotated.meta_mut() = meta;
                 }
             }
 
-            annotated.meta_mut().set_path(path.cloned());
+            annotated.meta_mut().set_path(path);
             annotated
         };
 
-        // Deserialize into a buffer first to catch syntax errors and fail fast. We use Serde's
-        // private Content type instead of serde-value so we retain deserializer state.
+        // Deserialize into a buffer first to catch syntax errors and fail fast. We use our own
+        // Content type instead of serde-value so we retain borrowed data.
         let content = Content::deserialize(deserializer)?;
 
         // Do not add an error to "meta" if the content is empty and there is already an error
         // or remakr. This would indicate that this field was previously validated and the value
         // removed by an error or processing. Otherwise, we would potentially generate error
-        // duplicates. We use the internal ContentRepr here to avoid deserializing the content
-        // multiple times.
-        match content.repr() {
-            ContentRepr::Unit if annotated.meta().null_is_valid() => return Ok(annotated),
+        // duplicates. We match the content here to avoid deserializing it multiple times.
+        match content {
+            Content::Unit if annotated.meta().null_is_valid() => return Ok(annotated),
             _ => (),
         };
 
-        // Continue deserialization into the target type. If this returns an error, we leave the
-        // value as None and add the error to the meta data.
-        match C::deserialize(ContentDeserializer::<D::Error>::new(content)) {
+        // Continue deserialization into the target type. If this returns an error, we try to
+        // coerce primitives into the target type and leave a remark. Otherwise, we leave the value
+        // as None, add the error to the meta data and retain the original value. The content is
+        // borrowed so that it is still available in case of an error. Tracking resumes from this
+        // value's context, so that nested values receive their paths and meta data.
+        let deserializer = ContentRefDeserializer::<D::Error>::new(&content);
+        let result = match context {
+            Some(context) => C::deserialize(TrackedDeserializer::new(deserializer, context)),
+            None => C::deserialize(deserializer),
+        };
+
+        match result {
             Ok(value) => annotated.set_value(Some(value)),
-            Err(err) => annotated.meta_mut().errors_mut().push(err.to_string()),
+            Err(err) => {
+                let value =
+                    serde_json::Value::deserialize(ContentDeserializer::<Error>::new(content)).ok();
+
+                // Coercions that change the text of a value, such as trimming whitespace, retain
+                // the original value so that no data is lost.
+                let coerced = value.as_ref().and_then(coerce::<T, C>);
+                if let Some((coerced, lossless)) = coerced {
+                    annotated.set_value(Some(coerced));
+                    let meta = annotated.meta_mut();
+                    meta.remarks_mut()
+                        .push(Remark::new(RemarkType::Annotated, COERCION_RULE_ID));
+                    if !lossless && meta.original_value().is_none() {
+                        meta.set_original_value(value.and_then(original_value));
+                    }
+                    return Ok(annotated);
+                }
+
+                let meta = annotated.meta_mut();
+                meta.errors_mut()
+                    .push(MetaError::from_deserialize_error(&err.to_string()));
+                if meta.original_value().is_none() {
+                    meta.set_original_value(value.and_then(original_value));
+                }
+            }
         }
 
         Ok(annotated)
@@ -454,6 +832,12 @@ impl<T> Annotated<T> {
         Annotated(self.0.map(f), self.1)
     }
 
+    /// Retains the original value of invalid or modified data in the meta data.
+    pub fn with_original_value(mut self, original_value: serde_json::Value) -> Self {
+        self.1.set_original_value(Some(original_value));
+        self
+    }
+
     /// Removes a value and writes a well known remark.
     pub fn with_removed_value(mut self, remark: Remark) -> Self {
         if self.0.is_some() {
@@ -490,7 +874,7 @@ impl<T: Serialize> Serialize for Annotated<T> {
 
 /// A map of meta data entries for paths in a model.
 #[derive(Clone, Debug, Default, PartialEq)]
-struct MetaMap {
+pub(crate) struct MetaMap {
     inner: RefCell<BTreeMap<String, Meta>>,
 }
 
@@ -517,6 +901,18 @@ impl MetaMap {
     {
         self.inner.borrow_mut().remove(path)
     }
+
+    /// Adds errors for limits that were exceeded while deserializing.
+    fn add_violations(&self, violations: Vec<LimitViolation>) {
+        let mut inner = self.inner.borrow_mut();
+        for violation in violations {
+            let meta = inner.entry(violation.path).or_default();
+            meta.errors_mut().push(violation.kind.into());
+            if meta.original_length.is_none() {
+                meta.original_length = violation.original_length.map(|len| len as u32);
+            }
+        }
+    }
 }
 
 impl FromIterator<(String, Meta)> for MetaMap {
@@ -563,7 +959,9 @@ impl<'de> de::Visitor<'de> for MetaMapVisitor {
 
 impl<'de> Deserialize<'de> for MetaMapHelper {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        let path = deserializer.state().get().map(|p: &Rc<Path>| p.to_string());
+        let path = take_context()
+            .and_then(|context| context.path)
+            .map(|path| path.to_string());
         deserializer.deserialize_map(MetaMapVisitor(path))
     }
 }
@@ -576,26 +974,53 @@ impl<'de> Deserialize<'de> for MetaMap {
     }
 }
 
+/// Filters the JSON value of invalid data to retain in meta data.
+///
+/// Returns `None` for `null` and for values exceeding `MAX_ORIGINAL_VALUE_SIZE` when serialized.
+fn original_value(value: serde_json::Value) -> Option<serde_json::Value> {
+    if value.is_null() || serde_json::to_vec(&value).ok()?.len() > MAX_ORIGINAL_VALUE_SIZE {
+        return None;
+    }
+
+    Some(value)
+}
+
 /// Deserializes an annotated value with given meta data.
 fn deserialize_meta<'de, D, T>(deserializer: D, meta_map: MetaMap) -> Result<Annotated<T>, D::Error>
 where
     D: Deserializer<'de>,
     T: Deserialize<'de>,
 {
-    let mut state = de::State::default();
-    state.set(Rc::new(meta_map));
+    let context = TrackedContext {
+        meta_map: Some(Rc::new(meta_map)),
+        ..Default::default()
+    };
 
-    let tracked = TrackedDeserializer::new(deserializer, state);
+    let tracked = TrackedDeserializer::new(deserializer, context);
     Annotated::<T>::deserialize(tracked)
 }
 
+/// A writer that discards all data and only counts the number of bytes.
+struct SizeCounter(usize);
+
+impl io::Write for SizeCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 /// Indicates whether Annotated's meta data or values should be serialized.
 pub(crate) fn should_serialize_meta() -> bool {
     SERIALIZE_META.with(|b| b.load(Ordering::Relaxed))
 }
 
 /// Serializes meta data of an annotated value into a nested map structure.
-fn serialize_meta<T>(value: &Annotated<T>) -> Result<MetaTree, MetaError>
+pub(crate) fn serialize_meta<T>(value: &Annotated<T>) -> Result<MetaTree, MetaSerializeError>
 where
     Annotated<T>: Serialize,
 {
@@ -683,7 +1108,7 @@ mod test_annotated_with_meta {
         let deserializer = &mut Deserializer::from_str("null");
 
         // It should reject the "null" value and add an error
-        let value = Annotated::<i32>::from_error("invalid type: null, expected i32");
+        let value = Annotated::<i32>::from_error(MetaError::expected("i32"));
         assert_eq_dbg!(
             value,
             deserialize_meta(deserializer, MetaMap::new()).unwrap()
@@ -696,7 +1121,7 @@ mod test_annotated_with_meta {
 
         // It should reject the "null" value and add an error
         let value = Annotated::from(Test {
-            answer: Annotated::from_error("invalid type: null, expected i32"),
+            answer: Annotated::from_error(MetaError::expected("i32")),
             other: 21,
         });
         assert_eq_dbg!(
@@ -739,7 +1164,8 @@ mod test_annotated_without_meta {
 
     #[test]
     fn test_invalid() {
-        let value = Annotated::<i32>::from_error("invalid type: map, expected i32");
+        let value = Annotated::<i32>::from_error(MetaError::expected("i32"))
+            .with_original_value(serde_json::from_str(r#"{}"#).unwrap());
         assert_eq_dbg!(value, serde_json::from_str(r#"{}"#).unwrap());
         assert_eq_str!("null", &serde_json::to_string(&value).unwrap());
     }
@@ -747,7 +1173,8 @@ mod test_annotated_without_meta {
     #[test]
     fn test_invalid_nested() {
         let value = Annotated::from(Test {
-            answer: Annotated::from_error("invalid type: string \"invalid\", expected i32"),
+            answer: Annotated::from_error(MetaError::expected("i32"))
+                .with_original_value("invalid".into()),
             other: 21,
         });
 
@@ -761,6 +1188,35 @@ mod test_annotated_without_meta {
         )
     }
 
+    #[test]
+    fn test_coerced() {
+        let mut meta = Meta::default();
+        meta.remarks_mut()
+            .push(Remark::new(RemarkType::Annotated, COERCION_RULE_ID));
+        let value = Annotated::new(42, meta);
+
+        assert_eq_dbg!(value, serde_json::from_str(r#""42""#).unwrap());
+        assert_eq_str!("42", &serde_json::to_string(&value).unwrap());
+    }
+
+    #[test]
+    fn test_coerced_lossy() {
+        let mut meta = Meta::default();
+        meta.remarks_mut()
+            .push(Remark::new(RemarkType::Annotated, COERCION_RULE_ID));
+        meta.set_original_value(Some(" 42 ".into()));
+        let value = Annotated::new(42, meta);
+
+        assert_eq_dbg!(value, serde_json::from_str(r#"" 42 ""#).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_too_large() {
+        let json = format!(r#""{}""#, "x".repeat(MAX_ORIGINAL_VALUE_SIZE));
+        let value = Annotated::<i32>::from_error(MetaError::expected("i32"));
+        assert_eq_dbg!(value, serde_json::from_str(&json).unwrap());
+    }
+
     #[test]
     fn test_syntax_error() {
         assert!(serde_json::from_str::<i32>("nul").is_err());
@@ -896,6 +1352,140 @@ mod test_remarks {
     }
 }
 
+#[cfg(test)]
+mod test_errors {
+    use super::*;
+
+    #[test]
+    fn test_code_only() {
+        let json = r#""future_timestamp""#;
+        let error = MetaError::new(ErrorKind::FutureTimestamp);
+
+        assert_eq_dbg!(error, serde_json::from_str(json).unwrap());
+        assert_eq_str!(json, &serde_json::to_string(&error).unwrap());
+    }
+
+    #[test]
+    fn test_with_data() {
+        let json = r#"["invalid_data",{"expected":"a string"}]"#;
+        let error = MetaError::expected("a string");
+
+        assert_eq_dbg!(error, serde_json::from_str(json).unwrap());
+        assert_eq_str!(json, &serde_json::to_string(&error).unwrap());
+        assert_eq_str!(error.to_string(), "invalid data, expected a string");
+    }
+
+    #[test]
+    fn test_legacy_message() {
+        let json = r#""some legacy error""#;
+        let error: MetaError = serde_json::from_str(json).unwrap();
+
+        assert_eq_dbg!(
+            error.kind(),
+            &ErrorKind::Unknown("some legacy error".to_string())
+        );
+        assert_eq_str!(json, &serde_json::to_string(&error).unwrap());
+        assert_eq_str!(error.to_string(), "some legacy error");
+    }
+
+    #[test]
+    fn test_deserialize_error() {
+        assert_eq_dbg!(
+            MetaError::from_deserialize_error("missing field `message`"),
+            MetaError::missing_attribute("message")
+        );
+        assert_eq_dbg!(
+            MetaError::from_deserialize_error("invalid type: null, expected i32"),
+            MetaError::expected("i32")
+        );
+        assert_eq_dbg!(
+            MetaError::from_deserialize_error("invalid level"),
+            MetaError::invalid("invalid level")
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_limits {
+    use super::*;
+    use protocol::{Map, Value};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Test {
+        message: Annotated<String>,
+    }
+
+    #[test]
+    fn test_truncated_string() {
+        let limits = DeserializeLimits {
+            max_string_bytes: 3,
+            ..Default::default()
+        };
+
+        let json = r#"{"message": "abcdef"}"#;
+        let value = Annotated::<Test>::from_json_with_limits(json, limits).unwrap();
+
+        let mut meta = Meta::from_error(ErrorKind::ValueTooLong);
+        meta.set_original_length(Some(6));
+        let message = Annotated::new("abc".to_string(), meta);
+        assert_eq_dbg!(value, Annotated::from(Test { message }));
+    }
+
+    #[test]
+    fn test_dropped_key() {
+        let limits = DeserializeLimits {
+            max_string_bytes: 3,
+            ..Default::default()
+        };
+
+        let json = r#"{"a": 1, "abcdef": 2}"#;
+        let value = Annotated::<Value>::from_json_with_limits(json, limits).unwrap();
+
+        let mut map = Map::new();
+        map.insert("a".to_string(), Annotated::from(Value::U64(1)));
+
+        let meta = Meta::from_error(ErrorKind::InvalidAttribute);
+        assert_eq_dbg!(value, Annotated::new(Value::Map(map), meta));
+    }
+
+    #[test]
+    fn test_truncated_root_array() {
+        let limits = DeserializeLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+
+        let value = Annotated::<Value>::from_json_with_limits("[1, 2, 3]", limits).unwrap();
+
+        let array = vec![
+            Annotated::from(Value::U64(1)),
+            Annotated::from(Value::U64(2)),
+        ];
+        let mut meta = Meta::from_error(ErrorKind::TooManyValues);
+        meta.set_original_length(Some(3));
+        assert_eq_dbg!(value, Annotated::new(Value::Array(array), meta));
+    }
+
+    #[test]
+    fn test_truncated_map() {
+        let limits = DeserializeLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+
+        let json = r#"{"a": 1, "b": 2, "c": 3}"#;
+        let value = Annotated::<Value>::from_json_with_limits(json, limits).unwrap();
+
+        let mut map = Map::new();
+        map.insert("a".to_string(), Annotated::from(Value::U64(1)));
+        map.insert("b".to_string(), Annotated::from(Value::U64(2)));
+
+        let mut meta = Meta::from_error(ErrorKind::TooManyValues);
+        meta.set_original_length(Some(3));
+        assert_eq_dbg!(value, Annotated::new(Value::Map(map), meta));
+    }
+}
+
 #[cfg(test)]
 mod test_serialize_meta {
     use super::*;
@@ -936,6 +1526,23 @@ mod test_serialize_meta {
         assert_eq_str!(serialize(&value).unwrap(), r#"{"":{"err":["some error"]}}"#);
     }
 
+    #[test]
+    fn test_estimate_size() {
+        let value = Annotated::from(Test {
+            answer: Annotated::new(42, Meta::from_error("some error")),
+        });
+        assert_eq!(value.estimate_size(), value.to_json().unwrap().len());
+    }
+
+    #[test]
+    fn test_original_value() {
+        let value: Annotated<Test> = serde_json::from_str(r#"{"answer":"invalid"}"#).unwrap();
+        assert_eq_str!(
+            serialize(&value).unwrap(),
+            r#"{"answer":{"":{"err":[["invalid_data",{"expected":"i32"}]],"val":"invalid"}}}"#
+        );
+    }
+
     #[test]
     fn test_nested() {
         let value = Annotated::new(
@@ -961,4 +1568,21 @@ mod test_serialize_meta {
             r#"{"0":{"":{"err":["a"]}},"1":{"":{"err":["b"]}}}"#
         );
     }
+
+    #[test]
+    fn test_cbor_roundtrip() {
+        let mut meta = Meta::from_error(ErrorKind::ValueTooLong);
+        meta.remarks_mut()
+            .push(Remark::with_range(RemarkType::Masked, "@test", (0, 5)));
+        meta.set_original_length(Some(10));
+
+        let value = Annotated::from(Test {
+            answer: Annotated::new(42, meta),
+        });
+
+        let cbor = value.to_cbor().unwrap();
+        let roundtripped = Annotated::<Test>::from_cbor(&cbor).unwrap();
+        assert_eq_dbg!(value, roundtripped);
+        assert_eq_str!(value.to_json().unwrap(), roundtripped.to_json().unwrap());
+    }
 }
@@ -652,8 +652,20 @@ impl SerializeMap for SerializeMapMeta {
             .take()
             .ok_or_else(|| Error::custom("serialize_value called without serialize_key"))?;
 
-        if let Some(value) = value.serialize(MetaSerializer)? {
-            self.map.insert(key, value);
+        // Flattened annotated fields are serialized as entries of the enclosing map. Their values
+        // are merged into this map, and their meta data is shadowed by the map's own meta data.
+        match key.as_str() {
+            ANNOTATED_META => (),
+            ANNOTATED_VALUE => {
+                if let Some(tree) = value.serialize(MetaSerializer)? {
+                    self.map.extend(tree.children);
+                }
+            }
+            _ => {
+                if let Some(value) = value.serialize(MetaSerializer)? {
+                    self.map.insert(key, value);
+                }
+            }
         }
 
         Ok(())
@@ -3,8 +3,12 @@
 #[macro_use]
 mod macros;
 
+mod auth;
 mod buffer;
+mod coerce;
 mod common;
+mod diff;
+mod dsn;
 mod meta;
 mod meta_ser;
 mod serde;
@@ -13,6 +17,12 @@ mod tracked;
 mod types;
 mod utils;
 
+pub use self::auth::*;
 pub use self::common::*;
+pub use self::diff::{Change, ChangeKind, Diff};
+pub use self::dsn::*;
 pub use self::meta::*;
+pub(crate) use self::serde_chrono::datetime_to_value;
+pub use self::tracked::DeserializeLimits;
 pub use self::types::*;
+pub(crate) use self::utils::get_str;
@@ -4,14 +4,31 @@ use std::fmt;
 
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use serde::{de, ser};
+use serde_json::{self, Value};
 
 use super::meta::Annotated;
 use super::serde::{CustomDeserialize, CustomSerialize, ForwardSerialize};
 
-pub fn timestamp_to_datetime(ts: f64) -> DateTime<Utc> {
-    let secs = ts as i64;
-    let micros = (ts.fract() * 1_000_000f64) as u32;
-    Utc.timestamp_opt(secs, micros * 1000).unwrap()
+/// Converts a unix timestamp with fractional seconds into a `DateTime`.
+///
+/// Returns `None` if the timestamp is not finite or outside of the supported range of dates.
+pub fn timestamp_to_datetime(ts: f64) -> Option<DateTime<Utc>> {
+    if !ts.is_finite() {
+        return None;
+    }
+
+    // Round to whole microseconds, since the fraction of a float timestamp is rarely exact.
+    // Truncating would shift the timestamp every time it is serialized and parsed again.
+    let micros = (ts * 1_000_000f64).round() as i64;
+    let mut secs = micros / 1_000_000;
+    let mut subsec_micros = micros % 1_000_000;
+    if subsec_micros < 0 {
+        secs -= 1;
+        subsec_micros += 1_000_000;
+    }
+
+    Utc.timestamp_opt(secs, subsec_micros as u32 * 1000)
+        .single()
 }
 
 struct SecondsTimestampVisitor;
@@ -24,15 +41,21 @@ impl<'de> de::Visitor<'de> for SecondsTimestampVisitor {
     }
 
     fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
-        Ok(timestamp_to_datetime(value))
+        timestamp_to_datetime(value)
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Float(value), &self))
     }
 
     fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
-        Ok(Utc.timestamp_opt(value, 0).unwrap())
+        Utc.timestamp_opt(value, 0)
+            .single()
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Signed(value), &self))
     }
 
     fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
-        Ok(Utc.timestamp_opt(value as i64, 0).unwrap())
+        Some(value)
+            .filter(|value| *value <= i64::max_value() as u64)
+            .and_then(|value| Utc.timestamp_opt(value as i64, 0).single())
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(value), &self))
     }
 
     fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
@@ -92,6 +115,11 @@ impl CustomSerialize<DateTime<Utc>> for SerdeDateTime {
     }
 }
 
+/// Converts a timestamp into the JSON value it is serialized as.
+pub fn datetime_to_value(datetime: &DateTime<Utc>) -> Value {
+    serde_json::to_value(ForwardSerialize(datetime, SerdeDateTime)).unwrap_or(Value::Null)
+}
+
 impl CustomSerialize<Option<DateTime<Utc>>> for SerdeDateTime {
     fn serialize<S>(datetime: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -123,6 +151,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use protocol::MetaError;
     use serde_json::Deserializer;
 
     #[test]
@@ -134,6 +163,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fractional_timestamp() {
+        let deserializer = &mut Deserializer::from_str("1532692006.715");
+        assert_eq_dbg!(
+            deserialize(deserializer).unwrap(),
+            Annotated::from(Utc.ymd(2018, 7, 27).and_hms_milli(11, 46, 46, 715))
+        );
+
+        let deserializer = &mut Deserializer::from_str("-0.5");
+        assert_eq_dbg!(
+            deserialize(deserializer).unwrap(),
+            Annotated::from(Utc.ymd(1969, 12, 31).and_hms_milli(23, 59, 59, 500))
+        );
+    }
+
+    #[test]
+    fn test_timestamp_out_of_range() {
+        let deserializer = &mut Deserializer::from_str("1e20");
+        assert_eq_dbg!(
+            deserialize::<DateTime<Utc>, _>(deserializer).unwrap(),
+            Annotated::from_error(MetaError::expected("a unix timestamp"))
+                .with_original_value(1e20.into())
+        );
+
+        let deserializer = &mut Deserializer::from_str("18446744073709551615");
+        assert!(deserialize::<DateTime<Utc>, _>(deserializer)
+            .unwrap()
+            .value()
+            .is_none());
+
+        let deserializer = &mut Deserializer::from_str("-9223372036854775808");
+        assert!(deserialize::<DateTime<Utc>, _>(deserializer)
+            .unwrap()
+            .value()
+            .is_none());
+    }
+
     #[test]
     fn test_date() {
         let deserializer = &mut Deserializer::from_str("\"2000-01-01T00:00:00Z\"");
@@ -166,7 +232,8 @@ mod tests {
         let deserializer = &mut Deserializer::from_str("\"invalid\"");
         assert_eq_dbg!(
             deserialize::<DateTime<Utc>, _>(deserializer).unwrap(),
-            Annotated::from_error("input contains invalid characters")
+            Annotated::from_error(MetaError::invalid("input contains invalid characters"))
+                .with_original_value("invalid".into())
         );
     }
 
@@ -175,7 +242,8 @@ mod tests {
         let deserializer = &mut Deserializer::from_str("true");
         assert_eq_dbg!(
             deserialize::<DateTime<Utc>, _>(deserializer).unwrap(),
-            Annotated::from_error("invalid type: boolean `true`, expected a unix timestamp")
+            Annotated::from_error(MetaError::expected("a unix timestamp"))
+                .with_original_value(true.into())
         );
     }
 }
@@ -1,17 +1,148 @@
-//! Defines `TrackedSerializer`, a Serializer implementation that tracks paths.
-
-use serde::de::{self, DeserializeSeed, Deserializer, Error, State, Visitor};
+//! Defines `TrackedDeserializer`, a Deserializer implementation that tracks paths and enforces
+//! deserialization limits.
+//!
+//! Serde does not pass any context into `Deserialize` implementations. Instead, every tracked
+//! deserializer publishes its context in a thread local when it is created and withdraws it as
+//! soon as it is used. `Deserialize` implementations that need their path or meta data call
+//! `take_context` before using the deserializer.
+//!
+//! Serde deserializes flattened fields from buffered entries without any tracked deserializer.
+//! To still track them, every map publishes its context while it is visited, and the first value
+//! deserialized without a context of its own receives the context of the enclosing map.
+
+use serde::de::value::StrDeserializer;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, Error, IgnoredAny, IntoDeserializer, Visitor,
+};
+use std::cell::{Cell, RefCell};
 use std::fmt;
 use std::rc::Rc;
 
-fn state_with_parent_path<F: FnOnce(Rc<Path>) -> Rc<Path>>(state: &State, f: F) -> State {
-    let mut rv = state.clone();
-    let parent = state
-        .get::<Rc<Path>>()
-        .cloned()
-        .unwrap_or_else(|| Rc::new(Path::Root));
-    rv.set(f(parent));
-    rv
+use super::meta::{ErrorKind, MetaMap};
+
+/// The context of the tracked deserializer that was created last and has not been used yet.
+thread_local!(static CONTEXT: RefCell<Option<TrackedContext>> = RefCell::new(None));
+
+/// The context of the map that is currently being visited, for its flattened fields.
+thread_local!(static MAP_CONTEXT: RefCell<Option<TrackedContext>> = RefCell::new(None));
+
+/// Context of a value that is passed down to nested tracked deserializers.
+#[derive(Clone)]
+pub(crate) struct TrackedContext {
+    /// Path to the current value, or `None` if paths are not tracked.
+    pub path: Option<Rc<Path>>,
+    /// Meta data to merge into annotated values while deserializing.
+    pub meta_map: Option<Rc<MetaMap>>,
+    /// Limits enforced on the input.
+    pub limiter: Option<Rc<Limiter>>,
+}
+
+impl TrackedContext {
+    /// Creates the context of a child value, whose path is derived from this context's path.
+    fn child<F: FnOnce(Rc<Path>) -> Rc<Path>>(&self, f: F) -> TrackedContext {
+        TrackedContext {
+            path: self.path.clone().map(f),
+            meta_map: self.meta_map.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+
+    /// Creates a context that tracks neither paths nor meta data.
+    ///
+    /// This is used to resume deserialization of buffered content if the original deserializer
+    /// was not tracked.
+    pub(crate) fn untracked() -> TrackedContext {
+        TrackedContext {
+            path: None,
+            meta_map: None,
+            limiter: None,
+        }
+    }
+
+    /// Creates the context of a map value with the given key.
+    pub(crate) fn enter_key(&self, key: &str) -> TrackedContext {
+        let key = key.to_string();
+        self.child(|parent| Rc::new(Path::Map { parent, key }))
+    }
+
+    /// Creates the context of a sequence element at the given index.
+    pub(crate) fn enter_index(&self, index: usize) -> TrackedContext {
+        self.child(|parent| Rc::new(Path::Seq { parent, index }))
+    }
+}
+
+impl Default for TrackedContext {
+    fn default() -> Self {
+        TrackedContext {
+            path: Some(Rc::new(Path::Root)),
+            meta_map: None,
+            limiter: None,
+        }
+    }
+}
+
+/// Takes the context of the value that is about to be deserialized.
+///
+/// Returns `None` if the deserializer passed to the calling `Deserialize` implementation is not
+/// tracked.
+pub(crate) fn take_context() -> Option<TrackedContext> {
+    CONTEXT
+        .with(|cell| cell.borrow_mut().take())
+        .or_else(|| MAP_CONTEXT.with(|cell| cell.borrow_mut().take()))
+}
+
+/// Guards a top-level deserialization against contexts that were published but never taken.
+///
+/// The published context is cleared when the scope is entered and again when it is left, even
+/// if deserialization fails. Contexts of tracked deserializers that were dropped without being
+/// used can therefore never leak into the next deserialization.
+pub(crate) struct ContextScope(());
+
+impl ContextScope {
+    /// Enters a top-level deserialization.
+    pub(crate) fn enter() -> Self {
+        withdraw_context();
+        MAP_CONTEXT.with(|cell| *cell.borrow_mut() = None);
+        ContextScope(())
+    }
+}
+
+impl Drop for ContextScope {
+    fn drop(&mut self) {
+        withdraw_context();
+        MAP_CONTEXT.with(|cell| *cell.borrow_mut() = None);
+    }
+}
+
+/// Publishes the context of a map for its flattened fields while the map is visited.
+///
+/// The context of the enclosing map is restored when the scope is left.
+struct MapScope(Option<TrackedContext>);
+
+impl MapScope {
+    fn enter(context: &TrackedContext) -> Self {
+        let context = match context.path {
+            Some(_) => Some(context.clone()),
+            None => None,
+        };
+
+        MapScope(MAP_CONTEXT.with(|cell| cell.replace(context)))
+    }
+}
+
+impl Drop for MapScope {
+    fn drop(&mut self) {
+        let enclosing = self.0.take();
+        MAP_CONTEXT.with(|cell| *cell.borrow_mut() = enclosing);
+    }
+}
+
+fn publish_context(context: &TrackedContext) {
+    CONTEXT.with(|cell| *cell.borrow_mut() = Some(context.clone()));
+}
+
+fn withdraw_context() {
+    CONTEXT.with(|cell| *cell.borrow_mut() = None);
 }
 
 /// Path to the current value in the input, like `dependencies.serde.typo1`.
@@ -37,6 +168,16 @@ impl Path {
             Path::NewtypeVariant { parent } => parent.key(),
         }
     }
+
+    /// Skips wrappers such as `Some` and newtypes, which do not show up in the input.
+    fn unwrap(&self) -> &Path {
+        match self {
+            Path::Some { parent } => parent.unwrap(),
+            Path::NewtypeStruct { parent } => parent.unwrap(),
+            Path::NewtypeVariant { parent } => parent.unwrap(),
+            path => path,
+        }
+    }
 }
 
 impl fmt::Display for Path {
@@ -45,23 +186,23 @@ impl fmt::Display for Path {
 
         impl<'a> fmt::Display for Parent<'a> {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                match **self.0 {
+                match *self.0.unwrap() {
                     Path::Root => Ok(()),
                     ref path => write!(f, "{}.", path),
                 }
             }
         }
 
-        match *self {
+        match *self.unwrap() {
             Path::Root => f.write_str("."),
             Path::Seq { ref parent, index } => write!(f, "{}{}", Parent(parent), index),
             Path::Map {
                 ref parent,
                 ref key,
             } => write!(f, "{}{}", Parent(parent), key),
-            Path::Some { ref parent }
-            | Path::NewtypeStruct { ref parent }
-            | Path::NewtypeVariant { ref parent } => write!(f, "{}?", Parent(parent)),
+            Path::Some { .. } | Path::NewtypeStruct { .. } | Path::NewtypeVariant { .. } => {
+                unreachable!()
+            }
         }
     }
 }
@@ -72,15 +213,136 @@ impl fmt::Debug for Path {
     }
 }
 
+/// Limits on untrusted input enforced during deserialization.
+///
+/// Values exceeding a limit do not fail deserialization. Instead, strings are truncated,
+/// collections are cut off and nested values are dropped, and an error is recorded in the meta
+/// data of the affected value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeserializeLimits {
+    /// The maximum nesting depth of maps and sequences.
+    pub max_depth: usize,
+    /// The maximum length of strings in bytes.
+    ///
+    /// Longer strings are truncated. Map entries with longer keys are dropped entirely.
+    pub max_string_bytes: usize,
+    /// The maximum number of entries in a single map or sequence.
+    pub max_entries: usize,
+    /// The maximum total number of values in maps and sequences.
+    pub max_nodes: usize,
+}
+
+impl Default for DeserializeLimits {
+    fn default() -> Self {
+        DeserializeLimits {
+            max_depth: 64,
+            max_string_bytes: 1024 * 1024,
+            max_entries: 10_000,
+            max_nodes: 200_000,
+        }
+    }
+}
+
+/// A limit that was exceeded at a path in the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitViolation {
+    pub path: String,
+    pub kind: ErrorKind,
+    pub original_length: Option<usize>,
+}
+
+/// Shared state for enforcing `DeserializeLimits` across all nested deserializers.
+///
+/// Put an `Rc<Limiter>` into the context of a `TrackedDeserializer` to enable limits.
+#[derive(Debug)]
+pub struct Limiter {
+    limits: DeserializeLimits,
+    depth: Cell<usize>,
+    nodes: Cell<usize>,
+    violations: RefCell<Vec<LimitViolation>>,
+}
+
+impl Limiter {
+    pub fn new(limits: DeserializeLimits) -> Self {
+        Limiter {
+            limits,
+            depth: Cell::new(0),
+            nodes: Cell::new(0),
+            violations: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Moves all recorded violations to the caller.
+    pub fn take_violations(&self) -> Vec<LimitViolation> {
+        self.violations.replace(Vec::new())
+    }
+
+    fn record(&self, context: &TrackedContext, kind: ErrorKind, original_length: Option<usize>) {
+        self.violations.borrow_mut().push(LimitViolation {
+            path: context
+                .path
+                .as_ref()
+                .map(|path| path.to_string())
+                .unwrap_or_default(),
+            kind,
+            original_length,
+        });
+    }
+
+    /// Truncates a string exceeding the limit at a character boundary.
+    fn truncate(&self, context: &TrackedContext, v: &str) -> Option<String> {
+        if v.len() <= self.limits.max_string_bytes {
+            return None;
+        }
+
+        let mut end = self.limits.max_string_bytes;
+        while !v.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.record(context, ErrorKind::ValueTooLong, Some(v.chars().count()));
+        Some(v[..end].to_string())
+    }
+
+    /// Indicates whether a collection may yield another entry at the given index.
+    fn accepts_entry(&self, index: usize) -> bool {
+        index < self.limits.max_entries && self.nodes.get() < self.limits.max_nodes
+    }
+
+    fn count_node(&self) {
+        self.nodes.set(self.nodes.get() + 1);
+    }
+
+    /// Enters a nested map or sequence, or returns `None` if it is nested too deeply.
+    fn enter(limiter: &Rc<Limiter>) -> Option<DepthGuard> {
+        if limiter.depth.get() >= limiter.limits.max_depth {
+            return None;
+        }
+
+        limiter.depth.set(limiter.depth.get() + 1);
+        Some(DepthGuard(limiter.clone()))
+    }
+}
+
+/// Leaves a nested map or sequence when dropped.
+struct DepthGuard(Rc<Limiter>);
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        self.0.depth.set(self.0.depth.get() - 1);
+    }
+}
+
 pub struct TrackedDeserializer<D> {
     de: D,
-    state: State,
+    context: TrackedContext,
 }
 
 impl<D> TrackedDeserializer<D> {
-    pub fn new(de: D, mut state: State) -> Self {
-        state.set(Rc::new(Path::Root));
-        TrackedDeserializer { de, state }
+    /// Creates a tracked deserializer for the value described by the given context.
+    pub(crate) fn new(de: D, context: TrackedContext) -> Self {
+        publish_context(&context);
+        TrackedDeserializer { de, context }
     }
 }
 
@@ -90,103 +352,118 @@ where
 {
     type Error = D::Error;
 
-    fn state(&self) -> &State {
-        &self.state
-    }
-
     fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_any(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_any(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_bool(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_bool(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_u8(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_u8(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_u16(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_u16(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_u32(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_u32(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_u64(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_u64(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_i8(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_i8(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_i16(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_i16(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_i32(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_i32(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_i64(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_i64(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_f32(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_f32(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_f64(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_f64(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_char(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_char(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_str(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_str(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_string(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_string(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_bytes(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_bytes(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_byte_buf(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_byte_buf(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_option(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_option(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_unit(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_unit(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_unit_struct<V: Visitor<'de>>(
@@ -194,8 +471,9 @@ where
         name: &'static str,
         visitor: V,
     ) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_unit_struct(name, TrackedVisitor::new(visitor, &self.state))
+            .deserialize_unit_struct(name, TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_newtype_struct<V: Visitor<'de>>(
@@ -203,13 +481,15 @@ where
         name: &'static str,
         visitor: V,
     ) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_newtype_struct(name, TrackedVisitor::new(visitor, &self.state))
+            .deserialize_newtype_struct(name, TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_seq(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_seq(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_tuple<V: Visitor<'de>>(
@@ -217,8 +497,9 @@ where
         len: usize,
         visitor: V,
     ) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_tuple(len, TrackedVisitor::new(visitor, &self.state))
+            .deserialize_tuple(len, TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_tuple_struct<V: Visitor<'de>>(
@@ -227,13 +508,15 @@ where
         len: usize,
         visitor: V,
     ) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_tuple_struct(name, len, TrackedVisitor::new(visitor, &self.state))
+            .deserialize_tuple_struct(name, len, TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_map(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_map(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_struct<V: Visitor<'de>>(
@@ -242,8 +525,9 @@ where
         fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_struct(name, fields, TrackedVisitor::new(visitor, &self.state))
+            .deserialize_struct(name, fields, TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_enum<V: Visitor<'de>>(
@@ -252,17 +536,20 @@ where
         variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_enum(name, variants, TrackedVisitor::new(visitor, &self.state))
+            .deserialize_enum(name, variants, TrackedVisitor::new(visitor, &self.context))
     }
 
     fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de.deserialize_ignored_any(visitor)
     }
 
     fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        withdraw_context();
         self.de
-            .deserialize_identifier(TrackedVisitor::new(visitor, &self.state))
+            .deserialize_identifier(TrackedVisitor::new(visitor, &self.context))
     }
 }
 
@@ -270,14 +557,36 @@ where
 /// `VariantAccess`.
 struct TrackedVisitor<X> {
     delegate: X,
-    state: State,
+    context: TrackedContext,
 }
 
 impl<X> TrackedVisitor<X> {
-    fn new(delegate: X, state: &State) -> Self {
+    fn new(delegate: X, context: &TrackedContext) -> Self {
         TrackedVisitor {
             delegate,
-            state: state.clone(),
+            context: context.clone(),
+        }
+    }
+
+    /// Truncates strings exceeding the limits, if any.
+    fn truncate(&self, v: &str) -> Option<String> {
+        let limiter = self.context.limiter.as_ref()?;
+        limiter.truncate(&self.context, v)
+    }
+
+    /// Enters a nested map or sequence, or records an error if it is nested too deeply.
+    fn enter(&self) -> Result<Option<DepthGuard>, ()> {
+        let limiter = match self.context.limiter {
+            Some(ref limiter) => limiter,
+            None => return Ok(None),
+        };
+
+        match Limiter::enter(limiter) {
+            Some(guard) => Ok(Some(guard)),
+            None => {
+                limiter.record(&self.context, ErrorKind::NestingTooDeep, None);
+                Err(())
+            }
         }
     }
 }
@@ -342,15 +651,24 @@ where
     }
 
     fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
-        self.delegate.visit_str(v)
+        match self.truncate(v) {
+            Some(truncated) => self.delegate.visit_string(truncated),
+            None => self.delegate.visit_str(v),
+        }
     }
 
     fn visit_borrowed_str<E: Error>(self, v: &'de str) -> Result<Self::Value, E> {
-        self.delegate.visit_borrowed_str(v)
+        match self.truncate(v) {
+            Some(truncated) => self.delegate.visit_string(truncated),
+            None => self.delegate.visit_borrowed_str(v),
+        }
     }
 
     fn visit_string<E: Error>(self, v: String) -> Result<Self::Value, E> {
-        self.delegate.visit_string(v)
+        match self.truncate(&v) {
+            Some(truncated) => self.delegate.visit_string(truncated),
+            None => self.delegate.visit_string(v),
+        }
     }
 
     fn visit_unit<E: Error>(self) -> Result<Self::Value, E> {
@@ -362,37 +680,52 @@ where
     }
 
     fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
-        self.delegate.visit_some(TrackedDeserializer {
-            de: deserializer,
-            state: state_with_parent_path(&self.state, |parent| Rc::new(Path::Some { parent })),
-        })
+        let context = self.context.child(|parent| Rc::new(Path::Some { parent }));
+        self.delegate
+            .visit_some(TrackedDeserializer::new(deserializer, context))
     }
 
     fn visit_newtype_struct<D: Deserializer<'de>>(
         self,
         deserializer: D,
     ) -> Result<Self::Value, D::Error> {
-        self.delegate.visit_newtype_struct(TrackedDeserializer {
-            de: deserializer,
-            state: state_with_parent_path(&self.state, |parent| {
-                Rc::new(Path::NewtypeStruct { parent })
-            }),
-        })
+        let context = self
+            .context
+            .child(|parent| Rc::new(Path::NewtypeStruct { parent }));
+        self.delegate
+            .visit_newtype_struct(TrackedDeserializer::new(deserializer, context))
     }
 
-    fn visit_seq<V: de::SeqAccess<'de>>(self, visitor: V) -> Result<Self::Value, V::Error> {
+    fn visit_seq<V: de::SeqAccess<'de>>(self, mut visitor: V) -> Result<Self::Value, V::Error> {
+        let _guard = match self.enter() {
+            Ok(guard) => guard,
+            Err(()) => {
+                while visitor.next_element::<IgnoredAny>()?.is_some() {}
+                return self.delegate.visit_unit();
+            }
+        };
+
         self.delegate
-            .visit_seq(SeqAccess::new(visitor, &self.state))
+            .visit_seq(SeqAccess::new(visitor, &self.context))
     }
 
-    fn visit_map<V: de::MapAccess<'de>>(self, visitor: V) -> Result<Self::Value, V::Error> {
+    fn visit_map<V: de::MapAccess<'de>>(self, mut visitor: V) -> Result<Self::Value, V::Error> {
+        let _guard = match self.enter() {
+            Ok(guard) => guard,
+            Err(()) => {
+                while visitor.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                return self.delegate.visit_unit();
+            }
+        };
+
+        let _scope = MapScope::enter(&self.context);
         self.delegate
-            .visit_map(MapAccess::new(visitor, &self.state))
+            .visit_map(MapAccess::new(visitor, &self.context))
     }
 
     fn visit_enum<V: de::EnumAccess<'de>>(self, visitor: V) -> Result<Self::Value, V::Error> {
         self.delegate
-            .visit_enum(TrackedVisitor::new(visitor, &self.state))
+            .visit_enum(TrackedVisitor::new(visitor, &self.context))
     }
 
     fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
@@ -420,10 +753,10 @@ where
     where
         V: DeserializeSeed<'de>,
     {
-        let path = &self.state;
+        let context = &self.context;
         self.delegate
             .variant_seed(seed)
-            .map(move |(v, vis)| (v, TrackedVisitor::new(vis, path)))
+            .map(move |(v, vis)| (v, TrackedVisitor::new(vis, context)))
     }
 }
 
@@ -442,16 +775,16 @@ where
     where
         T: DeserializeSeed<'de>,
     {
-        let state = state_with_parent_path(&self.state, |parent| {
-            Rc::new(Path::NewtypeVariant { parent })
-        });
+        let context = self
+            .context
+            .child(|parent| Rc::new(Path::NewtypeVariant { parent }));
         self.delegate
-            .newtype_variant_seed(TrackedSeed::new(seed, state))
+            .newtype_variant_seed(TrackedSeed::new(seed, context))
     }
 
     fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, X::Error> {
         self.delegate
-            .tuple_variant(len, TrackedVisitor::new(visitor, &self.state))
+            .tuple_variant(len, TrackedVisitor::new(visitor, &self.context))
     }
 
     fn struct_variant<V: Visitor<'de>>(
@@ -460,335 +793,38 @@ where
         visitor: V,
     ) -> Result<V::Value, X::Error> {
         self.delegate
-            .struct_variant(fields, TrackedVisitor::new(visitor, &self.state))
-    }
-}
-
-/// Seed that saves the string into the given optional during `visit_str` and
-/// `visit_string`.
-struct CaptureKey<'a, X> {
-    delegate: X,
-    key: &'a mut Option<String>,
-}
-
-impl<'a, X> CaptureKey<'a, X> {
-    fn new(delegate: X, key: &'a mut Option<String>) -> Self {
-        CaptureKey { delegate, key }
+            .struct_variant(fields, TrackedVisitor::new(visitor, &self.context))
     }
 }
 
-/// Forwarding impl.
-impl<'a, 'de, X> DeserializeSeed<'de> for CaptureKey<'a, X>
-where
-    X: DeserializeSeed<'de>,
-{
-    type Value = X::Value;
-
-    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<X::Value, D::Error> {
-        self.delegate
-            .deserialize(CaptureKey::new(deserializer, self.key))
-    }
+/// Seed that reads a map key into a string.
+///
+/// Keys exceeding the maximum number of bytes are rejected before they are copied and yield
+/// `None` instead.
+struct KeySeed {
+    max_bytes: Option<usize>,
 }
 
-/// Forwarding impl.
-impl<'a, 'de, X> Deserializer<'de> for CaptureKey<'a, X>
-where
-    X: Deserializer<'de>,
-{
-    type Error = X::Error;
-
-    fn state(&self) -> &State {
-        self.delegate.state()
-    }
-
-    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_any(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_bool(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_u8(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_u16(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_u32(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_u64(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_i8(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_i16(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_i32(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_i64(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_f32(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_f64(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_char(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_str(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_string(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_bytes(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_byte_buf(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_option(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_unit(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_unit_struct<V: Visitor<'de>>(
-        self,
-        name: &'static str,
-        visitor: V,
-    ) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_unit_struct(name, CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_newtype_struct<V: Visitor<'de>>(
-        self,
-        name: &'static str,
-        visitor: V,
-    ) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_newtype_struct(name, CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_seq(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_tuple<V: Visitor<'de>>(
-        self,
-        len: usize,
-        visitor: V,
-    ) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_tuple(len, CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_tuple_struct<V: Visitor<'de>>(
-        self,
-        name: &'static str,
-        len: usize,
-        visitor: V,
-    ) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_tuple_struct(name, len, CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_map(CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_struct<V: Visitor<'de>>(
-        self,
-        name: &'static str,
-        fields: &'static [&'static str],
-        visitor: V,
-    ) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_struct(name, fields, CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_enum<V: Visitor<'de>>(
-        self,
-        name: &'static str,
-        variants: &'static [&'static str],
-        visitor: V,
-    ) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_enum(name, variants, CaptureKey::new(visitor, self.key))
-    }
-
-    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_ignored_any(CaptureKey::new(visitor, self.key))
-    }
+impl<'de> DeserializeSeed<'de> for KeySeed {
+    type Value = Option<String>;
 
-    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, X::Error> {
-        self.delegate
-            .deserialize_identifier(CaptureKey::new(visitor, self.key))
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
     }
 }
 
-/// Forwarding impl except `visit_str` and `visit_string` which save the string.
-impl<'a, 'de, X> Visitor<'de> for CaptureKey<'a, X>
-where
-    X: Visitor<'de>,
-{
-    type Value = X::Value;
+impl<'de> Visitor<'de> for KeySeed {
+    type Value = Option<String>;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        self.delegate.expecting(formatter)
-    }
-
-    fn visit_bool<E: Error>(self, v: bool) -> Result<Self::Value, E> {
-        self.delegate.visit_bool(v)
-    }
-
-    fn visit_i8<E: Error>(self, v: i8) -> Result<Self::Value, E> {
-        self.delegate.visit_i8(v)
-    }
-
-    fn visit_i16<E: Error>(self, v: i16) -> Result<Self::Value, E> {
-        self.delegate.visit_i16(v)
-    }
-
-    fn visit_i32<E: Error>(self, v: i32) -> Result<Self::Value, E> {
-        self.delegate.visit_i32(v)
-    }
-
-    fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
-        self.delegate.visit_i64(v)
-    }
-
-    fn visit_u8<E: Error>(self, v: u8) -> Result<Self::Value, E> {
-        self.delegate.visit_u8(v)
-    }
-
-    fn visit_u16<E: Error>(self, v: u16) -> Result<Self::Value, E> {
-        self.delegate.visit_u16(v)
-    }
-
-    fn visit_u32<E: Error>(self, v: u32) -> Result<Self::Value, E> {
-        self.delegate.visit_u32(v)
-    }
-
-    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
-        self.delegate.visit_u64(v)
-    }
-
-    fn visit_f32<E: Error>(self, v: f32) -> Result<Self::Value, E> {
-        self.delegate.visit_f32(v)
-    }
-
-    fn visit_f64<E: Error>(self, v: f64) -> Result<Self::Value, E> {
-        self.delegate.visit_f64(v)
-    }
-
-    fn visit_char<E: Error>(self, v: char) -> Result<Self::Value, E> {
-        self.delegate.visit_char(v)
+        formatter.write_str("a string key")
     }
 
     fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
-        *self.key = Some(v.to_owned());
-        self.delegate.visit_str(v)
-    }
-
-    fn visit_borrowed_str<E: Error>(self, v: &'de str) -> Result<Self::Value, E> {
-        *self.key = Some(v.to_owned());
-        self.delegate.visit_borrowed_str(v)
-    }
-
-    fn visit_string<E: Error>(self, v: String) -> Result<Self::Value, E> {
-        *self.key = Some(v.clone());
-        self.delegate.visit_string(v)
-    }
-
-    fn visit_unit<E: Error>(self) -> Result<Self::Value, E> {
-        self.delegate.visit_unit()
-    }
-
-    fn visit_none<E: Error>(self) -> Result<Self::Value, E> {
-        self.delegate.visit_none()
-    }
-
-    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
-        self.delegate.visit_some(deserializer)
-    }
-
-    fn visit_newtype_struct<D: Deserializer<'de>>(
-        self,
-        deserializer: D,
-    ) -> Result<Self::Value, D::Error> {
-        self.delegate.visit_newtype_struct(deserializer)
-    }
-
-    fn visit_seq<V: de::SeqAccess<'de>>(self, visitor: V) -> Result<Self::Value, V::Error> {
-        self.delegate.visit_seq(visitor)
-    }
-
-    fn visit_map<V: de::MapAccess<'de>>(self, visitor: V) -> Result<Self::Value, V::Error> {
-        self.delegate.visit_map(visitor)
-    }
-
-    fn visit_enum<V: de::EnumAccess<'de>>(self, visitor: V) -> Result<Self::Value, V::Error> {
-        self.delegate.visit_enum(visitor)
-    }
-
-    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
-        self.delegate.visit_bytes(v)
-    }
-
-    fn visit_borrowed_bytes<E: Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
-        self.delegate.visit_borrowed_bytes(v)
-    }
-
-    fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
-        self.delegate.visit_byte_buf(v)
+        match self.max_bytes {
+            Some(max_bytes) if v.len() > max_bytes => Ok(None),
+            _ => Ok(Some(v.to_owned())),
+        }
     }
 }
 
@@ -796,12 +832,12 @@ where
 /// their path.
 struct TrackedSeed<X> {
     seed: X,
-    state: State,
+    context: TrackedContext,
 }
 
 impl<X> TrackedSeed<X> {
-    fn new(seed: X, state: State) -> Self {
-        TrackedSeed { seed, state }
+    fn new(seed: X, context: TrackedContext) -> Self {
+        TrackedSeed { seed, context }
     }
 }
 
@@ -812,30 +848,54 @@ where
     type Value = X::Value;
 
     fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<X::Value, D::Error> {
-        self.seed.deserialize(TrackedDeserializer {
-            de: deserializer,
-            state: self.state.clone(),
-        })
+        self.seed
+            .deserialize(TrackedDeserializer::new(deserializer, self.context))
     }
 }
 
 /// Seq visitor that tracks the index of its elements.
 struct SeqAccess<X> {
     delegate: X,
-    state: State,
+    context: TrackedContext,
     index: usize,
 }
 
 impl<X> SeqAccess<X> {
-    fn new(delegate: X, state: &State) -> Self {
+    fn new(delegate: X, context: &TrackedContext) -> Self {
         SeqAccess {
             delegate,
-            state: state.clone(),
+            context: context.clone(),
             index: 0,
         }
     }
 }
 
+impl<'de, X> SeqAccess<X>
+where
+    X: de::SeqAccess<'de>,
+{
+    /// Skips all remaining elements if the limits are exceeded.
+    ///
+    /// Returns `true` if the sequence has been cut off.
+    fn skip_exceeding(&mut self) -> Result<bool, X::Error> {
+        let limiter = match self.context.limiter {
+            Some(ref limiter) if !limiter.accepts_entry(self.index) => limiter,
+            _ => return Ok(false),
+        };
+
+        let mut length = self.index;
+        while self.delegate.next_element::<IgnoredAny>()?.is_some() {
+            length += 1;
+        }
+
+        if length > self.index {
+            limiter.record(&self.context, ErrorKind::TooManyValues, Some(length));
+        }
+
+        Ok(true)
+    }
+}
+
 /// Forwarding impl to preserve context.
 impl<'de, X> de::SeqAccess<'de> for SeqAccess<X>
 where
@@ -847,15 +907,24 @@ where
     where
         T: DeserializeSeed<'de>,
     {
-        let state = state_with_parent_path(&self.state, |parent| {
-            Rc::new(Path::Seq {
-                parent,
-                index: self.index,
-            })
-        });
+        if self.skip_exceeding()? {
+            return Ok(None);
+        }
+
+        let index = self.index;
+        let context = self
+            .context
+            .child(|parent| Rc::new(Path::Seq { parent, index }));
         self.index += 1;
-        self.delegate
-            .next_element_seed(TrackedSeed::new(seed, state))
+        let element = self
+            .delegate
+            .next_element_seed(TrackedSeed::new(seed, context))?;
+
+        if let Some(ref limiter) = self.context.limiter {
+            limiter.count_node();
+        }
+
+        Ok(element)
     }
 
     fn size_hint(&self) -> Option<usize> {
@@ -863,20 +932,23 @@ where
     }
 }
 
-/// Map visitor that captures the string value of its keys and uses that to
-/// track the path to its values.
+/// Map visitor that reads its keys as strings and uses them to track the path to its values.
+///
+/// Entries with keys exceeding the string limit are dropped, and an error is recorded on the map.
 struct MapAccess<X> {
     delegate: X,
-    state: State,
+    context: TrackedContext,
     key: Option<String>,
+    len: usize,
 }
 
 impl<X> MapAccess<X> {
-    fn new(delegate: X, state: &State) -> Self {
+    fn new(delegate: X, context: &TrackedContext) -> Self {
         MapAccess {
             delegate,
-            state: state.clone(),
+            context: context.clone(),
             key: None,
+            len: 0,
         }
     }
 
@@ -885,6 +957,36 @@ impl<X> MapAccess<X> {
     }
 }
 
+impl<'de, X> MapAccess<X>
+where
+    X: de::MapAccess<'de>,
+{
+    /// Skips all remaining entries if the limits are exceeded.
+    ///
+    /// Returns `true` if the map has been cut off.
+    fn skip_exceeding(&mut self) -> Result<bool, X::Error> {
+        let limiter = match self.context.limiter {
+            Some(ref limiter) if !limiter.accepts_entry(self.len) => limiter,
+            _ => return Ok(false),
+        };
+
+        let mut length = self.len;
+        while self
+            .delegate
+            .next_entry::<IgnoredAny, IgnoredAny>()?
+            .is_some()
+        {
+            length += 1;
+        }
+
+        if length > self.len {
+            limiter.record(&self.context, ErrorKind::TooManyValues, Some(length));
+        }
+
+        Ok(true)
+    }
+}
+
 impl<'de, X> de::MapAccess<'de> for MapAccess<X>
 where
     X: de::MapAccess<'de>,
@@ -895,18 +997,199 @@ where
         &mut self,
         seed: K,
     ) -> Result<Option<K::Value>, X::Error> {
-        self.delegate
-            .next_key_seed(CaptureKey::new(seed, &mut self.key))
+        let max_bytes = self
+            .context
+            .limiter
+            .as_ref()
+            .map(|limiter| limiter.limits.max_string_bytes);
+
+        loop {
+            if self.skip_exceeding()? {
+                return Ok(None);
+            }
+
+            let key = match self.delegate.next_key_seed(KeySeed { max_bytes })? {
+                Some(Some(key)) => key,
+                Some(None) => {
+                    self.delegate.next_value::<IgnoredAny>()?;
+                    if let Some(ref limiter) = self.context.limiter {
+                        limiter.record(&self.context, ErrorKind::InvalidAttribute, None);
+                    }
+                    continue;
+                }
+                None => return Ok(None),
+            };
+
+            let deserializer: StrDeserializer<X::Error> = key.as_str().into_deserializer();
+            let value = seed.deserialize(deserializer)?;
+            self.key = Some(key);
+            return Ok(Some(value));
+        }
     }
 
     fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, X::Error> {
         let key = self.key()?;
-        let state =
-            state_with_parent_path(&self.state, |parent| Rc::new(Path::Map { parent, key }));
-        self.delegate.next_value_seed(TrackedSeed::new(seed, state))
+        let context = self
+            .context
+            .child(|parent| Rc::new(Path::Map { parent, key }));
+        self.len += 1;
+        let value = self
+            .delegate
+            .next_value_seed(TrackedSeed::new(seed, context))?;
+
+        if let Some(ref limiter) = self.context.limiter {
+            limiter.count_node();
+        }
+
+        Ok(value)
     }
 
     fn size_hint(&self) -> Option<usize> {
         self.delegate.size_hint()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json;
+
+    fn deserialize(
+        json: &str,
+        limits: DeserializeLimits,
+    ) -> (serde_json::Value, Vec<LimitViolation>) {
+        let limiter = Rc::new(Limiter::new(limits));
+        let context = TrackedContext {
+            limiter: Some(limiter.clone()),
+            ..Default::default()
+        };
+
+        let value = serde_json::Value::deserialize(TrackedDeserializer::new(
+            &mut serde_json::Deserializer::from_str(json),
+            context,
+        )).unwrap();
+
+        (value, limiter.take_violations())
+    }
+
+    fn json(json: &str) -> serde_json::Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn violation(path: &str, kind: ErrorKind, original_length: Option<usize>) -> LimitViolation {
+        LimitViolation {
+            path: path.to_string(),
+            kind,
+            original_length,
+        }
+    }
+
+    #[test]
+    fn test_within_limits() {
+        let input = r#"{"foo": ["bar", {"baz": 42}]}"#;
+        let (value, violations) = deserialize(input, DeserializeLimits::default());
+        assert_eq_dbg!(value, json(input));
+        assert_eq_dbg!(violations, vec![]);
+    }
+
+    #[test]
+    fn test_max_string_bytes() {
+        let limits = DeserializeLimits {
+            max_string_bytes: 4,
+            ..Default::default()
+        };
+
+        let (value, violations) = deserialize(r#"{"foo": "abcdef", "bar": "aäb"}"#, limits);
+        assert_eq_dbg!(value, json(r#"{"foo": "abcd", "bar": "aäb"}"#));
+        assert_eq_dbg!(
+            violations,
+            vec![violation("foo", ErrorKind::ValueTooLong, Some(6))]
+        );
+
+        let (value, violations) = deserialize(r#""abcäd""#, limits);
+        assert_eq_dbg!(value, json(r#""abc""#));
+        assert_eq_dbg!(
+            violations,
+            vec![violation(".", ErrorKind::ValueTooLong, Some(5))]
+        );
+    }
+
+    #[test]
+    fn test_max_string_bytes_keys() {
+        let limits = DeserializeLimits {
+            max_string_bytes: 5,
+            ..Default::default()
+        };
+
+        let (value, violations) =
+            deserialize(r#"{"extra": {"aaaaaaaaaa": "bb", "ok": "b"}}"#, limits);
+        assert_eq_dbg!(value, json(r#"{"extra": {"ok": "b"}}"#));
+        assert_eq_dbg!(
+            violations,
+            vec![violation("extra", ErrorKind::InvalidAttribute, None)]
+        );
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let limits = DeserializeLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+
+        let (value, violations) = deserialize(r#"{"a": [1, 2, [3]], "b": 2, "c": 3}"#, limits);
+        assert_eq_dbg!(value, json(r#"{"a": [1, 2], "b": 2}"#));
+        assert_eq_dbg!(
+            violations,
+            vec![
+                violation("a", ErrorKind::TooManyValues, Some(3)),
+                violation(".", ErrorKind::TooManyValues, Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let limits = DeserializeLimits {
+            max_depth: 2,
+            ..Default::default()
+        };
+
+        let (value, violations) = deserialize(r#"{"a": {"b": {"c": [1]}}, "d": [2]}"#, limits);
+        assert_eq_dbg!(value, json(r#"{"a": {"b": null}, "d": [2]}"#));
+        assert_eq_dbg!(
+            violations,
+            vec![violation("a.b", ErrorKind::NestingTooDeep, None)]
+        );
+    }
+
+    #[test]
+    fn test_max_nodes() {
+        let limits = DeserializeLimits {
+            max_nodes: 3,
+            ..Default::default()
+        };
+
+        let (value, violations) = deserialize(r#"{"a": [1, 2], "b": [3], "c": 4}"#, limits);
+        assert_eq_dbg!(value, json(r#"{"a": [1, 2]}"#));
+        assert_eq_dbg!(
+            violations,
+            vec![violation(".", ErrorKind::TooManyValues, Some(3))]
+        );
+    }
+
+    #[test]
+    fn test_context_scope() {
+        // A tracked deserializer that is never used leaves its context behind.
+        let _unused = TrackedDeserializer::new((), TrackedContext::default());
+
+        {
+            let _scope = ContextScope::enter();
+            assert!(take_context().is_none());
+            let _unused = TrackedDeserializer::new((), TrackedContext::default());
+        }
+
+        assert!(take_context().is_none());
+    }
+}
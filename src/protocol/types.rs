@@ -11,6 +11,7 @@ use super::buffer::{Content, ContentDeserializer};
 use super::common::{Array, Map, Value, Values};
 use super::meta::Annotated;
 use super::serde::CustomSerialize;
+use super::tracked::{take_context, TrackedContext, TrackedDeserializer};
 use super::{serde_chrono, utils};
 
 /// An error used when parsing `Level`.
@@ -125,7 +126,16 @@ mod test_level {
 ///
 /// A log message is similar to the `message` attribute on the event itself but
 /// can additionally hold optional parameters.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct LogEntry {
     /// The log message with parameter placeholders (required).
     #[process_annotated_value(pii_kind = "freeform", cap = "message")]
@@ -136,6 +146,11 @@ pub struct LogEntry {
     #[process_annotated_value(pii_kind = "databag")]
     pub params: Annotated<Array<Value>>,
 
+    /// The log message with all parameters interpolated.
+    #[serde(default, skip_serializing_if = "utils::is_none")]
+    #[process_annotated_value(pii_kind = "freeform", cap = "message")]
+    pub formatted: Annotated<Option<String>>,
+
     /// Additional arbitrary fields for forwards compatibility.
     #[serde(flatten)]
     #[process_annotated_value(pii_kind = "databag")]
@@ -164,6 +179,7 @@ mod test_logentry {
                 Value::String("World".to_string()).into(),
                 Value::U64(1).into(),
             ].into(),
+            formatted: Default::default(),
             other: {
                 let mut map = Map::new();
                 map.insert(
@@ -184,6 +200,7 @@ mod test_logentry {
         let entry = LogEntry {
             message: "mymessage".to_string().into(),
             params: Default::default(),
+            formatted: Default::default(),
             other: Default::default(),
         };
 
@@ -193,13 +210,24 @@ mod test_logentry {
 
     #[test]
     fn test_invalid() {
-        let entry: Annotated<LogEntry> = Annotated::from_error("missing field `message`");
+        let entry: Annotated<LogEntry> = Annotated::from_error(MetaError::missing_attribute("message"))
+            .with_original_value(serde_json::from_str("{}").unwrap());
         assert_eq_dbg!(entry, serde_json::from_str("{}").unwrap());
     }
 }
 
 /// Information about the user who triggered an event.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct User {
     /// Unique identifier of the user.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -277,19 +305,96 @@ mod test_user {
 }
 
 /// Wrapper type for query-string like maps.
-#[derive(Debug, Clone, Default, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Query(pub Map<Value>);
 
 /// Wrapper type for request header maps.
-#[derive(Debug, Clone, Default, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Cookies(pub Map<String>);
 
-/// Wrapper type for request header maps.
-#[derive(Debug, Clone, Default, PartialEq, ProcessAnnotatedValue, Serialize)]
-pub struct Headers(pub Map<String>);
+/// Wrapper type for HTTP headers.
+///
+/// Headers retain their original order and may contain multiple values for the same name. They
+/// serialize to a map if every header name occurs only once, and to a list of pairs otherwise.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers(pub Vec<(String, Annotated<String>)>);
+
+impl Headers {
+    /// Returns the first value of the header with the given name, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&Annotated<String>> {
+        self.0
+            .iter()
+            .find(|&&(ref key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value)
+    }
+
+    /// Returns all values of the header with the given name in order, ignoring case.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Annotated<String>> {
+        self.0
+            .iter()
+            .filter(move |&&(ref key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value)
+    }
+
+    /// Returns whether a header with the given name exists, ignoring case.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Appends a header value, keeping existing values of the same name.
+    pub fn push(&mut self, name: String, value: Annotated<String>) {
+        self.0.push((name, value));
+    }
+
+    /// Removes all values of the header with the given name, ignoring case.
+    ///
+    /// Returns whether any values were removed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|&(ref key, _)| !key.eq_ignore_ascii_case(name));
+        self.0.len() != len
+    }
+
+    /// Returns the number of header values.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
 
 /// Http request information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Request {
     /// URL of the request.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -307,11 +412,21 @@ pub struct Request {
     // TODO: Custom logic + info
     pub data: Annotated<Option<Value>>,
 
+    /// The content type of the request body, as inferred when parsing `data`.
+    #[serde(default, skip_serializing_if = "utils::is_none")]
+    #[process_annotated_value(cap = "summary")]
+    pub inferred_content_type: Annotated<Option<String>>,
+
     /// URL encoded HTTP query string.
     #[serde(default, skip_serializing_if = "request::is_empty_query")]
     #[process_annotated_value(pii_kind = "databag")]
     pub query_string: Annotated<Query>,
 
+    /// Fragment of the request URL, without the leading `#`.
+    #[serde(default, skip_serializing_if = "utils::is_none")]
+    #[process_annotated_value(pii_kind = "freeform")]
+    pub fragment: Annotated<Option<String>>,
+
     /// URL encoded contents of the Cookie header.
     #[serde(default, skip_serializing_if = "request::is_empty_cookies")]
     #[process_annotated_value(pii_kind = "databag")]
@@ -335,9 +450,16 @@ pub struct Request {
 }
 
 mod request {
+    use std::collections::BTreeSet;
+
     use cookie::Cookie;
+    use processor::{
+        process_invalid_value, AnyAnnotated, PathItem, ProcessAnnotatedValue, Processor,
+        SelectAnnotatedValue, ValueInfo, VisitAnnotatedValue, VisitState, Visitor, VisitorMut,
+    };
     use queryst;
     use serde::de;
+    use serde::ser::SerializeMap;
     use serde_json;
 
     use super::super::utils;
@@ -352,7 +474,7 @@ mod request {
     }
 
     pub fn is_empty_headers(annotated: &Annotated<Headers>) -> bool {
-        utils::skip_if(annotated, |headers| headers.0.is_empty())
+        utils::skip_if(annotated, Headers::is_empty)
     }
 
     struct ParseQueryError(String);
@@ -380,6 +502,26 @@ mod request {
         Ok(serde_json::from_value(value)?)
     }
 
+    impl Query {
+        /// Parses a URL encoded query string with an optional leading `?`.
+        pub fn parse(qs: &str) -> Result<Self, String> {
+            let qs = if qs.starts_with('?') { &qs[1..] } else { qs };
+            parse_qs(qs).map_err(|error| error.0)
+        }
+    }
+
+    impl Cookies {
+        /// Parses the contents of a `Cookie` header.
+        pub fn parse(header: &str) -> Result<Self, String> {
+            let mut cookies = Map::new();
+            for cookie in header.split("; ") {
+                let cookie = Cookie::parse_encoded(cookie).map_err(|error| error.to_string())?;
+                cookies.insert(cookie.name().to_string(), cookie.value().to_string().into());
+            }
+            Ok(Cookies(cookies))
+        }
+    }
+
     struct QueryVisitor;
 
     impl<'de> de::Visitor<'de> for QueryVisitor {
@@ -390,8 +532,7 @@ mod request {
         }
 
         fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
-            let qs = if v.starts_with('?') { &v[1..] } else { v };
-            parse_qs(qs).map_err(E::custom)
+            Query::parse(v).map_err(E::custom)
         }
 
         fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
@@ -419,12 +560,7 @@ mod request {
         }
 
         fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
-            let mut cookies = Map::new();
-            for cookie in v.split("; ") {
-                let cookie = Cookie::parse_encoded(cookie).map_err(E::custom)?;
-                cookies.insert(cookie.name().to_string(), cookie.value().to_string().into());
-            }
-            Ok(Cookies(cookies))
+            Cookies::parse(v).map_err(E::custom)
         }
 
         fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
@@ -471,21 +607,21 @@ mod request {
         type Value = Headers;
 
         fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            write!(f, "a headers map")
+            write!(f, "a headers map or list of pairs")
         }
 
         fn visit_seq<A: de::SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
-            let mut map = Map::new();
+            let mut headers = Vec::new();
             while let Some((key, value)) = access.next_element()? {
-                map.insert(capitalize_header(key), value);
+                headers.push((capitalize_header(key), value));
             }
-            Ok(Headers(map))
+            Ok(Headers(headers))
         }
 
         fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
-            let mut headers = Map::new();
+            let mut headers = Vec::new();
             while let Some((key, value)) = map.next_entry()? {
-                headers.insert(capitalize_header(key), value);
+                headers.push((capitalize_header(key), value));
             }
             Ok(Headers(headers))
         }
@@ -496,11 +632,123 @@ mod request {
             deserializer.deserialize_any(HeadersVisitor)
         }
     }
+
+    impl Serialize for Headers {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut names = BTreeSet::new();
+            if self.0.iter().all(|&(ref key, _)| names.insert(key.as_str())) {
+                let mut map = serializer.serialize_map(Some(self.0.len()))?;
+                for &(ref key, ref value) in &self.0 {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            } else {
+                self.0.serialize(serializer)
+            }
+        }
+    }
+
+    impl ProcessAnnotatedValue for Headers {
+        fn process_annotated_value(
+            annotated: Annotated<Self>,
+            processor: &Processor,
+            info: &ValueInfo,
+        ) -> Annotated<Self> {
+            let annotated = annotated.map(|headers| {
+                Headers(
+                    headers
+                        .0
+                        .into_iter()
+                        .map(|(key, value)| {
+                            let value = ProcessAnnotatedValue::process_annotated_value(
+                                value,
+                                processor,
+                                &info.derive(),
+                            );
+                            (key, value)
+                        }).collect(),
+                )
+            });
+            process_invalid_value(annotated, processor, info)
+        }
+    }
+
+    impl VisitAnnotatedValue for Headers {
+        fn visit_children(&self, visitor: &mut Visitor, state: &VisitState) {
+            for &(ref key, ref value) in &self.0 {
+                VisitAnnotatedValue::visit_annotated_value(
+                    value.value(),
+                    value.meta(),
+                    visitor,
+                    &state.enter_key(key, state.info().derive()),
+                );
+            }
+        }
+
+        fn visit_children_mut(&mut self, visitor: &mut VisitorMut, state: &VisitState) {
+            for &mut (ref key, ref mut value) in &mut self.0 {
+                VisitAnnotatedValue::visit_annotated_value_mut(
+                    value,
+                    visitor,
+                    &state.enter_key(key, state.info().derive()),
+                );
+            }
+        }
+    }
+
+    impl SelectAnnotatedValue for Headers {
+        fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+            self.0
+                .iter()
+                .find(|&&(ref name, _)| name.eq_ignore_ascii_case(key))
+                .map(|&(ref name, ref value)| (PathItem::Key(name), value as &AnyAnnotated))
+        }
+
+        fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+            self.0
+                .iter_mut()
+                .find(|&&mut (ref name, _)| name.eq_ignore_ascii_case(key))
+                .map(|&mut (ref name, ref mut value)| {
+                    (PathItem::Key(name), value as &mut AnyAnnotated)
+                })
+        }
+
+        fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+            self.0
+                .iter()
+                .map(|&(ref name, ref value)| (PathItem::Key(name), value as &AnyAnnotated))
+                .collect()
+        }
+
+        fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+            self.0
+                .iter_mut()
+                .map(|&mut (ref name, ref mut value)| {
+                    (PathItem::Key(name), value as &mut AnyAnnotated)
+                }).collect()
+        }
+
+        fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+            if !self.contains(key) {
+                self.push(key.to_string(), Annotated::empty());
+            }
+            self.select_child_mut(key).map(|(_, value)| value)
+        }
+
+        fn remove_child(&mut self, key: &str) {
+            self.remove(key);
+        }
+
+        fn create() -> Option<Self> {
+            Some(Headers::default())
+        }
+    }
 }
 
 #[cfg(test)]
 mod test_request {
     use super::*;
+    use protocol::{Meta, MetaError, Remark, RemarkType};
     use serde_json;
 
     #[test]
@@ -511,9 +759,11 @@ mod test_request {
   "data": {
     "some": 1
   },
+  "inferred_content_type": "application/json",
   "query_string": {
     "q": "foo"
   },
+  "fragment": "results",
   "cookies": {
     "GOOGLE": "1"
   },
@@ -534,24 +784,22 @@ mod test_request {
                 map.insert("some".to_string(), Value::U64(1).into());
                 Annotated::from(Some(Value::Map(map)))
             },
+            inferred_content_type: Some("application/json".to_string()).into(),
             query_string: Query({
                 let mut map = Map::new();
                 map.insert("q".to_string(), Value::String("foo".to_string()).into());
                 map
             }).into(),
+            fragment: Some("results".to_string()).into(),
             cookies: Cookies({
                 let mut map = Map::new();
                 map.insert("GOOGLE".to_string(), "1".to_string().into());
                 map
             }).into(),
-            headers: Headers({
-                let mut map = Map::new();
-                map.insert(
-                    "Referer".to_string(),
-                    "https://google.com/".to_string().into(),
-                );
-                map
-            }).into(),
+            headers: Headers(vec![(
+                "Referer".to_string(),
+                "https://google.com/".to_string().into(),
+            )]).into(),
             env: {
                 let mut map = Map::new();
                 map.insert(
@@ -581,7 +829,9 @@ mod test_request {
             url: None.into(),
             method: None.into(),
             data: None.into(),
+            inferred_content_type: None.into(),
             query_string: Default::default(),
+            fragment: None.into(),
             cookies: Default::default(),
             headers: Default::default(),
             env: Default::default(),
@@ -618,9 +868,8 @@ mod test_request {
 
     #[test]
     fn test_query_invalid() {
-        let query = Annotated::<Query>::from_error(
-            "invalid type: integer `42`, expected a query string or map",
-        );
+        let query = Annotated::<Query>::from_error(MetaError::expected("a query string or map"))
+            .with_original_value(42.into());
         assert_eq_dbg!(query, serde_json::from_str("42").unwrap());
     }
 
@@ -642,13 +891,23 @@ mod test_request {
 
     #[test]
     fn test_cookies_object() {
-        let json = r#"{"foo":"bar", "invalid": 42}"#;
+        let json = r#"{"foo":"bar", "number": 42, "invalid": true}"#;
 
         let mut map = Map::new();
         map.insert("foo".to_string(), "bar".to_string().into());
+        map.insert(
+            "number".to_string(),
+            Annotated::new(
+                "42".to_string(),
+                Meta {
+                    remarks: vec![Remark::new(RemarkType::Annotated, "@coercion")],
+                    ..Default::default()
+                },
+            ),
+        );
         map.insert(
             "invalid".to_string(),
-            Annotated::from_error("invalid type: integer `42`, expected a string"),
+            Annotated::from_error(MetaError::expected("a string")).with_original_value(true.into()),
         );
 
         let cookies = Annotated::from(Cookies(map));
@@ -657,9 +916,9 @@ mod test_request {
 
     #[test]
     fn test_cookies_invalid() {
-        let cookies = Annotated::<Cookies>::from_error(
-            "invalid type: integer `42`, expected a cookie map or header string",
-        );
+        let cookies =
+            Annotated::<Cookies>::from_error(MetaError::expected("a cookie map or header string"))
+                .with_original_value(42.into());
         assert_eq_dbg!(cookies, serde_json::from_str("42").unwrap());
     }
 
@@ -671,13 +930,12 @@ mod test_request {
   "-other-": "header"
 }"#;
 
-        let mut map = Map::new();
-        map.insert("Accept".to_string(), "application/json".to_string().into());
-        map.insert("X-Sentry".to_string(), "version=8".to_string().into());
-        map.insert("-Other-".to_string(), "header".to_string().into());
-
-        let query = Annotated::from(Headers(map));
-        assert_eq_dbg!(query, serde_json::from_str(json).unwrap());
+        let headers = Annotated::from(Headers(vec![
+            ("Accept".to_string(), "application/json".to_string().into()),
+            ("X-Sentry".to_string(), "version=8".to_string().into()),
+            ("-Other-".to_string(), "header".to_string().into()),
+        ]));
+        assert_eq_dbg!(headers, serde_json::from_str(json).unwrap());
     }
 
     #[test]
@@ -686,16 +944,76 @@ mod test_request {
   ["accept", "application/json"]
 ]"#;
 
-        let mut map = Map::new();
-        map.insert("Accept".to_string(), "application/json".to_string().into());
+        let headers = Annotated::from(Headers(vec![(
+            "Accept".to_string(),
+            "application/json".to_string().into(),
+        )]));
+        assert_eq_dbg!(headers, serde_json::from_str(json).unwrap());
+    }
 
-        let query = Annotated::from(Headers(map));
-        assert_eq_dbg!(query, serde_json::from_str(json).unwrap());
+    #[test]
+    fn test_header_multiple_values() {
+        let json = r#"[["X-Forwarded-For","10.0.0.1"],["accept","text/html"],["x-forwarded-for","10.0.0.2"]]"#;
+
+        let headers = Headers(vec![
+            ("X-Forwarded-For".to_string(), "10.0.0.1".to_string().into()),
+            ("Accept".to_string(), "text/html".to_string().into()),
+            ("X-Forwarded-For".to_string(), "10.0.0.2".to_string().into()),
+        ]);
+
+        assert_eq_dbg!(Annotated::from(headers.clone()), serde_json::from_str(json).unwrap());
+        assert_eq_str!(
+            serde_json::to_string(&headers).unwrap(),
+            r#"[["X-Forwarded-For","10.0.0.1"],["Accept","text/html"],["X-Forwarded-For","10.0.0.2"]]"#
+        );
+
+        let values: Vec<_> = headers
+            .get_all("x-forwarded-for")
+            .filter_map(Annotated::value)
+            .collect();
+        assert_eq_dbg!(values, vec!["10.0.0.1", "10.0.0.2"]);
+    }
+
+    #[test]
+    fn test_header_compact_serialization() {
+        let headers = Headers(vec![
+            ("Referer".to_string(), "https://google.com/".to_string().into()),
+            ("Accept".to_string(), "text/html".to_string().into()),
+        ]);
+
+        assert_eq_str!(
+            serde_json::to_string(&headers).unwrap(),
+            r#"{"Referer":"https://google.com/","Accept":"text/html"}"#
+        );
+    }
+
+    #[test]
+    fn test_header_remove() {
+        let mut headers = Headers(vec![
+            ("Cookie".to_string(), "a=1".to_string().into()),
+            ("Accept".to_string(), "text/html".to_string().into()),
+            ("Cookie".to_string(), "b=2".to_string().into()),
+        ]);
+
+        assert!(headers.remove("cookie"));
+        assert!(!headers.remove("cookie"));
+        assert_eq_dbg!(headers.len(), 1);
+        assert!(headers.contains("Accept"));
     }
 }
 
 /// Device information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct DeviceContext {
     /// Name of the device.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -777,7 +1095,17 @@ pub struct DeviceContext {
 }
 
 /// Operating system information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct OsContext {
     /// Name of the operating system.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -815,7 +1143,16 @@ pub struct OsContext {
 }
 
 /// Runtime information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct RuntimeContext {
     /// Runtime name.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -839,7 +1176,16 @@ pub struct RuntimeContext {
 }
 
 /// Application information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct AppContext {
     /// Start time of the app.
     #[serde(
@@ -883,7 +1229,17 @@ pub struct AppContext {
 }
 
 /// Web browser information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct BrowserContext {
     /// Runtime name.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -919,11 +1275,12 @@ pub enum Context {
 }
 
 mod context {
-    use processor::{ProcessAnnotatedValue, Processor, ValueInfo};
-    use std::rc::Rc;
+    use processor::{
+        process_invalid_value, AnyAnnotated, PathItem, ProcessAnnotatedValue, Processor,
+        SelectAnnotatedValue, ValueInfo, VisitAnnotatedValue, VisitState, Visitor, VisitorMut,
+    };
 
     use super::super::buffer::{Content, ContentDeserializer};
-    use super::super::tracked::Path;
     use super::*;
 
     impl<'de> Deserialize<'de> for Context {
@@ -936,13 +1293,13 @@ mod context {
                 content: Content<'a>,
             };
 
-            let key = deserializer.state().get::<Rc<Path>>().map(|p| p.key());
+            let context = take_context().unwrap_or_else(TrackedContext::untracked);
+            let key = context.path.as_ref().map(|path| path.key());
             let D { t, content } = D::deserialize(deserializer)?;
-            let deserializer = ContentDeserializer::new(content);
+            let deserializer = TrackedDeserializer::new(ContentDeserializer::new(content), context);
 
-            // The context type is either declared explicitly or inferred from the map key via
-            // deserializer state. If the deserializer does not support state, we default to
-            // "unknown".
+            // The context type is either declared explicitly or inferred from the map key of the
+            // tracked path. If the deserializer is not tracked, we default to "unknown".
             let ty = t.or(key).unwrap_or_else(|| "unknown".to_string());
 
             Ok(match ty.as_ref() {
@@ -1045,7 +1402,99 @@ mod context {
                     );
                     Annotated::new(Context::Other(name, context.unwrap_or_default()), meta)
                 }
-                other @ Annotated(None, _) => other,
+                other @ Annotated(None, _) => process_invalid_value(other, processor, info),
+            }
+        }
+    }
+
+    impl VisitAnnotatedValue for Context {
+        fn visit_children(&self, visitor: &mut Visitor, state: &VisitState) {
+            match *self {
+                Context::Device(ref context) => context.visit_children(visitor, state),
+                Context::Os(ref context) => context.visit_children(visitor, state),
+                Context::Runtime(ref context) => context.visit_children(visitor, state),
+                Context::App(ref context) => context.visit_children(visitor, state),
+                Context::Browser(ref context) => context.visit_children(visitor, state),
+                Context::Other(_, ref context) => context.visit_children(visitor, state),
+            }
+        }
+
+        fn visit_children_mut(&mut self, visitor: &mut VisitorMut, state: &VisitState) {
+            match *self {
+                Context::Device(ref mut context) => context.visit_children_mut(visitor, state),
+                Context::Os(ref mut context) => context.visit_children_mut(visitor, state),
+                Context::Runtime(ref mut context) => context.visit_children_mut(visitor, state),
+                Context::App(ref mut context) => context.visit_children_mut(visitor, state),
+                Context::Browser(ref mut context) => context.visit_children_mut(visitor, state),
+                Context::Other(_, ref mut context) => context.visit_children_mut(visitor, state),
+            }
+        }
+    }
+
+    impl SelectAnnotatedValue for Context {
+        fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+            match *self {
+                Context::Device(ref context) => context.select_child(key),
+                Context::Os(ref context) => context.select_child(key),
+                Context::Runtime(ref context) => context.select_child(key),
+                Context::App(ref context) => context.select_child(key),
+                Context::Browser(ref context) => context.select_child(key),
+                Context::Other(_, ref context) => context.select_child(key),
+            }
+        }
+
+        fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+            match *self {
+                Context::Device(ref mut context) => context.select_child_mut(key),
+                Context::Os(ref mut context) => context.select_child_mut(key),
+                Context::Runtime(ref mut context) => context.select_child_mut(key),
+                Context::App(ref mut context) => context.select_child_mut(key),
+                Context::Browser(ref mut context) => context.select_child_mut(key),
+                Context::Other(_, ref mut context) => context.select_child_mut(key),
+            }
+        }
+
+        fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+            match *self {
+                Context::Device(ref context) => context.select_children(),
+                Context::Os(ref context) => context.select_children(),
+                Context::Runtime(ref context) => context.select_children(),
+                Context::App(ref context) => context.select_children(),
+                Context::Browser(ref context) => context.select_children(),
+                Context::Other(_, ref context) => context.select_children(),
+            }
+        }
+
+        fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+            match *self {
+                Context::Device(ref mut context) => context.select_children_mut(),
+                Context::Os(ref mut context) => context.select_children_mut(),
+                Context::Runtime(ref mut context) => context.select_children_mut(),
+                Context::App(ref mut context) => context.select_children_mut(),
+                Context::Browser(ref mut context) => context.select_children_mut(),
+                Context::Other(_, ref mut context) => context.select_children_mut(),
+            }
+        }
+
+        fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+            match *self {
+                Context::Device(ref mut context) => context.insert_child(key),
+                Context::Os(ref mut context) => context.insert_child(key),
+                Context::Runtime(ref mut context) => context.insert_child(key),
+                Context::App(ref mut context) => context.insert_child(key),
+                Context::Browser(ref mut context) => context.insert_child(key),
+                Context::Other(_, ref mut context) => context.insert_child(key),
+            }
+        }
+
+        fn remove_child(&mut self, key: &str) {
+            match *self {
+                Context::Device(ref mut context) => context.remove_child(key),
+                Context::Os(ref mut context) => context.remove_child(key),
+                Context::Runtime(ref mut context) => context.remove_child(key),
+                Context::App(ref mut context) => context.remove_child(key),
+                Context::Browser(ref mut context) => context.remove_child(key),
+                Context::Other(_, ref mut context) => context.remove_child(key),
             }
         }
     }
@@ -1057,6 +1506,27 @@ mod test_contexts {
     use super::*;
     use serde_json;
 
+    #[test]
+    fn test_meta_roundtrip() {
+        let json = r#"{"type":"os","name":"Linux","other":"value","_meta":{"name":{"":{"len":10}},"other":{"":{"rem":[["@test","x"]]}}}}"#;
+        let context = Annotated::<Context>::from_json(json).unwrap();
+
+        let os = match context.value() {
+            Some(Context::Os(ref os)) => os,
+            _ => panic!("expected os context"),
+        };
+        assert_eq_dbg!(os.name.meta().path(), Some("name"));
+        assert_eq_dbg!(os.name.meta().original_length(), Some(10));
+        assert_eq_dbg!(
+            os.other.value().unwrap()["other"].meta().path(),
+            Some("other")
+        );
+
+        let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&context.to_json().unwrap()).unwrap();
+        assert_eq_dbg!(actual, expected);
+    }
+
     #[test]
     fn test_device_roundtrip() {
         let json = r#"{
@@ -1385,7 +1855,16 @@ fn default_breadcrumb_type() -> Annotated<String> {
 }
 
 /// A breadcrumb.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Breadcrumb {
     /// The timestamp of the breadcrumb (required).
     #[serde(with = "serde_chrono")]
@@ -1488,7 +1967,8 @@ mod test_breadcrumb {
 
     #[test]
     fn test_invalid() {
-        let breadcrumb: Annotated<Breadcrumb> = Annotated::from_error("missing field `timestamp`");
+        let breadcrumb: Annotated<Breadcrumb> = Annotated::from_error(MetaError::missing_attribute("timestamp"))
+            .with_original_value(serde_json::from_str("{}").unwrap());
         assert_eq_dbg!(breadcrumb, serde_json::from_str("{}").unwrap());
     }
 }
@@ -1506,7 +1986,16 @@ pub struct Addr(pub u64);
 impl_hex_serde!(Addr, u64);
 
 /// Single frame in a stack trace.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Frame {
     /// Name of the frame's function. This might include the name of a class.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -1714,7 +2203,16 @@ mod test_frame {
 }
 
 /// Stack trace containing a thread's frames.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Stacktrace {
     /// List of frames in this stack trace (required).
     #[process_annotated_value]
@@ -1737,6 +2235,7 @@ pub struct Stacktrace {
 #[cfg(test)]
 mod test_stacktrace {
     use super::*;
+    use protocol::MetaError;
     use serde_json;
 
     #[test]
@@ -1796,13 +2295,16 @@ mod test_stacktrace {
 
     #[test]
     fn test_invalid() {
-        let stack: Annotated<Stacktrace> = Annotated::from_error("missing field `frames`");
+        let stack: Annotated<Stacktrace> = Annotated::from_error(MetaError::missing_attribute("frames"))
+            .with_original_value(serde_json::from_str("{}").unwrap());
         assert_eq_dbg!(stack, serde_json::from_str("{}").unwrap());
     }
 }
 
 /// POSIX signal with optional extended data.
-#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+#[derive(
+    Debug, Clone, Deserialize, PartialEq, SelectAnnotatedValue, Serialize, VisitAnnotatedValue,
+)]
 pub struct CError {
     /// The error code as specified by ISO C99, POSIX.1-2001 or POSIX.1-2008.
     pub number: Annotated<i32>,
@@ -1813,7 +2315,9 @@ pub struct CError {
 }
 
 /// Mach exception information.
-#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+#[derive(
+    Debug, Clone, Deserialize, PartialEq, SelectAnnotatedValue, Serialize, VisitAnnotatedValue,
+)]
 pub struct MachException {
     /// The mach exception type.
     #[serde(rename = "exception")]
@@ -1831,7 +2335,9 @@ pub struct MachException {
 }
 
 /// POSIX signal with optional extended data.
-#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+#[derive(
+    Debug, Clone, Deserialize, PartialEq, SelectAnnotatedValue, Serialize, VisitAnnotatedValue,
+)]
 pub struct PosixSignal {
     /// The POSIX signal number.
     pub number: Annotated<i32>,
@@ -1850,7 +2356,16 @@ pub struct PosixSignal {
 }
 
 /// Operating system or runtime meta information to an exception mechanism.
-#[derive(Debug, Clone, Default, Deserialize, PartialEq, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Deserialize,
+    PartialEq,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct MechanismMeta {
     /// Optional ISO C standard error code.
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -1883,7 +2398,15 @@ impl MechanismMeta {
 }
 
 /// The mechanism by which an exception was generated and handled.
-#[derive(Debug, Clone, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Mechanism {
     /// Mechanism type (required).
     #[serde(rename = "type")]
@@ -1922,7 +2445,10 @@ mod mechanism {
     use serde::de::Error;
     use std::collections::BTreeMap;
 
-    fn deserialize<E: Error>(map: BTreeMap<String, Content>) -> Result<Mechanism, E> {
+    fn deserialize<E: Error>(
+        map: BTreeMap<String, Content>,
+        context: &TrackedContext,
+    ) -> Result<Mechanism, E> {
         let mut ty = None;
         let mut description = None;
         let mut help_link = None;
@@ -1932,7 +2458,8 @@ mod mechanism {
         let mut other: Map<Value> = Default::default();
 
         for (key, content) in map {
-            let deserializer = ContentDeserializer::new(content);
+            let context = context.enter_key(&key);
+            let deserializer = TrackedDeserializer::new(ContentDeserializer::new(content), context);
             match key.as_str() {
                 "type" => ty = Some(Deserialize::deserialize(deserializer)?),
                 "description" => description = Some(Deserialize::deserialize(deserializer)?),
@@ -1999,12 +2526,16 @@ mod mechanism {
         }
     }
 
-    fn deserialize_legacy<E: Error>(map: BTreeMap<String, Content>) -> Result<Mechanism, E> {
+    fn deserialize_legacy<E: Error>(
+        map: BTreeMap<String, Content>,
+        context: &TrackedContext,
+    ) -> Result<Mechanism, E> {
         let mut data = Map::<Value>::new();
         let mut meta = MechanismMeta::default();
 
         for (key, content) in map {
-            let deserializer = ContentDeserializer::new(content);
+            let context = context.enter_key(&key);
+            let deserializer = TrackedDeserializer::new(ContentDeserializer::new(content), context);
             match key.as_str() {
                 "posix_signal" => {
                     let de = Annotated::<LegacyPosixSignal>::deserialize(deserializer)?;
@@ -2033,11 +2564,12 @@ mod mechanism {
 
     impl<'de> Deserialize<'de> for Mechanism {
         fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let context = take_context().unwrap_or_else(TrackedContext::untracked);
             let map = BTreeMap::deserialize(deserializer)?;
             if !map.is_empty() && !map.contains_key("type") {
-                deserialize_legacy(map)
+                deserialize_legacy(map, &context)
             } else {
-                deserialize(map)
+                deserialize(map, &context)
             }
         }
     }
@@ -2046,8 +2578,29 @@ mod mechanism {
 #[cfg(test)]
 mod test_mechanism {
     use super::*;
+    use protocol::MetaError;
     use serde_json;
 
+    #[test]
+    fn test_meta_roundtrip() {
+        let json = r#"{"type":"generic","description":"desc","meta":{"errno":{"number":2}},"_meta":{"description":{"":{"len":10}},"meta":{"errno":{"number":{"":{"rem":[["@test","x"]]}}}}}}"#;
+        let mechanism = Annotated::<Mechanism>::from_json(json).unwrap();
+
+        let mechanism = mechanism.value().unwrap();
+        assert_eq_dbg!(mechanism.description.meta().path(), Some("description"));
+        assert_eq_dbg!(mechanism.description.meta().original_length(), Some(10));
+
+        let errno = mechanism.meta.value().unwrap().errno.value().unwrap();
+        let number = &errno.as_ref().unwrap().number;
+        assert_eq_dbg!(number.meta().path(), Some("meta.errno.number"));
+        assert_eq_dbg!(number.meta().remarks().count(), 1);
+
+        let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+        let actual = Annotated::from(mechanism.clone()).to_json().unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&actual).unwrap();
+        assert_eq_dbg!(actual, expected);
+    }
+
     #[test]
     fn test_roundtrip() {
         let json = r#"{
@@ -2153,7 +2706,8 @@ mod test_mechanism {
 
     #[test]
     fn test_invalid() {
-        let mechanism: Annotated<Mechanism> = Annotated::from_error("missing field `type`");
+        let mechanism: Annotated<Mechanism> = Annotated::from_error(MetaError::missing_attribute("type"))
+            .with_original_value(serde_json::from_str("{}").unwrap());
         assert_eq_dbg!(mechanism, serde_json::from_str("{}").unwrap());
     }
 
@@ -2174,9 +2728,12 @@ mod test_mechanism {
             handled: None.into(),
             data: Map::new().into(),
             meta: MechanismMeta {
-                errno: Annotated::from_error("missing field `number`"),
-                mach_exception: Annotated::from_error("missing field `exception`"),
-                signal: Annotated::from_error("missing field `number`"),
+                errno: Annotated::from_error(MetaError::missing_attribute("number"))
+                    .with_original_value(serde_json::from_str("{}").unwrap()),
+                mach_exception: Annotated::from_error(MetaError::missing_attribute("exception"))
+                    .with_original_value(serde_json::from_str("{}").unwrap()),
+                signal: Annotated::from_error(MetaError::missing_attribute("number"))
+                    .with_original_value(serde_json::from_str("{}").unwrap()),
                 other: Default::default(),
             }.into(),
             other: Default::default(),
@@ -2271,7 +2828,16 @@ pub enum ThreadId {
 }
 
 /// An exception (error).
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Exception {
     /// Exception type (required).
     #[serde(rename = "type")]
@@ -2315,6 +2881,7 @@ pub struct Exception {
 #[cfg(test)]
 mod test_exception {
     use super::*;
+    use protocol::MetaError;
     use serde_json;
 
     #[test]
@@ -2369,13 +2936,23 @@ mod test_exception {
 
     #[test]
     fn test_invalid() {
-        let exception: Annotated<Exception> = Annotated::from_error("missing field `type`");
+        let exception: Annotated<Exception> = Annotated::from_error(MetaError::missing_attribute("type"))
+            .with_original_value(serde_json::from_str("{}").unwrap());
         assert_eq_dbg!(exception, serde_json::from_str("{}").unwrap());
     }
 }
 
 /// Template debug information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct TemplateInfo {
     /// The file name (basename only).
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -2496,7 +3073,16 @@ mod test_template_info {
 }
 
 /// A process thread of an event.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Thread {
     /// Identifier of this thread within the process (usually an integer).
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -2589,7 +3175,16 @@ mod thread {
 ///
 /// This is relevant for iOS and other platforms that have a system
 /// SDK.  Not to be confused with the client SDK.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct SystemSdkInfo {
     /// The internal name of the SDK.
     pub sdk_name: Annotated<String>,
@@ -2610,7 +3205,16 @@ pub struct SystemSdkInfo {
 }
 
 /// Apple debug image in
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct AppleDebugImage {
     /// Path and name of the debug image (required).
     pub name: Annotated<String>,
@@ -2647,7 +3251,16 @@ pub struct AppleDebugImage {
 }
 
 /// Any debug information file supported by symbolic.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct SymbolicDebugImage {
     /// Path and name of the debug image (required).
     pub name: Annotated<String>,
@@ -2676,7 +3289,16 @@ pub struct SymbolicDebugImage {
 }
 
 /// Proguard mapping file.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct ProguardDebugImage {
     /// UUID computed from the file contents.
     pub uuid: Annotated<Uuid>,
@@ -2702,7 +3324,10 @@ pub enum DebugImage {
 }
 
 mod debug_image {
-    use processor::{ProcessAnnotatedValue, Processor, ValueInfo};
+    use processor::{
+        process_invalid_value, AnyAnnotated, PathItem, ProcessAnnotatedValue, Processor,
+        SelectAnnotatedValue, ValueInfo, VisitAnnotatedValue, VisitState, Visitor, VisitorMut,
+    };
     use std::borrow::Cow;
 
     use super::super::buffer::Content;
@@ -2726,8 +3351,9 @@ mod debug_image {
                 content: Content<'a>,
             };
 
+            let context = take_context().unwrap_or_else(TrackedContext::untracked);
             let D { t, content } = D::deserialize(deserializer)?;
-            let deserializer = ContentDeserializer::new(content);
+            let deserializer = TrackedDeserializer::new(ContentDeserializer::new(content), context);
 
             Ok(match t.as_ref() {
                 "apple" => DebugImage::Apple(Deserialize::deserialize(deserializer)?),
@@ -2805,7 +3431,83 @@ mod debug_image {
                     );
                     Annotated::new(DebugImage::Other(name, image.unwrap_or_default()), meta)
                 }
-                other @ Annotated(None, _) => other,
+                other @ Annotated(None, _) => process_invalid_value(other, processor, info),
+            }
+        }
+    }
+
+    impl VisitAnnotatedValue for DebugImage {
+        fn visit_children(&self, visitor: &mut Visitor, state: &VisitState) {
+            match *self {
+                DebugImage::Apple(ref image) => image.visit_children(visitor, state),
+                DebugImage::Symbolic(ref image) => image.visit_children(visitor, state),
+                DebugImage::Proguard(ref image) => image.visit_children(visitor, state),
+                DebugImage::Other(_, ref image) => image.visit_children(visitor, state),
+            }
+        }
+
+        fn visit_children_mut(&mut self, visitor: &mut VisitorMut, state: &VisitState) {
+            match *self {
+                DebugImage::Apple(ref mut image) => image.visit_children_mut(visitor, state),
+                DebugImage::Symbolic(ref mut image) => image.visit_children_mut(visitor, state),
+                DebugImage::Proguard(ref mut image) => image.visit_children_mut(visitor, state),
+                DebugImage::Other(_, ref mut image) => image.visit_children_mut(visitor, state),
+            }
+        }
+    }
+
+    impl SelectAnnotatedValue for DebugImage {
+        fn select_child(&self, key: &str) -> Option<(PathItem, &AnyAnnotated)> {
+            match *self {
+                DebugImage::Apple(ref image) => image.select_child(key),
+                DebugImage::Symbolic(ref image) => image.select_child(key),
+                DebugImage::Proguard(ref image) => image.select_child(key),
+                DebugImage::Other(_, ref image) => image.select_child(key),
+            }
+        }
+
+        fn select_child_mut(&mut self, key: &str) -> Option<(PathItem, &mut AnyAnnotated)> {
+            match *self {
+                DebugImage::Apple(ref mut image) => image.select_child_mut(key),
+                DebugImage::Symbolic(ref mut image) => image.select_child_mut(key),
+                DebugImage::Proguard(ref mut image) => image.select_child_mut(key),
+                DebugImage::Other(_, ref mut image) => image.select_child_mut(key),
+            }
+        }
+
+        fn select_children(&self) -> Vec<(PathItem, &AnyAnnotated)> {
+            match *self {
+                DebugImage::Apple(ref image) => image.select_children(),
+                DebugImage::Symbolic(ref image) => image.select_children(),
+                DebugImage::Proguard(ref image) => image.select_children(),
+                DebugImage::Other(_, ref image) => image.select_children(),
+            }
+        }
+
+        fn select_children_mut(&mut self) -> Vec<(PathItem, &mut AnyAnnotated)> {
+            match *self {
+                DebugImage::Apple(ref mut image) => image.select_children_mut(),
+                DebugImage::Symbolic(ref mut image) => image.select_children_mut(),
+                DebugImage::Proguard(ref mut image) => image.select_children_mut(),
+                DebugImage::Other(_, ref mut image) => image.select_children_mut(),
+            }
+        }
+
+        fn insert_child(&mut self, key: &str) -> Option<&mut AnyAnnotated> {
+            match *self {
+                DebugImage::Apple(ref mut image) => image.insert_child(key),
+                DebugImage::Symbolic(ref mut image) => image.insert_child(key),
+                DebugImage::Proguard(ref mut image) => image.insert_child(key),
+                DebugImage::Other(_, ref mut image) => image.insert_child(key),
+            }
+        }
+
+        fn remove_child(&mut self, key: &str) {
+            match *self {
+                DebugImage::Apple(ref mut image) => image.remove_child(key),
+                DebugImage::Symbolic(ref mut image) => image.remove_child(key),
+                DebugImage::Proguard(ref mut image) => image.remove_child(key),
+                DebugImage::Other(_, ref mut image) => image.remove_child(key),
             }
         }
     }
@@ -2816,6 +3518,26 @@ mod test_debug_image {
     use super::*;
     use serde_json;
 
+    #[test]
+    fn test_meta_roundtrip() {
+        let json = r#"{"type":"proguard","uuid":"395835f4-03e0-4436-80d3-136f0749a893","other":"value","_meta":{"uuid":{"":{"rem":[["@test","x"]]}},"other":{"":{"len":10}}}}"#;
+        let image = Annotated::<DebugImage>::from_json(json).unwrap();
+
+        let proguard = match image.value() {
+            Some(DebugImage::Proguard(ref proguard)) => proguard,
+            _ => panic!("expected proguard image"),
+        };
+        assert_eq_dbg!(proguard.uuid.meta().path(), Some("uuid"));
+        assert_eq_dbg!(proguard.uuid.meta().remarks().count(), 1);
+        let other = &proguard.other.value().unwrap()["other"];
+        assert_eq_dbg!(other.meta().path(), Some("other"));
+        assert_eq_dbg!(other.meta().original_length(), Some(10));
+
+        let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&image.to_json().unwrap()).unwrap();
+        assert_eq_dbg!(actual, expected);
+    }
+
     #[test]
     fn test_proguard_roundtrip() {
         let json = r#"{
@@ -3013,7 +3735,16 @@ mod test_debug_image {
 }
 
 /// Geographical location of the end user or device.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Geo {
     /// Two-letter country code (ISO 3166-1 alpha-2).
     #[serde(default, skip_serializing_if = "utils::is_none")]
@@ -3083,7 +3814,16 @@ mod test_geo {
 }
 
 /// Debugging and processing meta information.
-#[derive(Debug, Clone, Deserialize, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct DebugMeta {
     /// Information about the system SDK (e.g. iOS SDK).
     #[serde(
@@ -3168,7 +3908,9 @@ mod test_debug_meta {
 }
 
 /// An installed and loaded package as part of the Sentry SDK.
-#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+#[derive(
+    Debug, Clone, Deserialize, PartialEq, SelectAnnotatedValue, Serialize, VisitAnnotatedValue,
+)]
 pub struct ClientSdkPackage {
     /// Name of the package.
     pub name: Annotated<String>,
@@ -3177,7 +3919,9 @@ pub struct ClientSdkPackage {
 }
 
 /// Information about the Sentry SDK.
-#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+#[derive(
+    Debug, Clone, Deserialize, PartialEq, SelectAnnotatedValue, Serialize, VisitAnnotatedValue,
+)]
 pub struct ClientSdkInfo {
     /// Unique SDK name.
     pub name: Annotated<String>,
@@ -3201,6 +3945,7 @@ pub struct ClientSdkInfo {
 #[cfg(test)]
 mod test_client_sdk {
     use super::*;
+    use protocol::MetaError;
     use serde_json;
 
     #[test]
@@ -3272,7 +4017,9 @@ mod test_client_sdk {
     #[test]
     fn test_invalid() {
         let json = r#"{"name":"sentry.rust"}"#;
-        let entry: Annotated<ClientSdkInfo> = Annotated::from_error("missing field `version`");
+        let entry: Annotated<ClientSdkInfo> =
+            Annotated::from_error(MetaError::missing_attribute("version"))
+                .with_original_value(serde_json::from_str(json).unwrap());
         assert_eq_dbg!(entry, serde_json::from_str(json).unwrap());
     }
 }
@@ -3432,8 +4179,8 @@ mod test_fingerprint {
         assert_eq_dbg!(
             Annotated::new(
                 vec!["{{ default }}".to_string()],
-                Meta::from_error("invalid type: null, expected a fingerprint value")
-            ),
+                Meta::from_error(MetaError::expected("a fingerprint value"))
+            ).with_original_value(serde_json::from_str(r#"["a", null, "d"]"#).unwrap()),
             deserialize("[\"a\", null, \"d\"]").unwrap()
         );
     }
@@ -3446,10 +4193,32 @@ mod test_fingerprint {
 
 mod tags {
     use serde::de;
+    use serde_json;
 
+    use super::super::meta::{Remark, RemarkType};
     use super::super::serde::CustomDeserialize;
     use super::*;
 
+    /// The rule id used for tag values converted to strings.
+    const STRINGIFY_RULE_ID: &str = "@stringify";
+
+    /// Converts a tag value into a string, annotating values of other types.
+    fn stringify(value: Annotated<Value>) -> Annotated<String> {
+        let Annotated(value, mut meta) = value;
+        let string = match value {
+            Some(Value::String(string)) => return Annotated(Some(string), meta),
+            Some(Value::Null) | None => return Annotated(None, meta),
+            Some(value @ Value::Array(_)) | Some(value @ Value::Map(_)) => {
+                serde_json::to_string(&value).unwrap_or_default()
+            }
+            Some(value) => value.to_string(),
+        };
+
+        meta.remarks_mut()
+            .push(Remark::new(RemarkType::Annotated, STRINGIFY_RULE_ID));
+        Annotated(Some(string), meta)
+    }
+
     struct TagsVisitor;
 
     impl<'de> de::Visitor<'de> for TagsVisitor {
@@ -3462,7 +4231,7 @@ mod tags {
         fn visit_seq<A: de::SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
             let mut map = Map::new();
             while let Some((key, value)) = access.next_element()? {
-                map.insert(key, value);
+                map.insert(key, stringify(value));
             }
             Ok(map)
         }
@@ -3470,7 +4239,7 @@ mod tags {
         fn visit_map<A: de::MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
             let mut map = Map::new();
             while let Some((key, value)) = access.next_entry()? {
-                map.insert(key, value);
+                map.insert(key, stringify(value));
             }
             Ok(map)
         }
@@ -3528,6 +4297,30 @@ mod test_tags {
             deserialize(r#"[["context","production"],["ios_version","4.0"]]"#).unwrap()
         );
     }
+
+    #[test]
+    fn test_tags_stringify() {
+        let stringified = |value: &str| {
+            let mut meta = Meta::default();
+            meta.remarks_mut()
+                .push(Remark::new(RemarkType::Annotated, "@stringify"));
+            Annotated::new(value.to_string(), meta)
+        };
+
+        let mut map = Map::new();
+        map.insert("answer".to_string(), stringified("42"));
+        map.insert("flag".to_string(), stringified("true"));
+        map.insert("list".to_string(), stringified("[1,\"a\"]"));
+        map.insert("missing".to_string(), Annotated::empty());
+        map.insert("name".to_string(), "value".to_string().into());
+
+        assert_eq_dbg!(
+            Annotated::from(map),
+            deserialize(
+                r#"{"answer":42,"flag":true,"list":[1,"a"],"missing":null,"name":"value"}"#
+            ).unwrap()
+        );
+    }
 }
 
 mod event {
@@ -3584,6 +4377,7 @@ mod event {
             let mut modules = None;
             let mut platform = None;
             let mut timestamp = None;
+            let mut received = None;
             let mut server_name = None;
             let mut release = None;
             let mut dist = None;
@@ -3603,12 +4397,14 @@ mod event {
             let mut client_sdk = None;
             let mut other: Map<Value> = Default::default();
 
+            let context = take_context().unwrap_or_else(TrackedContext::untracked);
             for (key, content) in BTreeMap::<String, Content>::deserialize(deserializer)? {
                 if key.starts_with('_') {
                     continue;
                 }
 
-                let deserializer = ContentDeserializer::new(content);
+                let context = context.enter_key(&key);
+                let deserializer = TrackedDeserializer::new(ContentDeserializer::new(content), context);
                 match key.as_str() {
                     "event_id" => id = Some(Deserialize::deserialize(deserializer)?),
                     "level" => level = Some(Deserialize::deserialize(deserializer)?),
@@ -3624,6 +4420,7 @@ mod event {
                     "modules" => modules = Some(Deserialize::deserialize(deserializer)?),
                     "platform" => platform = Some(Deserialize::deserialize(deserializer)?),
                     "timestamp" => timestamp = Some(serde_chrono::deserialize(deserializer)?),
+                    "received" => received = Some(serde_chrono::deserialize(deserializer)?),
                     "server_name" => server_name = Some(Deserialize::deserialize(deserializer)?),
                     "release" => release = Some(Deserialize::deserialize(deserializer)?),
                     "dist" => dist = Some(Deserialize::deserialize(deserializer)?),
@@ -3686,6 +4483,7 @@ mod event {
                 modules: modules.unwrap_or_default(),
                 platform: platform.unwrap_or_else(default_platform),
                 timestamp: timestamp.unwrap_or_default(),
+                received: received.unwrap_or_default(),
                 server_name: server_name.unwrap_or_default(),
                 release: release.unwrap_or_default(),
                 dist: dist.unwrap_or_default(),
@@ -3710,7 +4508,16 @@ mod event {
 }
 
 /// Represents a full event for Sentry.
-#[derive(Debug, Clone, Default, PartialEq, ProcessAnnotatedValue, Serialize)]
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    ProcessAnnotatedValue,
+    SelectAnnotatedValue,
+    Serialize,
+    VisitAnnotatedValue,
+)]
 pub struct Event {
     /// Unique identifier of this event.
     #[serde(
@@ -3767,6 +4574,13 @@ pub struct Event {
     )]
     pub timestamp: Annotated<Option<DateTime<Utc>>>,
 
+    /// Timestamp when the event has been received by Sentry.
+    #[serde(
+        with = "serde_chrono",
+        skip_serializing_if = "utils::is_none"
+    )]
+    pub received: Annotated<Option<DateTime<Utc>>>,
+
     /// Server or device name the event was generated on.
     #[serde(skip_serializing_if = "utils::is_none")]
     #[process_annotated_value(pii_kind = "hostname")]
@@ -3873,6 +4687,68 @@ mod test_event {
         Annotated::<Event>::from_json(string)
     }
 
+    #[test]
+    fn test_meta_roundtrip() {
+        let json = r#"{
+  "logger": "foo",
+  "contexts": {"os": {"type": "os", "name": "Linux"}},
+  "exception": {"values": [{"type": "Error", "mechanism": {"type": "generic"}}]},
+  "debug_meta": {"images": [{"type": "proguard", "uuid": "395835f4-03e0-4436-80d3-136f0749a893"}]},
+  "_meta": {
+    "logger": {"": {"len": 10}},
+    "contexts": {"os": {"name": {"": {"len": 11}}}},
+    "exception": {"values": {"0": {"mechanism": {"type": {"": {"len": 12}}}}}},
+    "debug_meta": {"images": {"0": {"uuid": {"": {"rem": [["@test", "x"]]}}}}}
+  }
+}"#;
+        let event = deserialize(json).unwrap();
+        let value = event.value().unwrap();
+
+        assert_eq_dbg!(value.logger.meta().path(), Some("logger"));
+        assert_eq_dbg!(value.logger.meta().original_length(), Some(10));
+
+        let context = &value.contexts.value().unwrap()["os"];
+        assert_eq_dbg!(context.meta().path(), Some("contexts.os"));
+        match context.value() {
+            Some(Context::Os(ref os)) => {
+                assert_eq_dbg!(os.name.meta().path(), Some("contexts.os.name"));
+                assert_eq_dbg!(os.name.meta().original_length(), Some(11));
+            }
+            _ => panic!("expected os context"),
+        }
+
+        let exceptions = value.exceptions.value().unwrap().values.value().unwrap();
+        let mechanism = &exceptions[0].value().unwrap().mechanism;
+        assert_eq_dbg!(
+            mechanism.meta().path(),
+            Some("exception.values.0.mechanism")
+        );
+        let mechanism = mechanism.value().unwrap().as_ref().unwrap();
+        assert_eq_dbg!(
+            mechanism.ty.meta().path(),
+            Some("exception.values.0.mechanism.type")
+        );
+        assert_eq_dbg!(mechanism.ty.meta().original_length(), Some(12));
+
+        let debug_meta = value.debug_meta.value().unwrap().as_ref().unwrap();
+        let image = &debug_meta.images.value().unwrap()[0];
+        assert_eq_dbg!(image.meta().path(), Some("debug_meta.images.0"));
+        match image.value() {
+            Some(DebugImage::Proguard(ref proguard)) => {
+                assert_eq_dbg!(
+                    proguard.uuid.meta().path(),
+                    Some("debug_meta.images.0.uuid")
+                );
+                assert_eq_dbg!(proguard.uuid.meta().remarks().count(), 1);
+            }
+            _ => panic!("expected proguard image"),
+        }
+
+        let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&serialize(&event).unwrap()).unwrap();
+        assert_eq_dbg!(actual, expected);
+    }
+
     #[test]
     fn test_roundtrip() {
         // NOTE: Interfaces will be tested separately.
@@ -3891,6 +4767,7 @@ mod test_event {
   },
   "platform": "myplatform",
   "timestamp": 946684800,
+  "received": 946684801,
   "server_name": "myhost",
   "release": "myrelease",
   "dist": "mydist",
@@ -3932,6 +4809,7 @@ mod test_event {
             },
             platform: "myplatform".to_string().into(),
             timestamp: Some(Utc.ymd(2000, 1, 1).and_hms(0, 0, 0)).into(),
+            received: Some(Utc.ymd(2000, 1, 1).and_hms(0, 0, 1)).into(),
             server_name: Some("myhost".to_string()).into(),
             release: Some("myrelease".to_string()).into(),
             dist: Some("mydist".to_string()).into(),
@@ -3989,6 +4867,7 @@ mod test_event {
             modules: Default::default(),
             platform: "other".to_string().into(),
             timestamp: None.into(),
+            received: None.into(),
             server_name: None.into(),
             release: None.into(),
             dist: None.into(),
@@ -4064,6 +4943,7 @@ mod test_event {
             modules: Default::default(),
             platform: Annotated::new("other".to_string(), Meta::from_error("some error")),
             timestamp: None.into(),
+            received: None.into(),
             server_name: None.into(),
             release: None.into(),
             dist: None.into(),
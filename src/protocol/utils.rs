@@ -38,3 +38,11 @@ pub fn is_empty_array<V>(annotated: &Annotated<Array<V>>) -> bool {
 pub fn is_empty_map<V>(annotated: &Annotated<Map<V>>) -> bool {
     skip_if(annotated, Map::is_empty)
 }
+
+/// Returns the string value of an optional field, if set.
+pub(crate) fn get_str(annotated: &Annotated<Option<String>>) -> Option<&str> {
+    annotated
+        .value()
+        .and_then(Option::as_ref)
+        .map(String::as_str)
+}
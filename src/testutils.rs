@@ -1,3 +1,5 @@
+use protocol::{Annotated, Event};
+
 macro_rules! assert_eq_str {
     ($left:expr, $right:expr) => {{
         let left = &($left);
@@ -27,3 +29,8 @@ macro_rules! assert_eq_dbg {
         )
     }};
 }
+
+/// Parses an event from JSON, panicking if it is invalid or empty.
+pub fn event(json: &str) -> Event {
+    Annotated::<Event>::from_json(json).unwrap().0.unwrap()
+}
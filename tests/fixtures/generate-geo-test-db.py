@@ -0,0 +1,156 @@
+#!/usr/bin/env python3
+"""Generates marshal-geo-test.mmdb, a minimal GeoIP2 City database for tests.
+
+The database is written from scratch following the MaxMind DB format specification
+(https://maxmind.github.io/MaxMind-DB/) and contains only the records below. It does not include
+any data from MaxMind's databases.
+
+Usage: python3 tests/fixtures/generate-geo-test-db.py tests/fixtures/marshal-geo-test.mmdb
+"""
+
+import ipaddress
+import struct
+import sys
+
+TYPE_STRING = 2
+TYPE_MAP = 7
+TYPE_UINT16 = 5
+TYPE_UINT32 = 6
+TYPE_UINT64 = 9
+TYPE_ARRAY = 11
+
+RECORDS = [
+    ('81.2.69.0/24', {
+        'city': {'geoname_id': ('u32', 2643743), 'names': {'en': 'London'}},
+        'country': {
+            'geoname_id': ('u32', 2635167),
+            'iso_code': 'GB',
+            'names': {'en': 'United Kingdom'},
+        },
+        'subdivisions': [{
+            'geoname_id': ('u32', 6269131),
+            'iso_code': 'ENG',
+            'names': {'en': 'England'},
+        }],
+    }),
+    ('89.160.20.0/24', {
+        'city': {'geoname_id': ('u32', 2694762), 'names': {'en': 'Linköping'}},
+        'country': {
+            'geoname_id': ('u32', 2661886),
+            'iso_code': 'SE',
+            'names': {'en': 'Sweden'},
+        },
+        'subdivisions': [{
+            'geoname_id': ('u32', 2685867),
+            'iso_code': 'E',
+            'names': {'en': 'Östergötland County'},
+        }],
+    }),
+    ('2.125.160.0/24', {
+        'country': {
+            'geoname_id': ('u32', 2635167),
+            'iso_code': 'GB',
+            'names': {'en': 'United Kingdom'},
+        },
+    }),
+]
+
+METADATA = {
+    'binary_format_major_version': ('u16', 2),
+    'binary_format_minor_version': ('u16', 0),
+    'build_epoch': ('u64', 1546300800),
+    'database_type': 'GeoIP2-City',
+    'description': {'en': 'Test database for marshal'},
+    'ip_version': ('u16', 4),
+    'languages': ['en'],
+    'record_size': ('u16', 24),
+}
+
+
+def encode_control(ty, size):
+    if size < 29:
+        control, extra = size, b''
+    elif size < 285:
+        control, extra = 29, bytes([size - 29])
+    elif size < 65821:
+        control, extra = 30, struct.pack('>H', size - 285)
+    else:
+        control, extra = 31, struct.pack('>I', size - 65821)[1:]
+
+    if ty > 7:
+        return bytes([control, ty - 7]) + extra
+    return bytes([(ty << 5) | control]) + extra
+
+
+def encode_uint(ty, value):
+    data = value.to_bytes((value.bit_length() + 7) // 8, 'big')
+    return encode_control(ty, len(data)) + data
+
+
+def encode(value):
+    if isinstance(value, str):
+        data = value.encode('utf-8')
+        return encode_control(TYPE_STRING, len(data)) + data
+    if isinstance(value, tuple):
+        kind, number = value
+        ty = {'u16': TYPE_UINT16, 'u32': TYPE_UINT32, 'u64': TYPE_UINT64}[kind]
+        return encode_uint(ty, number)
+    if isinstance(value, dict):
+        out = encode_control(TYPE_MAP, len(value))
+        for key, item in value.items():
+            out += encode(key) + encode(item)
+        return out
+    if isinstance(value, list):
+        out = encode_control(TYPE_ARRAY, len(value))
+        for item in value:
+            out += encode(item)
+        return out
+    raise TypeError(value)
+
+
+def build():
+    data = bytearray()
+    offsets = []
+    for _, record in RECORDS:
+        offsets.append(len(data))
+        data += encode(record)
+
+    # Binary search tree over the address bits. Pointers are ('node', index) or ('data', index),
+    # empty pointers mark addresses without data.
+    nodes = [[None, None]]
+    for index, (network, _) in enumerate(RECORDS):
+        network = ipaddress.ip_network(network)
+        bits = int(network.network_address)
+        node = 0
+        for depth in range(network.prefixlen):
+            bit = (bits >> (31 - depth)) & 1
+            if depth == network.prefixlen - 1:
+                nodes[node][bit] = ('data', index)
+                continue
+
+            if nodes[node][bit] is None:
+                nodes.append([None, None])
+                nodes[node][bit] = ('node', len(nodes) - 1)
+            node = nodes[node][bit][1]
+
+    node_count = len(nodes)
+
+    def pointer(target):
+        if target is None:
+            return node_count
+        if target[0] == 'node':
+            return target[1]
+        return node_count + 16 + offsets[target[1]]
+
+    tree = bytearray()
+    for left, right in nodes:
+        tree += pointer(left).to_bytes(3, 'big') + pointer(right).to_bytes(3, 'big')
+
+    metadata = dict(METADATA, node_count=('u32', node_count))
+    metadata = dict(sorted(metadata.items()))
+    return tree + b'\x00' * 16 + data + b'\xab\xcd\xefMaxMind.com' + encode(metadata)
+
+
+if __name__ == '__main__':
+    with open(sys.argv[1], 'wb') as f:
+        f.write(build())
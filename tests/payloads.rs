@@ -14,13 +14,13 @@ static PII_CONFIG: &str = r#"{
 }"#;
 
 macro_rules! run {
-    ($mode:ident, $sdk:ident) => {
+    ($mode:ident, $expected:ident, $sdk:ident) => {
         #[test]
         fn $sdk() {
             let input = read_fixture(concat!("payloads/payloads/", stringify!($sdk), ".json"));
             let expected = read_fixture(concat!(
                 "payloads/",
-                stringify!($mode),
+                stringify!($expected),
                 "/",
                 stringify!($sdk),
                 ".json"
@@ -37,37 +37,59 @@ fn normalize(input: &str) -> String {
     event.to_json_pretty().expect("could not stringify event")
 }
 
-fn strip(input: &str) -> String {
+fn strip_event(input: &str) -> Annotated<Event> {
     let event = Annotated::<Event>::from_json(input).expect("could not parse event");
-    let result = PiiConfig::from_json(PII_CONFIG)
+    PiiConfig::from_json(PII_CONFIG)
         .expect("could not parse PII config")
         .processor()
-        .process_root_value(event);
+        .process_root_value(event)
+}
+
+fn strip(input: &str) -> String {
+    let result = strip_event(input);
     result.to_json_pretty().expect("could not stringify event")
 }
 
+fn roundtrip_cbor(event: &Annotated<Event>) -> String {
+    let cbor = event.to_cbor().expect("could not serialize event to CBOR");
+    let result = Annotated::<Event>::from_cbor(&cbor).expect("could not parse CBOR event");
+    result.to_json_pretty().expect("could not stringify event")
+}
+
+fn normalize_cbor(input: &str) -> String {
+    let event = Annotated::<Event>::from_json(input).expect("could not parse event");
+    roundtrip_cbor(&event)
+}
+
+fn strip_cbor(input: &str) -> String {
+    roundtrip_cbor(&strip_event(input))
+}
+
 macro_rules! test_all {
     ($mode:ident) => {
+        test_all!($mode, $mode);
+    };
+    ($mode:ident, $expected:ident) => {
         mod $mode {
             use super::common::read_fixture;
 
-            run!($mode, legacy_js_exception);
-            run!($mode, legacy_js_message);
-            run!($mode, legacy_js_onerror);
-            run!($mode, legacy_js_promise);
-            run!($mode, legacy_node_exception);
-            run!($mode, legacy_node_express);
-            run!($mode, legacy_node_message);
-            run!($mode, legacy_node_onerror);
-            run!($mode, legacy_node_promise);
-            run!($mode, legacy_python);
-            run!($mode, legacy_swift);
-
-            run!($mode, cocoa);
-            run!($mode, cordova);
-            run!($mode, dotnet);
-            run!($mode, electron_main);
-            run!($mode, electron_renderer);
+            run!($mode, $expected, legacy_js_exception);
+            run!($mode, $expected, legacy_js_message);
+            run!($mode, $expected, legacy_js_onerror);
+            run!($mode, $expected, legacy_js_promise);
+            run!($mode, $expected, legacy_node_exception);
+            run!($mode, $expected, legacy_node_express);
+            run!($mode, $expected, legacy_node_message);
+            run!($mode, $expected, legacy_node_onerror);
+            run!($mode, $expected, legacy_node_promise);
+            run!($mode, $expected, legacy_python);
+            run!($mode, $expected, legacy_swift);
+
+            run!($mode, $expected, cocoa);
+            run!($mode, $expected, cordova);
+            run!($mode, $expected, dotnet);
+            run!($mode, $expected, electron_main);
+            run!($mode, $expected, electron_renderer);
 
             // To add new tests, run:
             // cargo run --example add-test
@@ -78,6 +100,10 @@ macro_rules! test_all {
 test_all!(normalize);
 test_all!(strip);
 
+// Events must survive a CBOR roundtrip with all values and meta data.
+test_all!(normalize_cbor, normalize);
+test_all!(strip_cbor, strip);
+
 // NOTE: @ip has false-positives in dotnet
 //       "System.Private.CoreLib, Version=4.0.0.0, Culture=neutral, PublicKeyToken=7cec85d7bea7798e"
 //       "System.Private.CoreLib, Version=[ip], Culture=neutral, PublicKeyToken=7cec85d7bea7798e"